@@ -0,0 +1,60 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Measures how fast the XML fsimage is written with different numbers of
+// worker threads.  Run it with cargo bench.  FSGEN_BENCH_INODES sets the
+// number of inodes to generate.
+
+extern crate fsgen;
+
+use fsgen::ClusterSpec;
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+const DEFAULT_NUM_INODES : u32 = 1000000;
+
+fn main() {
+    let num_inodes = match env::var("FSGEN_BENCH_INODES") {
+        Ok(val) => val.parse::<u32>().expect("FSGEN_BENCH_INODES must be a number"),
+        Err(_) => DEFAULT_NUM_INODES,
+    };
+    let path = env::temp_dir().join(format!("fsgen_bench_{}.xml", std::process::id()));
+    let path = path.to_str().unwrap();
+    for &num_threads in [ 1, 2, 4, 8, 16 ].iter() {
+        let spec = ClusterSpec::new()
+            .num_datanodes(3)
+            .num_inodes(num_inodes)
+            .seed(1)
+            .num_threads(num_threads);
+        let fsimage = match spec.generate() {
+            Ok(fsimage) => fsimage,
+            Err(err) => panic!("{}", err),
+        };
+        let _ = fs::remove_file(path);
+        let start = Instant::now();
+        fsimage.write_xml(path).unwrap();
+        let elapsed = start.elapsed();
+        let secs = elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64) / 1e9;
+        let bytes = fs::metadata(path).unwrap().len();
+        println!("write_xml, {:>2} threads: {:>8.3} s, {:>8.1} MB/s, {:>10.0} inodes/s",
+                 num_threads, secs, (bytes as f64) / secs / 1e6,
+                 (fsimage.num_inodes() as f64) / secs);
+    }
+    let _ = fs::remove_file(path);
+}
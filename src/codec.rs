@@ -0,0 +1,203 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The compression codecs a binary fsimage can use, with the same stream
+// formats as the Hadoop codecs.  See FSImageFormatProtobuf#Saver and
+// CompressionCodec
+
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::read::ZlibDecoder;
+use flate2::write::GzEncoder;
+use flate2::write::ZlibEncoder;
+use lz4_flex;
+use snap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// The buffer size of the Snappy and LZ4 codecs.  See
+// io.compression.codec.snappy.buffersize and io.compression.codec.lz4.buffersize
+const BLOCK_CODEC_BUFFER_SIZE : usize = 256 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageCodec {
+    None,
+
+    // zlib, as written by DefaultCodec
+    Default,
+
+    Gzip,
+
+    Snappy,
+
+    Lz4,
+}
+
+impl ImageCodec {
+    pub fn parse(name: &str) -> Option<ImageCodec> {
+        match name {
+            "none" => return Some(ImageCodec::None),
+            "default" => return Some(ImageCodec::Default),
+            "gzip" => return Some(ImageCodec::Gzip),
+            "snappy" => return Some(ImageCodec::Snappy),
+            "lz4" => return Some(ImageCodec::Lz4),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ImageCodec::None => return "none",
+            ImageCodec::Default => return "default",
+            ImageCodec::Gzip => return "gzip",
+            ImageCodec::Snappy => return "snappy",
+            ImageCodec::Lz4 => return "lz4",
+        }
+    }
+
+    // Get the Hadoop class which implements the codec.  This is what the
+    // FileSummary of a compressed fsimage, and dfs.image.compression.codec,
+    // hold.
+    pub fn class_name(&self) -> &'static str {
+        match *self {
+            ImageCodec::None => return "",
+            ImageCodec::Default => return "org.apache.hadoop.io.compress.DefaultCodec",
+            ImageCodec::Gzip => return "org.apache.hadoop.io.compress.GzipCodec",
+            ImageCodec::Snappy => return "org.apache.hadoop.io.compress.SnappyCodec",
+            ImageCodec::Lz4 => return "org.apache.hadoop.io.compress.Lz4Codec",
+        }
+    }
+
+    pub fn from_class_name(class_name: &str) -> Option<ImageCodec> {
+        for codec in [ ImageCodec::None, ImageCodec::Default, ImageCodec::Gzip,
+                ImageCodec::Snappy, ImageCodec::Lz4 ].iter() {
+            if codec.class_name() == class_name {
+                return Some(*codec);
+            }
+        }
+        return None;
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match *self {
+            ImageCodec::None => return Result::Ok(data.to_vec()),
+            ImageCodec::Default => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                try!(encoder.write_all(data));
+                return encoder.finish();
+            },
+            ImageCodec::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                try!(encoder.write_all(data));
+                return encoder.finish();
+            },
+            ImageCodec::Snappy | ImageCodec::Lz4 => return self.compress_blocks(data),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = vec![];
+        match *self {
+            ImageCodec::None => out.extend_from_slice(data),
+            ImageCodec::Default => { try!(ZlibDecoder::new(data).read_to_end(&mut out)); },
+            ImageCodec::Gzip => { try!(MultiGzDecoder::new(data).read_to_end(&mut out)); },
+            ImageCodec::Snappy | ImageCodec::Lz4 => return self.decompress_blocks(data),
+        }
+        return Result::Ok(out);
+    }
+
+    // The most uncompressed bytes which go in one block of a Snappy or LZ4
+    // stream, leaving room in the codec's buffer for the data to grow when
+    // it can't be compressed.  See BlockCompressorStream
+    fn max_block_len(&self) -> usize {
+        let overhead = match *self {
+            ImageCodec::Snappy => BLOCK_CODEC_BUFFER_SIZE / 6 + 32,
+            _ => BLOCK_CODEC_BUFFER_SIZE / 255 + 16,
+        };
+        return BLOCK_CODEC_BUFFER_SIZE - overhead;
+    }
+
+    // Compress data in the format of BlockCompressorStream: a series of
+    // blocks, each of which is the uncompressed length, followed by the
+    // compressed length and the compressed data.  Both lengths are 4-byte
+    // big-endian integers.
+    fn compress_blocks(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = vec![];
+        for block in data.chunks(self.max_block_len()) {
+            let compressed = if *self == ImageCodec::Snappy {
+                try!(snap::raw::Encoder::new().compress_vec(block)
+                     .map_err(|e| Error::new(ErrorKind::InvalidInput, e)))
+            } else {
+                lz4_flex::block::compress(block)
+            };
+            out.extend_from_slice(&be_u32(block.len()));
+            out.extend_from_slice(&be_u32(compressed.len()));
+            out.extend_from_slice(&compressed);
+        }
+        return Result::Ok(out);
+    }
+
+    fn decompress_blocks(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = vec![];
+        let mut pos = 0;
+        while pos < data.len() {
+            let block_len = try!(read_be_u32(data, pos));
+            pos += 4;
+            let block_end = out.len() + block_len;
+            while out.len() < block_end {
+                let compressed_len = try!(read_be_u32(data, pos));
+                pos += 4;
+                if pos + compressed_len > data.len() {
+                    return Err(corrupt_block("the data is truncated"));
+                }
+                let compressed = &data[pos..pos + compressed_len];
+                pos += compressed_len;
+                let chunk = if *self == ImageCodec::Snappy {
+                    try!(snap::raw::Decoder::new().decompress_vec(compressed)
+                         .map_err(|e| corrupt_block(&e.to_string())))
+                } else {
+                    try!(lz4_flex::block::decompress(compressed, block_end - out.len())
+                         .map_err(|e| corrupt_block(&e.to_string())))
+                };
+                if chunk.is_empty() {
+                    return Err(corrupt_block("a chunk is empty"));
+                }
+                out.extend_from_slice(&chunk);
+            }
+        }
+        return Result::Ok(out);
+    }
+}
+
+fn be_u32(val: usize) -> [u8; 4] {
+    return [ (val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8 ];
+}
+
+fn read_be_u32(data: &[u8], pos: usize) -> Result<usize, Error> {
+    if pos + 4 > data.len() {
+        return Err(corrupt_block("the data is truncated"));
+    }
+    return Result::Ok(((data[pos] as usize) << 24) | ((data[pos + 1] as usize) << 16) |
+                      ((data[pos + 2] as usize) << 8) | (data[pos + 3] as usize));
+}
+
+fn corrupt_block(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("corrupt compressed block: {}", message));
+}
@@ -0,0 +1,1278 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use codec::ImageCodec;
+use fault::FaultAmount;
+use fault::FaultKind;
+use fault::FAULT_KINDS;
+use manifest::parse_date;
+use naming::DirNaming;
+use naming::FileNaming;
+use naming::MAX_NAME_BYTES;
+use naming::NameCharset;
+use replication::ReplicationRule;
+use replication::parse_replication_weights;
+use namespace::ROOT_INODE_ID;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use template::LinkMode;
+use toml_edit::ImDocument;
+use toml_edit::Table;
+use toml_edit::Value;
+use volume::DEFAULT_VOLUME_PREFIX;
+use volume::StorageType;
+use volume::VOLUME_CAPACITY_UNLIMITED;
+use volume::VolumeChoosingPolicy;
+use volume::VolumeGroup;
+
+// The date of the first date partition directory by default: 2024-01-01
+const DEFAULT_PARTITION_START_DAY : i64 = 19723;
+
+// Default namespace ID of generated fsimage
+const DEFAULT_NAMESPACE_ID : u64 = 397694258;
+
+// Default cluster ID of generated fsimage
+static DEFAULT_CLUSTER_ID : &'static str = "CID-4d05b066-8649-49c7-80cf-49ed7eac011c";
+
+// Default block pool ID of generated fsimage
+const DEFAULT_BLOCK_POOL_ID : &'static str = "BP-113955101-127.0.0.1-1455743472614";
+
+// The default cluster creation time.  This must be the same on NN and DNs.
+const DEFAULT_CLUSTER_CTIME : u64 = 1455743472614;
+
+// The default preferred block size used by files in the fsimage.
+const DEFAULT_PREFERRED_BLOCK_SIZE : u64 = 134217728;
+
+// The default datanode layout version of the generated fsimage
+const DEFAULT_DATANODE_LAYOUT_VERSION : i32 = -56;
+
+// The default namenode layout version of the generated fsimage
+const DEFAULT_NAMENODE_LAYOUT_VERSION : i32 = -60;
+
+// See CachePoolInfo#LIMIT_UNLIMITED
+pub const CACHE_POOL_LIMIT_UNLIMITED : u64 = 9223372036854775807;
+
+// See CacheDirectiveInfo.Expiration#MAX_RELATIVE_EXPIRY_MS
+pub const CACHE_MAX_RELATIVE_EXPIRY_MS : u64 = 2305843009213693951;
+
+// The number of ports each datanode of a pseudo-distributed cluster uses.
+pub const DATANODE_PORT_STRIDE : u64 = 10;
+
+// The number of milliseconds in a day.
+const MS_PER_DAY : u64 = 86400000;
+
+// Represents an fsgen configuration.
+//
+// A configuration starts out with the defaults, is optionally overlaid with
+// the contents of a configuration file, and is then overlaid with any
+// command-line flags.  The TOML key which sets each field is noted next to
+// it.
+pub struct Config {
+    // cluster.namespace_id
+    pub namespace_id: u64,
+
+    // cluster.cluster_id
+    pub cluster_id: String,
+
+    // cluster.block_pool_id
+    pub block_pool_id: String,
+
+    // cluster.ctime
+    pub cluster_ctime: u64,
+
+    // cluster.namenode_layout_version
+    pub nn_layout_version: i32,
+
+    // cluster.datanode_layout_version
+    pub dn_layout_version: i32,
+
+    // datanodes.count
+    pub num_datanodes: u16,
+
+    // datanodes.racks
+    pub num_racks: u16,
+
+    // datanodes.volume_choosing_policy
+    pub volume_choosing_policy: VolumeChoosingPolicy,
+
+    // The [[volumes]] tables.  Datanodes which aren't in any of these have
+    // num_storage_dirs_per_dn volumes of unlimited size.
+    pub volume_groups: Vec<VolumeGroup>,
+
+    // datanodes.storage_dirs_per_datanode
+    pub num_storage_dirs_per_dn: u16,
+
+    // namespace.num_inodes
+    pub num_inodes: u32,
+
+    // namespace.entries_per_dir
+    pub entries_per_dir: usize,
+
+    // namespace.dirs_per_dir
+    pub dirs_per_dir: usize,
+
+    // namespace.append_image: an existing fsimage, XML or binary, whose
+    // namespace is grown by num_inodes inodes instead of generating a new one
+    pub append_image: String,
+
+    // namespace.manifest: a file listing the paths to create, instead of
+    // generating num_inodes random ones.  See manifest.rs
+    pub manifest: String,
+
+    // names.directories: how directories are named
+    pub dir_naming: DirNaming,
+
+    // names.files: how files are named
+    pub file_naming: FileNaming,
+
+    // names.charset: the characters of random names
+    pub name_charset: NameCharset,
+
+    // names.min_length: the fewest characters in a random name
+    pub min_name_length: u32,
+
+    // names.max_length: the most characters in a random name
+    pub max_name_length: u32,
+
+    // names.partition_key: the key of hive partition directories
+    pub partition_key: String,
+
+    // names.start_date: the date of the first date or hive partition
+    // directory in each directory, in days since the epoch
+    pub partition_start_day: i64,
+
+    // files.replication
+    pub repl: u16,
+
+    // files.replication_weights: the replication factors which files get,
+    // each with a weight.  Empty means every file gets repl.
+    pub replication_weights: Vec<(u16, u32)>,
+
+    // The [[replication]] rules, in order
+    pub replication_rules: Vec<ReplicationRule>,
+
+    // files.preferred_block_size
+    pub preferred_block_size: u64,
+
+    // files.min_size
+    pub min_file_size: u64,
+
+    // files.max_size
+    pub max_file_size: u64,
+
+    // cache.num_pools
+    pub num_cache_pools: u32,
+
+    // cache.num_directives
+    pub num_cache_directives: u32,
+
+    // cache.pool_owner
+    pub cache_pool_owner: String,
+
+    // cache.pool_group
+    pub cache_pool_group: String,
+
+    // cache.pool_mode
+    pub cache_pool_mode: u16,
+
+    // cache.pool_limit
+    pub cache_pool_limit: u64,
+
+    // cache.pool_max_ttl
+    pub cache_pool_max_ttl: u64,
+
+    // cache.directive_replication
+    pub cache_directive_repl: u16,
+
+    // cache.directive_ttl
+    pub cache_directive_ttl: u64,
+
+    // delegation.num_keys
+    pub num_delegation_keys: u32,
+
+    // delegation.num_tokens
+    pub num_delegation_tokens: u32,
+
+    // delegation.key_ttl
+    pub delegation_key_ttl: u64,
+
+    // delegation.token_max_lifetime
+    pub delegation_token_max_lifetime: u64,
+
+    // delegation.token_renew_interval
+    pub delegation_token_renew_interval: u64,
+
+    // delegation.owners
+    pub delegation_token_owners: Vec<String>,
+
+    // delegation.renewers
+    pub delegation_token_renewers: Vec<String>,
+
+    // delegation.real_users
+    pub delegation_token_real_users: Vec<String>,
+
+    // output.dir
+    pub out_dir: String,
+
+    // output.seed
+    pub seed: u64,
+
+    // output.threads
+    pub num_threads: u32,
+
+    // output.stats_json
+    pub stats_json: String,
+
+    // output.only_datanodes: the 0-based indices of the datanodes whose
+    // directories should be written.  Empty means all of them.
+    pub only_datanodes: Vec<u16>,
+
+    // output.link_mode
+    pub link_mode: LinkMode,
+
+    // output.binary_image: whether to write a binary fsimage into the
+    // NameNode directory as well as the XML
+    pub binary_image: bool,
+
+    // output.image_codec: the codec which compresses the binary fsimage
+    pub image_codec: ImageCodec,
+
+    // output.xml_gzip: whether to gzip the XML fsimage
+    pub xml_gzip: bool,
+
+    // output.progress_interval: how often to report the progress of each
+    // phase, in seconds.  0 means only when a phase finishes.
+    pub progress_interval: u32,
+
+    // output.progress_json: whether to also write progress reports to
+    // stderr as JSON, one object per line
+    pub progress_json: bool,
+
+    // output.stall_timeout: how long a worker thread can go without making
+    // progress, in seconds, before we warn that it is stalled.  0 means
+    // never.
+    pub stall_timeout: u32,
+
+    // hadoop.install_dir: where the output directory will be on the cluster's
+    // hosts.  Empty means the output directory itself.
+    pub hadoop_install_dir: String,
+
+    // hadoop.namenode_hosts
+    pub hadoop_namenode_hosts: Vec<String>,
+
+    // hadoop.namenode_rpc_port
+    pub hadoop_namenode_rpc_port: u16,
+
+    // hadoop.nameservice
+    pub hadoop_nameservice: String,
+
+    // hadoop.shared_edits_dir
+    pub hadoop_shared_edits_dir: String,
+
+    // hadoop.datanode_hosts: the host name of each datanode.  Empty means
+    // datanode01, datanode02 and so on.
+    pub hadoop_datanode_hosts: Vec<String>,
+
+    // hadoop.pseudo_distributed: run every daemon on one host, with a
+    // different set of ports for each datanode.
+    pub hadoop_pseudo_distributed: bool,
+
+    // hadoop.datanode_base_port: in a pseudo-distributed cluster, the first
+    // port of the first datanode.  Each datanode gets DATANODE_PORT_STRIDE
+    // ports.
+    pub hadoop_datanode_base_port: u16,
+
+    // faults.missing_blocks
+    pub fault_missing_blocks: FaultAmount,
+
+    // faults.missing_replicas
+    pub fault_missing_replicas: FaultAmount,
+
+    // faults.extra_replicas
+    pub fault_extra_replicas: FaultAmount,
+
+    // faults.stale_genstamps
+    pub fault_stale_genstamps: FaultAmount,
+
+    // faults.corrupt_replicas
+    pub fault_corrupt_replicas: FaultAmount,
+
+    // faults.stale_replicas
+    pub fault_stale_replicas: FaultAmount,
+
+    // faults.orphan_replicas
+    pub fault_orphan_replicas: FaultAmount,
+
+    // faults.manifest: where to write the list of injected faults.  Empty
+    // means faults.json in the output directory.
+    pub fault_manifest: String,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        return Config {
+            namespace_id: DEFAULT_NAMESPACE_ID,
+            cluster_id: DEFAULT_CLUSTER_ID.to_owned(),
+            block_pool_id: DEFAULT_BLOCK_POOL_ID.to_owned(),
+            cluster_ctime: DEFAULT_CLUSTER_CTIME,
+            nn_layout_version: DEFAULT_NAMENODE_LAYOUT_VERSION,
+            dn_layout_version: DEFAULT_DATANODE_LAYOUT_VERSION,
+            num_datanodes: 4,
+            num_racks: 1,
+            num_storage_dirs_per_dn: 20,
+            volume_choosing_policy: VolumeChoosingPolicy::RoundRobin,
+            volume_groups: vec![],
+            num_inodes: 10000,
+            entries_per_dir: 6,
+            dirs_per_dir: 3,
+            append_image: "".to_owned(),
+            manifest: "".to_owned(),
+            dir_naming: DirNaming::Random,
+            file_naming: FileNaming::Random,
+            name_charset: NameCharset::Ascii,
+            min_name_length: 4,
+            max_name_length: 8,
+            partition_key: "dt".to_owned(),
+            partition_start_day: DEFAULT_PARTITION_START_DAY,
+            repl: 3,
+            replication_weights: vec![],
+            replication_rules: vec![],
+            preferred_block_size: DEFAULT_PREFERRED_BLOCK_SIZE,
+            min_file_size: 0,
+            max_file_size: 0,
+            num_cache_pools: 0,
+            num_cache_directives: 0,
+            cache_pool_owner: "cmccabe".to_owned(),
+            cache_pool_group: "supergroup".to_owned(),
+            cache_pool_mode: 0o755,
+            cache_pool_limit: CACHE_POOL_LIMIT_UNLIMITED,
+            cache_pool_max_ttl: CACHE_MAX_RELATIVE_EXPIRY_MS,
+            cache_directive_repl: 1,
+            cache_directive_ttl: CACHE_MAX_RELATIVE_EXPIRY_MS,
+            num_delegation_keys: 0,
+            num_delegation_tokens: 0,
+            delegation_key_ttl: 8 * MS_PER_DAY,
+            delegation_token_max_lifetime: 7 * MS_PER_DAY,
+            delegation_token_renew_interval: MS_PER_DAY,
+            delegation_token_owners: vec!["cmccabe".to_owned()],
+            delegation_token_renewers: vec!["yarn".to_owned()],
+            delegation_token_real_users: vec![],
+            out_dir: "".to_owned(),
+            seed: 0xdeadbeef,
+            num_threads: 16,
+            stats_json: "".to_owned(),
+            only_datanodes: vec![],
+            link_mode: LinkMode::None,
+            binary_image: false,
+            image_codec: ImageCodec::None,
+            xml_gzip: false,
+            progress_interval: 10,
+            progress_json: false,
+            stall_timeout: 60,
+            hadoop_install_dir: "".to_owned(),
+            hadoop_namenode_hosts: vec!["localhost".to_owned()],
+            hadoop_namenode_rpc_port: 8020,
+            hadoop_nameservice: "".to_owned(),
+            hadoop_shared_edits_dir: "".to_owned(),
+            hadoop_datanode_hosts: vec![],
+            hadoop_pseudo_distributed: false,
+            hadoop_datanode_base_port: 20000,
+            fault_missing_blocks: FaultAmount::Count(0),
+            fault_missing_replicas: FaultAmount::Count(0),
+            fault_extra_replicas: FaultAmount::Count(0),
+            fault_stale_genstamps: FaultAmount::Count(0),
+            fault_corrupt_replicas: FaultAmount::Count(0),
+            fault_stale_replicas: FaultAmount::Count(0),
+            fault_orphan_replicas: FaultAmount::Count(0),
+            fault_manifest: "".to_owned(),
+        }
+    }
+
+    // Get how many blocks should get a kind of fault.
+    pub fn fault_amount(&self, kind: FaultKind) -> FaultAmount {
+        match kind {
+            FaultKind::MissingBlock => return self.fault_missing_blocks,
+            FaultKind::MissingReplica => return self.fault_missing_replicas,
+            FaultKind::ExtraReplica => return self.fault_extra_replicas,
+            FaultKind::StaleGenstamp => return self.fault_stale_genstamps,
+            FaultKind::CorruptReplica => return self.fault_corrupt_replicas,
+            FaultKind::StaleReplica => return self.fault_stale_replicas,
+            FaultKind::OrphanReplica => return self.fault_orphan_replicas,
+        }
+    }
+
+    // Check whether any faults will be injected.
+    pub fn has_faults(&self) -> bool {
+        return FAULT_KINDS.iter().any(|kind| self.fault_amount(*kind) != FaultAmount::Count(0));
+    }
+
+    // Get the path of the fault manifest.
+    pub fn fault_manifest_path(&self) -> String {
+        if self.fault_manifest.is_empty() {
+            return format!("{}/faults.json", self.out_dir);
+        }
+        return self.fault_manifest.clone();
+    }
+
+    // Load a TOML configuration file on top of this configuration.
+    pub fn load_file(&mut self, path: &str,
+                     sources: &mut ConfigSources) -> Result<(), ConfigError> {
+        let mut text = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(e) => return Err(ConfigError::new(Some(path.to_owned()),
+                        format!("failed to read configuration file: {}", e))),
+        }
+        let doc = match ImDocument::parse(text.clone()) {
+            Ok(doc) => doc,
+            Err(e) => {
+                let line = e.span().map(|span| line_of(&text, span.start));
+                return Err(ConfigError::new(Some(location(path, line)),
+                            e.message().to_owned()));
+            }
+        };
+        let file = ConfigFile { path: path, text: &text };
+        for (section_name, section) in doc.iter() {
+            if section_name == "volumes" {
+                match section.as_array_of_tables() {
+                    Some(tables) => {
+                        for table in tables.iter() {
+                            let group = try!(load_volume_group(&file, table));
+                            self.volume_groups.push(group);
+                        }
+                        sources.set("volumes", location(path,
+                                Some(line_of(&text, section.span().unwrap_or(0..0).start))));
+                        continue;
+                    },
+                    None => return Err(file.error(doc.key(section_name).unwrap().span(),
+                            "volumes must be given as [[volumes]] tables".to_owned())),
+                }
+            }
+            if section_name == "replication" {
+                match section.as_array_of_tables() {
+                    Some(tables) => {
+                        for table in tables.iter() {
+                            let rule = try!(load_replication_rule(&file, table));
+                            self.replication_rules.push(rule);
+                        }
+                        continue;
+                    },
+                    None => return Err(file.error(doc.key(section_name).unwrap().span(),
+                            "replication rules must be given as [[replication]] tables"
+                            .to_owned())),
+                }
+            }
+            let table = match section.as_table() {
+                Some(table) => table,
+                None => return Err(file.error(doc.key(section_name).unwrap().span(),
+                        format!("expected a [{}] section, not a bare key", section_name))),
+            };
+            try!(self.load_section(&file, section_name, table, sources));
+        }
+        return Result::Ok(());
+    }
+
+    fn load_section(&mut self, file: &ConfigFile, section_name: &str, table: &Table,
+                    sources: &mut ConfigSources) -> Result<(), ConfigError> {
+        for (key_name, item) in table.iter() {
+            let value = match item.as_value() {
+                Some(value) => value,
+                None => return Err(file.error(item.span(),
+                        format!("{}.{} must be a value, not a table", section_name, key_name))),
+            };
+            let key = match find_key(section_name, key_name) {
+                Some(key) => key,
+                None => return Err(file.error(table.key(key_name).unwrap().span(),
+                        format!("unknown configuration key {}.{}", section_name, key_name))),
+            };
+            match self.set(key, value) {
+                Ok(()) => (),
+                Err(message) => return Err(file.error(value.span(),
+                        format!("{}: {}", key, message))),
+            }
+            sources.set(key, location(file.path,
+                    Some(line_of(file.text, value.span().unwrap_or(0..0).start))));
+        }
+        return Result::Ok(());
+    }
+
+    // Set the configuration field named by a TOML key.
+    fn set(&mut self, key: &'static str, value: &Value) -> Result<(), String> {
+        match key {
+            "cluster.namespace_id" => self.namespace_id = try!(to_u64(value)),
+            "cluster.cluster_id" => self.cluster_id = try!(to_string(value)),
+            "cluster.block_pool_id" => self.block_pool_id = try!(to_string(value)),
+            "cluster.ctime" => self.cluster_ctime = try!(to_u64(value)),
+            "cluster.namenode_layout_version" =>
+                self.nn_layout_version = try!(to_int(value, i32::MIN as i64,
+                                                     i32::MAX as i64)) as i32,
+            "cluster.datanode_layout_version" =>
+                self.dn_layout_version = try!(to_int(value, i32::MIN as i64,
+                                                     i32::MAX as i64)) as i32,
+            "datanodes.count" => self.num_datanodes = try!(to_u16(value)),
+            "datanodes.racks" => self.num_racks = try!(to_u16(value)),
+            "datanodes.volume_choosing_policy" =>
+                self.volume_choosing_policy = try!(to_volume_choosing_policy(value)),
+            "datanodes.storage_dirs_per_datanode" =>
+                self.num_storage_dirs_per_dn = try!(to_u16(value)),
+            "namespace.num_inodes" => self.num_inodes = try!(to_u32(value)),
+            "namespace.entries_per_dir" => self.entries_per_dir = try!(to_u32(value)) as usize,
+            "namespace.dirs_per_dir" => self.dirs_per_dir = try!(to_u32(value)) as usize,
+            "namespace.append_image" => self.append_image = try!(to_string(value)),
+            "namespace.manifest" => self.manifest = try!(to_string(value)),
+            "names.directories" => self.dir_naming = try!(to_dir_naming(value)),
+            "names.files" => self.file_naming = try!(to_file_naming(value)),
+            "names.charset" => self.name_charset = try!(to_name_charset(value)),
+            "names.min_length" => self.min_name_length = try!(to_u32(value)),
+            "names.max_length" => self.max_name_length = try!(to_u32(value)),
+            "names.partition_key" => self.partition_key = try!(to_string(value)),
+            "names.start_date" => self.partition_start_day = try!(to_date(value)),
+            "files.replication" => self.repl = try!(to_u16(value)),
+            "files.replication_weights" =>
+                self.replication_weights = try!(to_replication_weights(value)),
+            "files.preferred_block_size" => self.preferred_block_size = try!(to_u64(value)),
+            "files.min_size" => self.min_file_size = try!(to_u64(value)),
+            "files.max_size" => self.max_file_size = try!(to_u64(value)),
+            "cache.num_pools" => self.num_cache_pools = try!(to_u32(value)),
+            "cache.num_directives" => self.num_cache_directives = try!(to_u32(value)),
+            "cache.pool_owner" => self.cache_pool_owner = try!(to_string(value)),
+            "cache.pool_group" => self.cache_pool_group = try!(to_string(value)),
+            "cache.pool_mode" => self.cache_pool_mode = try!(to_u16(value)),
+            "cache.pool_limit" => self.cache_pool_limit = try!(to_u64(value)),
+            "cache.pool_max_ttl" => self.cache_pool_max_ttl = try!(to_u64(value)),
+            "cache.directive_replication" => self.cache_directive_repl = try!(to_u16(value)),
+            "cache.directive_ttl" => self.cache_directive_ttl = try!(to_u64(value)),
+            "delegation.num_keys" => self.num_delegation_keys = try!(to_u32(value)),
+            "delegation.num_tokens" => self.num_delegation_tokens = try!(to_u32(value)),
+            "delegation.key_ttl" => self.delegation_key_ttl = try!(to_u64(value)),
+            "delegation.token_max_lifetime" =>
+                self.delegation_token_max_lifetime = try!(to_u64(value)),
+            "delegation.token_renew_interval" =>
+                self.delegation_token_renew_interval = try!(to_u64(value)),
+            "delegation.owners" => self.delegation_token_owners = try!(to_string_list(value)),
+            "delegation.renewers" =>
+                self.delegation_token_renewers = try!(to_string_list(value)),
+            "delegation.real_users" =>
+                self.delegation_token_real_users = try!(to_string_list(value)),
+            "output.dir" => self.out_dir = try!(to_string(value)),
+            "output.seed" => self.seed = try!(to_u64(value)),
+            "output.threads" => self.num_threads = try!(to_u32(value)),
+            "output.stats_json" => self.stats_json = try!(to_string(value)),
+            "output.only_datanodes" => self.only_datanodes = try!(to_datanode_list(value)),
+            "output.link_mode" => self.link_mode = try!(to_link_mode(value)),
+            "output.binary_image" => self.binary_image = try!(to_bool(value)),
+            "output.image_codec" => self.image_codec = try!(to_image_codec(value)),
+            "output.xml_gzip" => self.xml_gzip = try!(to_bool(value)),
+            "output.progress_interval" => self.progress_interval = try!(to_u32(value)),
+            "output.progress_json" => self.progress_json = try!(to_bool(value)),
+            "output.stall_timeout" => self.stall_timeout = try!(to_u32(value)),
+            "hadoop.install_dir" => self.hadoop_install_dir = try!(to_string(value)),
+            "hadoop.namenode_hosts" => self.hadoop_namenode_hosts = try!(to_string_list(value)),
+            "hadoop.namenode_rpc_port" => self.hadoop_namenode_rpc_port = try!(to_u16(value)),
+            "hadoop.nameservice" => self.hadoop_nameservice = try!(to_string(value)),
+            "hadoop.shared_edits_dir" => self.hadoop_shared_edits_dir = try!(to_string(value)),
+            "hadoop.datanode_hosts" => self.hadoop_datanode_hosts = try!(to_string_list(value)),
+            "hadoop.pseudo_distributed" => self.hadoop_pseudo_distributed = try!(to_bool(value)),
+            "hadoop.datanode_base_port" => self.hadoop_datanode_base_port = try!(to_u16(value)),
+            "faults.missing_blocks" => self.fault_missing_blocks = try!(to_fault_amount(value)),
+            "faults.missing_replicas" =>
+                self.fault_missing_replicas = try!(to_fault_amount(value)),
+            "faults.extra_replicas" => self.fault_extra_replicas = try!(to_fault_amount(value)),
+            "faults.stale_genstamps" =>
+                self.fault_stale_genstamps = try!(to_fault_amount(value)),
+            "faults.corrupt_replicas" =>
+                self.fault_corrupt_replicas = try!(to_fault_amount(value)),
+            "faults.stale_replicas" => self.fault_stale_replicas = try!(to_fault_amount(value)),
+            "faults.orphan_replicas" =>
+                self.fault_orphan_replicas = try!(to_fault_amount(value)),
+            "faults.manifest" => self.fault_manifest = try!(to_string(value)),
+            _ => panic!("no setter for configuration key {}", key),
+        }
+        return Result::Ok(());
+    }
+
+    // Check that the configuration is self-consistent.  Errors point at the
+    // place where the offending key was set.
+    pub fn validate(&self, sources: &ConfigSources) -> Result<(), ConfigError> {
+        if self.out_dir == "" {
+            return Err(sources.error("output.dir",
+                "You must specify an output directory with -o.  -h for help.".to_owned()));
+        }
+        if self.num_threads == 0 {
+            return Err(sources.error("output.threads",
+                "There must be at least 1 worker thread.".to_owned()));
+        }
+        return self.validate_model(sources);
+    }
+
+    // Check the parts of the configuration which describe the cluster itself,
+    // ignoring where it will be written.
+    pub fn validate_model(&self, sources: &ConfigSources) -> Result<(), ConfigError> {
+        if self.dn_layout_version >= 0 {
+            return Err(sources.error("cluster.datanode_layout_version",
+                "The datanode layout version must be less than 0.".to_owned()));
+        }
+        if self.nn_layout_version >= 0 {
+            return Err(sources.error("cluster.namenode_layout_version",
+                "The namenode layout version must be less than 0.".to_owned()));
+        }
+        if self.num_inodes == 0 {
+            return Err(sources.error("namespace.num_inodes",
+                "The namespace must have at least 1 inode besides the root directory."
+                .to_owned()));
+        }
+        if self.num_inodes > u32::MAX - ROOT_INODE_ID {
+            return Err(sources.error("namespace.num_inodes",
+                format!("The namespace can have at most {} inodes besides the root \
+                        directory.", u32::MAX - ROOT_INODE_ID)));
+        }
+        if self.min_name_length == 0 {
+            return Err(sources.error("names.min_length",
+                "Names must be at least 1 character long.".to_owned()));
+        }
+        if self.min_name_length > self.max_name_length {
+            return Err(sources.error("names.min_length",
+                format!("The minimum name length, {}, is more than the maximum, {}.",
+                        self.min_name_length, self.max_name_length)));
+        }
+        if self.max_name_length > MAX_NAME_BYTES / self.name_charset.max_char_bytes() {
+            return Err(sources.error("names.max_length",
+                format!("{} names can be at most {} characters long, or HDFS will reject \
+                        them.", self.name_charset.name(),
+                        MAX_NAME_BYTES / self.name_charset.max_char_bytes())));
+        }
+        if self.partition_key.is_empty() || self.partition_key.contains('/') ||
+                self.partition_key.contains('=') {
+            return Err(sources.error("names.partition_key",
+                format!("{:?} is not a valid partition key.", self.partition_key)));
+        }
+        if self.repl == 0 {
+            return Err(sources.error("files.replication",
+                "The replication factor must be at least 1.".to_owned()));
+        }
+        if self.num_storage_dirs_per_dn == 0 {
+            return Err(sources.error("datanodes.storage_dirs_per_datanode",
+                "Each datanode must have at least 1 storage directory.".to_owned()));
+        }
+        if self.num_datanodes < self.repl {
+            return Err(sources.error("files.replication",
+                format!("You specified {}x replication, but only {} datanodes.",
+                        self.repl, self.num_datanodes)));
+        }
+        if let Some(&(repl, _)) = self.replication_weights.iter()
+                .find(|&&(repl, _)| repl > self.num_datanodes) {
+            return Err(sources.error("files.replication_weights",
+                format!("You specified {}x replication, but only {} datanodes.",
+                        repl, self.num_datanodes)));
+        }
+        for rule in &self.replication_rules {
+            if rule.replication > self.num_datanodes {
+                return Err(ConfigError::new(Some(rule.location.clone()),
+                    format!("The rule for {} specifies {}x replication, but there are only \
+                            {} datanodes.", rule.pattern, rule.replication,
+                            self.num_datanodes)));
+            }
+        }
+        if (self.num_racks == 0) || (self.num_racks > self.num_datanodes) {
+            return Err(sources.error("datanodes.racks",
+                format!("The number of racks must be between 1 and the number of \
+                        datanodes ({}).", self.num_datanodes)));
+        }
+        for group in &self.volume_groups {
+            if let Some(idx) = group.datanodes.iter().find(|idx| **idx >= self.num_datanodes) {
+                return Err(sources.error("volumes",
+                    format!("A volume group is for datanode {}, but there are only {} \
+                            datanodes.", idx + 1, self.num_datanodes)));
+            }
+        }
+        if let Some(idx) = self.only_datanodes.iter().find(|idx| **idx >= self.num_datanodes) {
+            return Err(sources.error("output.only_datanodes",
+                format!("You asked for datanode {}, but there are only {} datanodes.",
+                        idx + 1, self.num_datanodes)));
+        }
+        if (self.image_codec != ImageCodec::None) && !self.binary_image {
+            return Err(sources.error("output.image_codec",
+                "Only the binary fsimage can be compressed with a codec.  Set \
+                output.binary_image, or use output.xml_gzip for the XML.".to_owned()));
+        }
+        if !self.hadoop_install_dir.is_empty() && !self.hadoop_install_dir.starts_with('/') {
+            return Err(sources.error("hadoop.install_dir",
+                "The install directory must be an absolute path.".to_owned()));
+        }
+        if self.hadoop_namenode_hosts.is_empty() {
+            return Err(sources.error("hadoop.namenode_hosts",
+                "You must specify at least one namenode host.".to_owned()));
+        }
+        if (self.hadoop_namenode_hosts.len() > 1) && self.hadoop_nameservice.is_empty() {
+            return Err(sources.error("hadoop.namenode_hosts",
+                format!("You specified {} namenode hosts, but HA needs a nameservice.",
+                        self.hadoop_namenode_hosts.len())));
+        }
+        if !self.hadoop_datanode_hosts.is_empty() &&
+                (self.hadoop_datanode_hosts.len() != self.num_datanodes as usize) {
+            return Err(sources.error("hadoop.datanode_hosts",
+                format!("You specified {} datanode hosts, but there are {} datanodes.",
+                        self.hadoop_datanode_hosts.len(), self.num_datanodes)));
+        }
+        if self.hadoop_pseudo_distributed {
+            if self.hadoop_namenode_hosts.len() > 1 {
+                return Err(sources.error("hadoop.namenode_hosts",
+                    "A pseudo-distributed cluster has only one namenode host.".to_owned()));
+            }
+            let last_port = (self.hadoop_datanode_base_port as u64) +
+                (self.num_datanodes as u64) * DATANODE_PORT_STRIDE - 1;
+            if last_port > u16::MAX as u64 {
+                return Err(sources.error("hadoop.datanode_base_port",
+                    format!("The ports of {} datanodes starting at {} don't fit below {}.",
+                            self.num_datanodes, self.hadoop_datanode_base_port, u16::MAX)));
+            }
+        }
+        if self.dirs_per_dir > self.entries_per_dir {
+            return Err(sources.error("namespace.dirs_per_dir",
+                format!("You specified {} directories per directory, but only {} entries \
+                        per directory.", self.dirs_per_dir, self.entries_per_dir)));
+        }
+        if self.dirs_per_dir == 0 {
+            return Err(sources.error("namespace.dirs_per_dir",
+                "There must be at least 1 directory per directory.".to_owned()));
+        }
+        if self.preferred_block_size == 0 {
+            return Err(sources.error("files.preferred_block_size",
+                "The preferred block size must be at least 1.".to_owned()));
+        }
+        if self.min_file_size > self.max_file_size {
+            return Err(sources.error("files.min_size",
+                format!("The minimum file size of {} exceeds the maximum file size of {}.",
+                        self.min_file_size, self.max_file_size)));
+        }
+        if (self.num_cache_directives > 0) && (self.num_cache_pools == 0) {
+            return Err(sources.error("cache.num_directives",
+                format!("You asked for {} cache directives, but there are no cache pools \
+                        to put them in.", self.num_cache_directives)));
+        }
+        if self.cache_pool_mode > 0o777 {
+            return Err(sources.error("cache.pool_mode",
+                "The cache pool mode must be between 000 and 777.".to_owned()));
+        }
+        if self.cache_pool_max_ttl > CACHE_MAX_RELATIVE_EXPIRY_MS {
+            return Err(sources.error("cache.pool_max_ttl",
+                format!("The cache pool max TTL must be at most {} ms.",
+                        CACHE_MAX_RELATIVE_EXPIRY_MS)));
+        }
+        if self.cache_directive_ttl > self.cache_pool_max_ttl {
+            return Err(sources.error("cache.directive_ttl",
+                format!("The cache directive TTL of {} ms exceeds the cache pool max TTL \
+                        of {} ms.", self.cache_directive_ttl, self.cache_pool_max_ttl)));
+        }
+        if self.cache_directive_repl == 0 {
+            return Err(sources.error("cache.directive_replication",
+                "The cache directive replication must be at least 1.".to_owned()));
+        }
+        if (self.num_delegation_tokens > 0) && (self.num_delegation_keys == 0) {
+            return Err(sources.error("delegation.num_tokens",
+                format!("You asked for {} delegation tokens, but there are no delegation \
+                        keys to sign them with.", self.num_delegation_tokens)));
+        }
+        if self.delegation_token_owners.is_empty() {
+            return Err(sources.error("delegation.owners",
+                "You must specify at least one delegation token owner.".to_owned()));
+        }
+        if self.delegation_token_renewers.is_empty() {
+            return Err(sources.error("delegation.renewers",
+                "You must specify at least one delegation token renewer.".to_owned()));
+        }
+        return Result::Ok(());
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        return Config::new();
+    }
+}
+
+// All of the keys which may appear in a configuration file.
+static CONFIG_KEYS : &[&str] = &[
+    "cluster.namespace_id",
+    "cluster.cluster_id",
+    "cluster.block_pool_id",
+    "cluster.ctime",
+    "cluster.namenode_layout_version",
+    "cluster.datanode_layout_version",
+    "datanodes.count",
+    "datanodes.racks",
+    "datanodes.storage_dirs_per_datanode",
+    "datanodes.volume_choosing_policy",
+    "namespace.num_inodes",
+    "namespace.entries_per_dir",
+    "namespace.dirs_per_dir",
+    "namespace.append_image",
+    "namespace.manifest",
+    "names.directories",
+    "names.files",
+    "names.charset",
+    "names.min_length",
+    "names.max_length",
+    "names.partition_key",
+    "names.start_date",
+    "files.replication",
+    "files.replication_weights",
+    "files.preferred_block_size",
+    "files.min_size",
+    "files.max_size",
+    "cache.num_pools",
+    "cache.num_directives",
+    "cache.pool_owner",
+    "cache.pool_group",
+    "cache.pool_mode",
+    "cache.pool_limit",
+    "cache.pool_max_ttl",
+    "cache.directive_replication",
+    "cache.directive_ttl",
+    "delegation.num_keys",
+    "delegation.num_tokens",
+    "delegation.key_ttl",
+    "delegation.token_max_lifetime",
+    "delegation.token_renew_interval",
+    "delegation.owners",
+    "delegation.renewers",
+    "delegation.real_users",
+    "output.dir",
+    "output.seed",
+    "output.threads",
+    "output.stats_json",
+    "output.only_datanodes",
+    "output.link_mode",
+    "output.binary_image",
+    "output.image_codec",
+    "output.xml_gzip",
+    "output.progress_interval",
+    "output.progress_json",
+    "output.stall_timeout",
+    "hadoop.install_dir",
+    "hadoop.namenode_hosts",
+    "hadoop.namenode_rpc_port",
+    "hadoop.nameservice",
+    "hadoop.shared_edits_dir",
+    "hadoop.datanode_hosts",
+    "hadoop.pseudo_distributed",
+    "hadoop.datanode_base_port",
+    "faults.missing_blocks",
+    "faults.missing_replicas",
+    "faults.extra_replicas",
+    "faults.stale_genstamps",
+    "faults.corrupt_replicas",
+    "faults.stale_replicas",
+    "faults.orphan_replicas",
+    "faults.manifest",
+];
+
+fn find_key(section_name: &str, key_name: &str) -> Option<&'static str> {
+    let full_name = format!("{}.{}", section_name, key_name);
+    return CONFIG_KEYS.iter().find(|key| **key == full_name).cloned();
+}
+
+// Records where each configuration key was set, so that errors can point at
+// the offending line of the configuration file, or the offending flag.
+pub struct ConfigSources {
+    sources: HashMap<&'static str, String>,
+}
+
+impl ConfigSources {
+    pub fn new() -> ConfigSources {
+        return ConfigSources {
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: &'static str, source: String) {
+        self.sources.insert(key, source);
+    }
+
+    fn error(&self, key: &'static str, message: String) -> ConfigError {
+        return ConfigError::new(self.sources.get(key).cloned(), message);
+    }
+}
+
+impl Default for ConfigSources {
+    fn default() -> ConfigSources {
+        return ConfigSources::new();
+    }
+}
+
+// A configuration file which is being loaded.
+struct ConfigFile<'a> {
+    path: &'a str,
+    text: &'a str,
+}
+
+impl<'a> ConfigFile<'a> {
+    fn error(&self, span: Option<::std::ops::Range<usize>>, message: String) -> ConfigError {
+        let line = span.map(|span| line_of(self.text, span.start));
+        return ConfigError::new(Some(location(self.path, line)), message);
+    }
+}
+
+// An invalid configuration.
+#[derive(Debug)]
+pub struct ConfigError {
+    // Where the offending value came from, if known.
+    location: Option<String>,
+
+    // What is wrong with it.
+    message: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(location: Option<String>, message: String) -> ConfigError {
+        return ConfigError {
+            location: location,
+            message: message,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            None => write!(f, "{}", self.message),
+            Some(ref location) => write!(f, "{}: {}", location, self.message),
+        }
+    }
+}
+
+// Get the 1-based line number of a byte offset in some text.
+fn line_of(text: &str, offset: usize) -> usize {
+    return text[..offset].matches('\n').count() + 1;
+}
+
+fn location(path: &str, line: Option<usize>) -> String {
+    match line {
+        None => path.to_owned(),
+        Some(line) => format!("{}:{}", path, line),
+    }
+}
+
+fn to_int(value: &Value, min: i64, max: i64) -> Result<i64, String> {
+    let val = match value.as_integer() {
+        Some(val) => val,
+        None => return Err("expected an integer".to_owned()),
+    };
+    if (val < min) || (val > max) {
+        return Err(format!("{} is out of range; it must be between {} and {}",
+                           val, min, max));
+    }
+    return Result::Ok(val);
+}
+
+fn to_u16(value: &Value) -> Result<u16, String> {
+    return to_int(value, 0, u16::MAX as i64).map(|val| val as u16);
+}
+
+fn to_u32(value: &Value) -> Result<u32, String> {
+    return to_int(value, 0, u32::MAX as i64).map(|val| val as u32);
+}
+
+fn to_u64(value: &Value) -> Result<u64, String> {
+    return to_int(value, 0, i64::MAX).map(|val| val as u64);
+}
+
+fn to_bool(value: &Value) -> Result<bool, String> {
+    match value.as_bool() {
+        Some(val) => return Result::Ok(val),
+        None => return Err("expected true or false".to_owned()),
+    }
+}
+
+fn to_string(value: &Value) -> Result<String, String> {
+    match value.as_str() {
+        Some(val) => return Result::Ok(val.to_owned()),
+        None => return Err("expected a string".to_owned()),
+    }
+}
+
+fn to_string_list(value: &Value) -> Result<Vec<String>, String> {
+    let array = match value.as_array() {
+        Some(array) => array,
+        None => return Err("expected an array of strings".to_owned()),
+    };
+    let mut list : Vec<String> = vec![];
+    for element in array.iter() {
+        list.push(try!(to_string(element)));
+    }
+    return Result::Ok(list);
+}
+
+// Load one [[volumes]] table.
+fn load_volume_group(file: &ConfigFile, table: &Table) -> Result<VolumeGroup, ConfigError> {
+    let mut group = VolumeGroup {
+        datanodes: vec![],
+        count: 1,
+        path_prefix: DEFAULT_VOLUME_PREFIX.to_owned(),
+        capacity: VOLUME_CAPACITY_UNLIMITED,
+        storage_type: StorageType::Disk,
+    };
+    for (key_name, item) in table.iter() {
+        let value = match item.as_value() {
+            Some(value) => value,
+            None => return Err(file.error(item.span(),
+                    format!("volumes.{} must be a value, not a table", key_name))),
+        };
+        let result = match key_name {
+            "datanodes" => to_datanode_list(value).map(|val| group.datanodes = val),
+            "count" => to_u16(value).and_then(|val| {
+                if val == 0 {
+                    return Err("there must be at least 1 volume".to_owned());
+                }
+                group.count = val;
+                return Result::Ok(());
+            }),
+            "path" => to_string(value).and_then(|val| {
+                if val.is_empty() || val.starts_with('/') || val.contains("..") {
+                    return Err("expected a path relative to the datanode directory".to_owned());
+                }
+                group.path_prefix = val;
+                return Result::Ok(());
+            }),
+            "capacity" => to_u64(value).map(|val| group.capacity = val),
+            "storage_type" => to_string(value).and_then(|val| {
+                match StorageType::parse(&val) {
+                    Some(storage_type) => group.storage_type = storage_type,
+                    None => return Err(format!("unknown storage type {}; expected DISK, SSD, \
+                                               ARCHIVE, RAM_DISK or NVDIMM", val)),
+                }
+                return Result::Ok(());
+            }),
+            _ => return Err(file.error(table.key(key_name).unwrap().span(),
+                    format!("unknown configuration key volumes.{}", key_name))),
+        };
+        match result {
+            Ok(()) => (),
+            Err(message) => return Err(file.error(value.span(),
+                    format!("volumes.{}: {}", key_name, message))),
+        }
+    }
+    return Result::Ok(group);
+}
+
+// Load one [[replication]] table.
+fn load_replication_rule(file: &ConfigFile, table: &Table)
+                         -> Result<ReplicationRule, ConfigError> {
+    let mut pattern = None;
+    let mut replication = None;
+    for (key_name, item) in table.iter() {
+        let value = match item.as_value() {
+            Some(value) => value,
+            None => return Err(file.error(item.span(),
+                    format!("replication.{} must be a value, not a table", key_name))),
+        };
+        let result = match key_name {
+            "path" => to_string(value).and_then(|val| {
+                if !val.starts_with('/') {
+                    return Err("expected an absolute path, like /tmp/**".to_owned());
+                }
+                pattern = Some(val);
+                return Result::Ok(());
+            }),
+            "replication" => to_u16(value).and_then(|val| {
+                if val == 0 {
+                    return Err("the replication factor must be at least 1".to_owned());
+                }
+                replication = Some(val);
+                return Result::Ok(());
+            }),
+            _ => return Err(file.error(table.key(key_name).unwrap().span(),
+                    format!("unknown configuration key replication.{}", key_name))),
+        };
+        match result {
+            Ok(()) => (),
+            Err(message) => return Err(file.error(value.span(),
+                    format!("replication.{}: {}", key_name, message))),
+        }
+    }
+    let span = table.span();
+    return match (pattern, replication) {
+        (Some(pattern), Some(replication)) => Result::Ok(ReplicationRule {
+            pattern: pattern,
+            replication: replication,
+            location: location(file.path, span.map(|span| line_of(file.text, span.start))),
+        }),
+        _ => Err(file.error(span, "a [[replication]] rule needs a path and a replication \
+                                  factor".to_owned())),
+    };
+}
+
+fn to_replication_weights(value: &Value) -> Result<Vec<(u16, u32)>, String> {
+    return parse_replication_weights(&try!(to_string(value)));
+}
+
+// A fault amount is a number of blocks, or a fraction of all the blocks.
+fn to_fault_amount(value: &Value) -> Result<FaultAmount, String> {
+    if value.as_integer().is_some() {
+        return to_u64(value).map(FaultAmount::Count);
+    }
+    match value.as_float() {
+        Some(val) if (0.0..=1.0).contains(&val) => return Result::Ok(FaultAmount::Fraction(val)),
+        Some(val) => return Err(format!("the fraction {} must be between 0 and 1", val)),
+        None => return Err("expected a number of blocks, or a fraction like 0.01".to_owned()),
+    }
+}
+
+fn to_link_mode(value: &Value) -> Result<LinkMode, String> {
+    return parse_link_mode(&try!(to_string(value)));
+}
+
+pub fn parse_link_mode(name: &str) -> Result<LinkMode, String> {
+    match LinkMode::parse(name) {
+        Some(mode) => return Result::Ok(mode),
+        None => return Err(format!("unknown link mode {}; expected none, hardlink or reflink",
+                                   name)),
+    }
+}
+
+fn to_image_codec(value: &Value) -> Result<ImageCodec, String> {
+    return parse_image_codec(&try!(to_string(value)));
+}
+
+pub fn parse_image_codec(name: &str) -> Result<ImageCodec, String> {
+    match ImageCodec::parse(name) {
+        Some(codec) => return Result::Ok(codec),
+        None => return Err(format!("unknown image codec {}; expected none, default, gzip, \
+                                   snappy or lz4", name)),
+    }
+}
+
+fn to_dir_naming(value: &Value) -> Result<DirNaming, String> {
+    return parse_dir_naming(&try!(to_string(value)));
+}
+
+pub fn parse_dir_naming(name: &str) -> Result<DirNaming, String> {
+    match DirNaming::parse(name) {
+        Some(naming) => return Result::Ok(naming),
+        None => return Err(format!("unknown directory naming {}; expected random, date or \
+                                   hive", name)),
+    }
+}
+
+fn to_file_naming(value: &Value) -> Result<FileNaming, String> {
+    return parse_file_naming(&try!(to_string(value)));
+}
+
+pub fn parse_file_naming(name: &str) -> Result<FileNaming, String> {
+    match FileNaming::parse(name) {
+        Some(naming) => return Result::Ok(naming),
+        None => return Err(format!("unknown file naming {}; expected random or spark", name)),
+    }
+}
+
+fn to_name_charset(value: &Value) -> Result<NameCharset, String> {
+    return parse_name_charset(&try!(to_string(value)));
+}
+
+pub fn parse_name_charset(name: &str) -> Result<NameCharset, String> {
+    match NameCharset::parse(name) {
+        Some(charset) => return Result::Ok(charset),
+        None => return Err(format!("unknown name charset {}; expected ascii or unicode", name)),
+    }
+}
+
+// A date is a yyyy-mm-dd string.
+fn to_date(value: &Value) -> Result<i64, String> {
+    let text = try!(to_string(value));
+    match parse_date(&text) {
+        Some(days) => return Result::Ok(days),
+        None => return Err(format!("{} is not a yyyy-mm-dd date", text)),
+    }
+}
+
+fn to_volume_choosing_policy(value: &Value) -> Result<VolumeChoosingPolicy, String> {
+    return parse_volume_choosing_policy(&try!(to_string(value)));
+}
+
+pub fn parse_volume_choosing_policy(name: &str) -> Result<VolumeChoosingPolicy, String> {
+    match VolumeChoosingPolicy::parse(name) {
+        Some(policy) => return Result::Ok(policy),
+        None => return Err(format!("unknown volume choosing policy {}; expected round_robin \
+                                   or available_space", name)),
+    }
+}
+
+fn to_datanode_list(value: &Value) -> Result<Vec<u16>, String> {
+    if let Some(val) = value.as_integer() {
+        return parse_datanode_list(&val.to_string());
+    }
+    match value.as_str() {
+        Some(val) => return parse_datanode_list(val),
+        None => return Err("expected a datanode number or a string like \"1-4,7\"".to_owned()),
+    }
+}
+
+// Parse a comma-separated list of datanode numbers and ranges, like "1-4,7".
+// Datanodes are numbered from 1, like their directories, but the result holds
+// sorted 0-based indices.
+pub fn parse_datanode_list(list: &str) -> Result<Vec<u16>, String> {
+    let mut indices : Vec<u16> = vec![];
+    for item in list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (first, last) = match item.find('-') {
+            None => {
+                let num = try!(parse_datanode_number(item));
+                (num, num)
+            },
+            Some(idx) => (try!(parse_datanode_number(&item[..idx])),
+                          try!(parse_datanode_number(&item[(idx + 1)..]))),
+        };
+        if first > last {
+            return Err(format!("the datanode range {} is backwards", item));
+        }
+        for num in first..=last {
+            indices.push(num - 1);
+        }
+    }
+    if indices.is_empty() {
+        return Err("expected at least one datanode".to_owned());
+    }
+    indices.sort();
+    indices.dedup();
+    return Result::Ok(indices);
+}
+
+fn parse_datanode_number(num: &str) -> Result<u16, String> {
+    match num.trim().parse::<u16>() {
+        Ok(0) => return Err("datanodes are numbered from 1".to_owned()),
+        Ok(num) => return Result::Ok(num),
+        Err(_) => return Err(format!("'{}' is not a valid datanode number", num.trim())),
+    }
+}
+
+// Split a comma-separated list of user names, ignoring empty entries.
+pub fn split_user_list(list: &str) -> Vec<String> {
+    let mut users : Vec<String> = vec![];
+    for user in list.split(',') {
+        let user = user.trim();
+        if !user.is_empty() {
+            users.push(user.to_owned());
+        }
+    }
+    return users;
+}
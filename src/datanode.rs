@@ -0,0 +1,882 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use byteorder::{BigEndian, WriteBytesExt};
+use error::FsgenError;
+use fault::DiskReplica;
+use journal::JOURNAL_FILE_NAME;
+use journal::ProgressJournal;
+use journal::check_journal;
+use md5;
+use namespace::Block;
+use namespace::FSImage;
+use parallel::run_tasks;
+use protobuf::ProtoReader;
+use protobuf::read_varint_from;
+use protobuf::write_key;
+use protobuf::write_varint;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use template::TemplateKind;
+use template::TemplateStore;
+use verify::read_properties;
+
+// The number of data bytes covered by each checksum in a block meta file.
+const BYTES_PER_CHECKSUM : u64 = 512;
+
+// The first DataNode layout version which spreads finalized blocks over 32x32
+// subdirectories rather than 256x256.  See
+// DataNodeLayoutVersion#BLOCKID_BASED_LAYOUT_32_by_32
+const BLOCKID_BASED_LAYOUT_32_BY_32 : i32 = -57;
+
+// Get the directory, relative to finalized/, which holds the block with the
+// given ID.  See DatanodeUtil#idToBlockDir
+pub fn block_subdir(block_id: u64, dn_layout_version: i32) -> String {
+    let mask = if dn_layout_version <= BLOCKID_BASED_LAYOUT_32_BY_32 { 0x1f } else { 0xff };
+    return format!("subdir{}/subdir{}", (block_id >> 16) & mask, (block_id >> 8) & mask);
+}
+
+// The files which write_datanode_dirs would create for one datanode.
+pub struct DatanodeUsage {
+    // The directory which holds the datanode's storage directories
+    pub path: String,
+
+    pub num_storage_dirs: u16,
+
+    // The number of files, including VERSION and replica cache files
+    pub num_files: u64,
+
+    // The total length of the block data files.  These are sparse, so they
+    // take up almost no space on disk.
+    pub data_bytes: u64,
+
+    // The total length of the block meta files
+    pub meta_bytes: u64,
+}
+
+impl FSImage {
+    // Work out what write_datanode_dirs would create under base_path, without
+    // touching the filesystem.
+    pub fn datanode_usage(&self, base_path: &str) -> Vec<DatanodeUsage> {
+        let selected = self.selected_datanodes();
+        let mut usage : Vec<DatanodeUsage> = self.datanode_info.iter().enumerate()
+            .map(|(datanode_idx, dn_info)| DatanodeUsage {
+                path: self.datanode_dir(base_path, datanode_idx as u16),
+                num_storage_dirs: dn_info.volumes.len() as u16,
+                // Each storage directory has a datanode and a block pool
+                // VERSION file, a dfsUsed file and a replicas file.
+                num_files: 4 * (dn_info.volumes.len() as u64),
+                data_bytes: 0,
+                meta_bytes: 0,
+            }).collect();
+        for block in self.disk_blocks() {
+            for replica in block.disk_replicas() {
+                if !selected[replica.datanode as usize] {
+                    continue;
+                }
+                let dn_usage = &mut usage[replica.datanode as usize];
+                dn_usage.num_files = dn_usage.num_files + 2;
+                dn_usage.data_bytes = dn_usage.data_bytes + block.num_bytes;
+                dn_usage.meta_bytes = dn_usage.meta_bytes + block.meta_file_len();
+            }
+        }
+        return usage.into_iter().enumerate()
+            .filter(|&(idx, _)| selected[idx])
+            .map(|(_, dn_usage)| dn_usage).collect();
+    }
+
+    // Get the store of template files for each datanode.  The templates go
+    // in the datanode's directory, so that hardlinks to them survive copying
+    // it elsewhere with rsync -H.
+    fn template_stores(&self, base_path: &str) -> Vec<TemplateStore> {
+        return (0..self.datanode_info.len()).map(|datanode_idx| {
+            TemplateStore::new(self.config.link_mode,
+                               &format!("{}/templates",
+                                        self.datanode_dir(base_path, datanode_idx as u16)))
+        }).collect();
+    }
+
+    // Iterate over every block which has replicas on disk: the blocks of the
+    // files, and the orphans.
+    fn disk_blocks<'a>(&'a self) -> impl Iterator<Item=&'a Block> + 'a {
+        return self.inode_map.values().flat_map(|inode| inode.blocks.iter())
+            .chain(self.orphan_blocks.iter());
+    }
+
+    // Get a flag for each datanode saying whether its directories will be
+    // written.  This is all of them unless output.only_datanodes is set.
+    pub fn selected_datanodes(&self) -> Vec<bool> {
+        let num_datanodes = self.config.num_datanodes as usize;
+        if self.config.only_datanodes.is_empty() {
+            return vec![true; num_datanodes];
+        }
+        let mut selected = vec![false; num_datanodes];
+        for idx in &self.config.only_datanodes {
+            selected[*idx as usize] = true;
+        }
+        return selected;
+    }
+
+    // Write the storage directories of every selected DataNode under
+    // base_path, along with the block and meta files of their replicas.  If
+    // the namespace was appended to an existing image, only the new replicas
+    // are written, and the existing directories are kept.
+    pub fn write_datanode_dirs(&self, base_path: &str) -> Result<(), FsgenError> {
+        return self.write_datanode_dirs_impl(base_path, false);
+    }
+
+    // Finish writing the storage directories of every selected DataNode
+    // under base_path, after an earlier run died part way through.  The
+    // finalized subdirs which their progress journals list as complete are
+    // skipped.  In the others, replicas whose files are missing or have the
+    // wrong length are written again.  See ProgressJournal
+    pub fn resume_datanode_dirs(&self, base_path: &str) -> Result<(), FsgenError> {
+        return self.write_datanode_dirs_impl(base_path, true);
+    }
+
+    fn write_datanode_dirs_impl(&self, base_path: &str, resume: bool)
+                                -> Result<(), FsgenError> {
+        if self.num_base_inodes > 0 {
+            try!(self.check_appendable(base_path));
+        }
+        let selected = self.selected_datanodes();
+        self.progress.start_phase("datanode_dirs", "datanodes",
+                                  selected.iter().filter(|&&s| s).count() as u64);
+        for datanode_idx in 0..self.config.num_datanodes {
+            if selected[datanode_idx as usize] {
+                try!(self.generate_datanode_dir(base_path, datanode_idx));
+                self.progress.add(1, 0);
+            }
+        }
+        self.progress.finish_phase();
+        try!(self.generate_block_files(base_path, resume));
+        try!(self.write_replica_caches(base_path));
+        return Result::Ok(());
+    }
+
+    // Check that the directories of the selected datanodes under base_path
+    // can be resumed: each one either doesn't exist yet, or has a progress
+    // journal written for the same replicas.
+    pub fn check_resumable(&self, base_path: &str) -> Result<(), FsgenError> {
+        let volumes = self.volume_replicas();
+        let selected = self.selected_datanodes();
+        for datanode_idx in 0..self.config.num_datanodes {
+            if !selected[datanode_idx as usize] {
+                continue;
+            }
+            let dn_dir = self.datanode_dir(base_path, datanode_idx);
+            if fs::metadata(&dn_dir).is_err() {
+                continue;
+            }
+            try!(check_journal(&format!("{}/{}", dn_dir, JOURNAL_FILE_NAME),
+                               &self.replica_fingerprint(datanode_idx, &volumes)));
+        }
+        return Result::Ok(());
+    }
+
+    // Check that the new replicas of an appended namespace can be added to
+    // the volumes of the selected datanodes under base_path: each volume
+    // either doesn't exist yet, or belongs to the same cluster, datanode and
+    // block pool as this run.
+    pub fn check_appendable(&self, base_path: &str) -> Result<(), FsgenError> {
+        let selected = self.selected_datanodes();
+        for datanode_idx in 0..self.config.num_datanodes {
+            if !selected[datanode_idx as usize] {
+                continue;
+            }
+            let dn_info = &self.datanode_info[datanode_idx as usize];
+            for storage_idx in 0..dn_info.volumes.len() {
+                let dir = format!("{}/current",
+                                  self.volume_dir(base_path, datanode_idx, storage_idx as u16));
+                let version_path = format!("{}/VERSION", dir);
+                let props = match read_properties(&version_path) {
+                    Ok(props) => props,
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(FsgenError::io(&version_path, e)),
+                };
+                let expected = [ ("clusterID", &self.config.cluster_id),
+                                 ("datanodeUuid", &dn_info.datanode_uuid),
+                                 ("storageID", &dn_info.storage_ids[storage_idx]) ];
+                for &(key, value) in expected.iter() {
+                    let found = props.get(key).map(|v| v.as_str()).unwrap_or("");
+                    if found != value.as_str() {
+                        return Err(FsgenError::InvalidArgument(format!("{} was written for \
+                            a different cluster: its {} is {}, not {}.  Append with the same \
+                            seed and settings as the run which wrote it.", version_path, key,
+                            found, value)));
+                    }
+                }
+                let bp_dir = format!("{}/{}", dir, self.config.block_pool_id);
+                if fs::metadata(&bp_dir).is_err() {
+                    return Err(FsgenError::InvalidArgument(format!("{} has no block pool {}.  \
+                        Append with the same block pool ID as the run which wrote it.",
+                        dir, self.config.block_pool_id)));
+                }
+            }
+        }
+        return Result::Ok(());
+    }
+
+    // Write the block and meta files of every replica on the selected
+    // datanodes.  The replicas are split into tasks, each of which writes
+    // replicas on a single volume, and the worker threads take tasks from a
+    // shared queue.  Every directory is created before any file is written,
+    // so the workers never race to create the same one.
+    //
+    // Each datanode's progress journal records the finalized subdirs as they
+    // are completed.  If resume is set, we pick up where its journal left
+    // off.
+    pub fn generate_block_files(&self, base_path: &str, resume: bool)
+                                -> Result<(), FsgenError> {
+        let dn_layout_version = self.config.dn_layout_version;
+        let finalized_dirs = &self.finalized_dirs(base_path);
+        let templates = &self.template_stores(base_path);
+        let mut volumes = self.volume_replicas();
+        let selected = self.selected_datanodes();
+        let mut journals = vec![];
+        for datanode_idx in 0..self.config.num_datanodes {
+            if !selected[datanode_idx as usize] {
+                journals.push(None);
+                continue;
+            }
+            let path = format!("{}/{}", self.datanode_dir(base_path, datanode_idx),
+                               JOURNAL_FILE_NAME);
+            let fingerprint = self.replica_fingerprint(datanode_idx, &volumes);
+            let journal = if resume && fs::metadata(&path).is_ok() {
+                try!(ProgressJournal::open(&path, &fingerprint))
+            } else {
+                try!(ProgressJournal::create(&path, &fingerprint))
+            };
+            if journal.num_completed() > 0 {
+                println!("** resuming datanode dir {}: {} finalized subdirs are already \
+                         complete.", datanode_idx + 1, journal.num_completed());
+            }
+            journals.push(Some(journal));
+        }
+        let journals = &journals;
+        volumes.retain(|volume| selected[volume.datanode as usize]);
+        for volume in volumes.iter_mut() {
+            let journal = journals[volume.datanode as usize].as_ref().unwrap();
+            let storage_idx = volume.storage;
+            volume.replicas.retain(|&(block, _)| {
+                !journal.is_complete(storage_idx,
+                                     &block_subdir(block.id as u64, dn_layout_version))
+            });
+            for &(block, _) in &volume.replicas {
+                *volume.subdirs_left.entry(block_subdir(block.id as u64, dn_layout_version))
+                    .or_insert(0) += 1;
+            }
+        }
+        volumes.retain(|volume| !volume.replicas.is_empty());
+        // Each replica has two files: the block file and the meta file.
+        let num_files = volumes.iter().fold(0u64, |total, volume| {
+            total + 2 * volume.replicas.len() as u64
+        });
+        self.progress.start_phase("block_files", "files", num_files);
+        let num_threads = self.config.num_threads as usize;
+        try!(run_tasks(&self.progress, num_threads, &volumes, |volume| {
+            let finalized_base = &finalized_dirs[volume.datanode as usize]
+                [volume.storage as usize];
+            for subdir in volume.subdirs_left.keys() {
+                let dir = format!("{}/{}", finalized_base, subdir);
+                try!(fs::create_dir_all(&dir).map_err(|e| FsgenError::io(&dir, e)));
+            }
+            return Result::Ok(());
+        }));
+        let subdirs_left : Vec<HashMap<&str, AtomicUsize>> = volumes.iter()
+            .map(|volume| volume.subdirs_left.iter()
+                 .map(|(subdir, count)| (subdir.as_str(), AtomicUsize::new(*count)))
+                 .collect())
+            .collect();
+        let tasks = replica_tasks(&volumes);
+        let replicas_written = AtomicUsize::new(0);
+        let replicas_kept = AtomicUsize::new(0);
+        try!(run_tasks(&self.progress, num_threads, &tasks, |task| {
+            let volume = &volumes[task.volume_idx];
+            let finalized_base = &finalized_dirs[volume.datanode as usize]
+                [volume.storage as usize];
+            let datanode_templates = &templates[volume.datanode as usize];
+            for &(block, ref replica) in task.replicas {
+                if resume && try!(block.replica_files_complete(finalized_base,
+                                                               dn_layout_version, replica)) {
+                    replicas_kept.fetch_add(1, Ordering::Relaxed);
+                    self.progress.add(2, 0);
+                } else {
+                    try!(block.generate_meta_and_block_file(finalized_base, dn_layout_version,
+                                                            replica, datanode_templates));
+                    replicas_written.fetch_add(1, Ordering::Relaxed);
+                    self.progress.add(2, block.num_bytes + block.meta_file_len());
+                }
+                let subdir = block_subdir(block.id as u64, dn_layout_version);
+                if subdirs_left[task.volume_idx][subdir.as_str()]
+                        .fetch_sub(1, Ordering::Relaxed) == 1 {
+                    let journal = journals[volume.datanode as usize].as_ref().unwrap();
+                    try!(journal.record(volume.storage, &subdir));
+                }
+            }
+            return Result::Ok(());
+        }));
+        self.progress.finish_phase();
+        println!("** generate_block_files: wrote {} replicas.",
+                 replicas_written.load(Ordering::Relaxed));
+        if resume {
+            println!("** generate_block_files: kept {} replicas which were already on disk.",
+                     replicas_kept.load(Ordering::Relaxed));
+        }
+        return Result::Ok(());
+    }
+
+    // Get the replicas on every volume, sorted by block ID.
+    fn volume_replicas<'a>(&'a self) -> Vec<VolumeReplicas<'a>> {
+        let mut volumes : Vec<VolumeReplicas> = vec![];
+        for (datanode_idx, dn_info) in self.datanode_info.iter().enumerate() {
+            for storage_idx in 0..dn_info.volumes.len() {
+                volumes.push(VolumeReplicas {
+                    datanode: datanode_idx as u16,
+                    storage: storage_idx as u16,
+                    replicas: vec![],
+                    subdirs_left: HashMap::new(),
+                });
+            }
+        }
+        let first_volume : Vec<usize> = self.datanode_info.iter()
+            .scan(0, |next, dn_info| {
+                let first = *next;
+                *next += dn_info.volumes.len();
+                Some(first)
+            }).collect();
+        for block in self.disk_blocks() {
+            for replica in block.disk_replicas() {
+                volumes[first_volume[replica.datanode as usize] +
+                    replica.storage as usize].replicas.push((block, replica));
+            }
+        }
+        for volume in volumes.iter_mut() {
+            volume.replicas.sort_by_key(|&(block, _)| block.id);
+        }
+        return volumes;
+    }
+
+    // Get a fingerprint of the replicas which belong on a datanode, and
+    // where their files go, for its progress journal.
+    fn replica_fingerprint(&self, datanode_idx: u16, volumes: &[VolumeReplicas]) -> String {
+        let mut digest = md5::Context::new();
+        digest.consume(format!("{} {}\n", self.config.block_pool_id,
+                               self.config.dn_layout_version));
+        let dn_info = &self.datanode_info[datanode_idx as usize];
+        for volume in volumes.iter().filter(|volume| volume.datanode == datanode_idx) {
+            digest.consume(format!("volume {}\n",
+                                   dn_info.volumes[volume.storage as usize].name));
+            for &(block, ref replica) in &volume.replicas {
+                digest.consume(format!("{} {} {} {}\n", block.id, block.num_bytes,
+                                       replica.genstamp, replica.corrupt));
+            }
+        }
+        return format!("{:x}", digest.compute());
+    }
+
+    // Get the directory which holds the volumes of a datanode.
+    pub fn datanode_dir(&self, base_path: &str, datanode_idx: u16) -> String {
+        return format!("{}/datanode{:>02}", base_path, datanode_idx + 1);
+    }
+
+    // Get the directory of a volume of a datanode.
+    pub fn volume_dir(&self, base_path: &str, datanode_idx: u16, storage_idx: u16) -> String {
+        let volume = &self.datanode_info[datanode_idx as usize].volumes[storage_idx as usize];
+        return format!("{}/{}", self.datanode_dir(base_path, datanode_idx), volume.name);
+    }
+
+    // Get the current directory of the block pool in a volume of a datanode.
+    fn blockpool_current_dir(&self, base_path: &str, datanode_idx: u16,
+                             storage_idx: u16) -> String {
+        return format!("{}/current/{}/current",
+                       self.volume_dir(base_path, datanode_idx, storage_idx),
+                       self.config.block_pool_id);
+    }
+
+    // Get the finalized directory of every volume, indexed by datanode and
+    // then by volume.
+    fn finalized_dirs(&self, base_path: &str) -> Vec<Vec<String>> {
+        let mut dirs = vec![];
+        for (datanode_idx, dn_info) in self.datanode_info.iter().enumerate() {
+            dirs.push((0..dn_info.volumes.len()).map(|storage_idx| {
+                format!("{}/finalized",
+                        self.blockpool_current_dir(base_path, datanode_idx as u16,
+                                                   storage_idx as u16))
+            }).collect());
+        }
+        return dirs;
+    }
+
+    // Write the dfsUsed and replicas files of every volume of the selected
+    // datanodes.  A DataNode saves these when it shuts down cleanly, and
+    // reads them on startup instead of scanning its volumes.  See
+    // BlockPoolSlice#saveDfsUsed and BlockPoolSlice#saveReplicas
+    //
+    // When appending, the caches must also cover the replicas which were
+    // already on a volume, so the new replicas are added to its existing
+    // caches.  If it has none, for example because a DataNode has used them
+    // up, we leave them out and the DataNode scans the volume.
+    fn write_replica_caches(&self, base_path: &str) -> Result<(), FsgenError> {
+        let selected = self.selected_datanodes();
+        let mut replicas : Vec<Vec<Vec<(&Block, u32)>>> = self.datanode_info.iter()
+            .map(|dn_info| vec![vec![]; dn_info.volumes.len()]).collect();
+        for block in self.disk_blocks() {
+            for replica in block.disk_replicas() {
+                if selected[replica.datanode as usize] {
+                    replicas[replica.datanode as usize][replica.storage as usize]
+                        .push((block, replica.genstamp));
+                }
+            }
+        }
+        let now_ms = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() * 1000 + d.subsec_millis() as u64,
+            Err(_) => 0,
+        };
+        for (datanode_idx, volumes) in replicas.iter_mut().enumerate() {
+            if !selected[datanode_idx] {
+                continue;
+            }
+            for (storage_idx, blocks) in volumes.iter_mut().enumerate() {
+                blocks.sort_by_key(|&(block, _)| block.id);
+                let cdir = self.blockpool_current_dir(base_path, datanode_idx as u16,
+                                                      storage_idx as u16);
+                let dfs_used = blocks.iter().fold(0u64, |total, &(block, _)| {
+                    total + block.num_bytes + block.meta_file_len()
+                });
+                let mut dfs_used = dfs_used;
+                let mut num_replicas = blocks.len() as u64;
+                let mut replica_list = replica_list(blocks);
+                let dfs_used_path = format!("{}/dfsUsed", cdir);
+                let replicas_path = format!("{}/replicas", cdir);
+                if self.num_base_inodes > 0 {
+                    let old_dfs_used = try!(read_dfs_used_file(&dfs_used_path)
+                         .map_err(|e| FsgenError::io(&dfs_used_path, e)));
+                    let old_replicas = try!(read_replicas_file(&replicas_path)
+                         .map_err(|e| FsgenError::io(&replicas_path, e)));
+                    match (old_dfs_used, old_replicas) {
+                        (Some(_), Some((_, old_list))) => {
+                            // A run which died after writing the caches may
+                            // already have added the new replicas to them,
+                            // so only the base image's replicas are kept.
+                            let (old_num_replicas, old_list, old_dfs_used) =
+                                try!(base_replicas(&old_list, self.first_new_block_id as u64)
+                                     .map_err(|e| FsgenError::io(&replicas_path, e)));
+                            dfs_used = dfs_used + old_dfs_used;
+                            num_replicas = num_replicas + old_num_replicas;
+                            replica_list = [ old_list, replica_list ].concat();
+                        },
+                        _ => {
+                            for path in &[ &dfs_used_path, &replicas_path ] {
+                                try!(remove_if_exists(path)
+                                     .map_err(|e| FsgenError::io(path, e)));
+                            }
+                            continue;
+                        },
+                    }
+                }
+                try!(write_dfs_used_file(&dfs_used_path, dfs_used, now_ms)
+                     .map_err(|e| FsgenError::io(&dfs_used_path, e)));
+                try!(write_replicas_file(&replicas_path, num_replicas, &replica_list)
+                     .map_err(|e| FsgenError::io(&replicas_path, e)));
+            }
+        }
+        return Result::Ok(());
+    }
+
+    // The datanode layout looks like this:
+    //
+    // data
+    // data/current
+    // data/current/VERSION
+    // data/current/BP-113955101-127.0.0.1-1455743472614
+    // data/current/BP-113955101-127.0.0.1-1455743472614/tmp [empty dir]
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/VERSION
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/dfsUsed
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/replicas
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/rbw [empty dir]
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741825
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741826
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741828_1004.meta
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741828
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741825_1001.meta
+    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741826_1002.meta
+    //
+    // Note that block files must be placed based on their IDs. 
+    fn generate_datanode_dir(&self, base_path: &str, datanode_idx: u16)
+            -> Result<(), FsgenError> {
+        println!("** generating datanode dir {} in {}...",
+                 datanode_idx + 1, base_path);
+        let num_volumes = self.datanode_info[datanode_idx as usize].volumes.len() as u16;
+        for storage_idx in 0..num_volumes {
+            let dir = format!("{}/current",
+                 self.volume_dir(base_path, datanode_idx, storage_idx));
+            let bp_dir = format!("{}/{}", dir, self.config.block_pool_id);
+            try!(fs::create_dir_all(&bp_dir).map_err(|e| FsgenError::io(&bp_dir, e)));
+            let version_path = format!("{}/VERSION", dir);
+            try!(self.write_datanode_version_file(&version_path, datanode_idx, storage_idx)
+                 .map_err(|e| FsgenError::io(&version_path, e)));
+
+            let cdir = format!("{}/current", &bp_dir);
+            for dir in &[format!("{}/tmp", &bp_dir), cdir.clone(),
+                         format!("{}/rbw", cdir), format!("{}/finalized", cdir)] {
+                try!(fs::create_dir_all(dir).map_err(|e| FsgenError::io(dir, e)));
+            }
+            let bp_version_path = format!("{}/VERSION", cdir);
+            try!(self.write_blockpool_version_file(&bp_version_path)
+                 .map_err(|e| FsgenError::io(&bp_version_path, e)));
+        }
+        println!("** finished generating datanode dir {} in {}...",
+                 datanode_idx + 1, base_path);
+        return Result::Ok(());
+    }
+
+    // Write the VERSION file which identifies the version of this datanode storage directory.
+    fn write_datanode_version_file(&self, path: &str,
+                        datanode_idx: u16, storage_idx: u16)
+                        -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        let dn_info = self.datanode_info.get(datanode_idx as usize).unwrap();
+        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
+        try!(write!(w, "storageID={}\n",
+                    dn_info.storage_ids.get(storage_idx as usize).unwrap()));
+        try!(write!(w, "clusterID={}\n", self.config.cluster_id));
+        try!(write!(w, "cTime={}\n", self.config.cluster_ctime));
+        try!(write!(w, "datanodeUuid={}\n", dn_info.datanode_uuid));
+        try!(write!(w, "storageType=DATA_NODE\n"));
+        try!(write!(w, "layoutVersion={}\n", self.config.dn_layout_version));
+        return Result::Ok(());
+    }
+
+    fn write_blockpool_version_file(&self, path: &str)
+                        -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
+        try!(write!(w, "namespaceID={}\n", self.config.namespace_id));
+        try!(write!(w, "cTime={}\n", self.config.cluster_ctime));
+        try!(write!(w, "blockpoolID={}\n", self.config.block_pool_id));
+        try!(write!(w, "layoutVersion={}\n", self.config.dn_layout_version));
+        return Result::Ok(());
+    }
+}
+
+impl Block {
+    // Get the length of the meta file for this block: a 7 byte header, then
+    // a 4 byte checksum for each chunk of data.
+    pub fn meta_file_len(&self) -> u64 {
+        return meta_file_len(self.num_bytes);
+    }
+
+    // Get the paths of the block and meta files of a replica of this block.
+    fn replica_file_paths(&self, finalized_base: &str, dn_layout_version: i32,
+                          replica: &DiskReplica) -> (String, String) {
+        let subdir = format!("{}/{}", finalized_base,
+            block_subdir(self.id as u64, dn_layout_version));
+        return (format!("{}/blk_{}", &subdir, self.id),
+                format!("{}/blk_{}_{}.meta", &subdir, self.id, replica.genstamp));
+    }
+
+    // Check whether the block and meta files of a replica of this block were
+    // written completely by an earlier run.  If they weren't, whatever is
+    // there is removed, so that they can be written again.
+    fn replica_files_complete(&self, finalized_base: &str, dn_layout_version: i32,
+                              replica: &DiskReplica) -> Result<bool, FsgenError> {
+        let (data_path, meta_path) = self.replica_file_paths(finalized_base,
+                                                             dn_layout_version, replica);
+        let data_len = fs::metadata(&data_path).map(|m| m.len()).ok();
+        let meta_len = fs::metadata(&meta_path).map(|m| m.len()).ok();
+        if data_len == Some(self.num_bytes) && meta_len == Some(self.meta_file_len()) {
+            return Result::Ok(true);
+        }
+        for path in &[data_path, meta_path] {
+            match fs::remove_file(path) {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(FsgenError::io(path, e)),
+            }
+        }
+        return Result::Ok(false);
+    }
+
+    // Write the block and meta files of a replica of this block.  The
+    // subdirectory of finalized_base which holds them must already exist.
+    pub fn generate_meta_and_block_file(&self, finalized_base: &str, dn_layout_version: i32,
+                            replica: &DiskReplica, templates: &TemplateStore)
+                            -> Result<(), FsgenError> {
+        let (data_path, meta_path) = self.replica_file_paths(finalized_base,
+                                                             dn_layout_version, replica);
+        // Write the block data file.  The data is all zeroes, so we can
+        // leave the file sparse.
+        try!(templates.create_file(&data_path, TemplateKind::BlockData,
+                                   &format!("data_{}", self.num_bytes),
+                                   |file| file.set_len(self.num_bytes))
+             .map_err(|e| FsgenError::io(&data_path, e)));
+        let meta_name = if replica.corrupt {
+            format!("meta_{}_corrupt", self.num_bytes)
+        } else {
+            format!("meta_{}", self.num_bytes)
+        };
+        try!(templates.create_file(&meta_path, TemplateKind::BlockMeta, &meta_name,
+                                   |file| self.write_meta_file(file, replica.corrupt))
+             .map_err(|e| FsgenError::io(&meta_path, e)));
+        return Result::Ok(());
+    }
+
+    // Write the block meta file: a header followed by a CRC32C checksum for
+    // each chunk of data.  If corrupt is set, the first checksum is wrong.
+    fn write_meta_file(&self, file: &File, corrupt: bool) -> Result<(), std::io::Error> {
+        let arr = [ 0x00u8, 0x01u8, 0x02u8, 0x00u8,
+            0x00u8, 0x02u8, 0x00u8 ];
+        let mut w = BufWriter::new(file);
+        try!(w.write(&arr));
+        let chunk_crc = crc32c(&vec![0u8; BYTES_PER_CHECKSUM as usize]);
+        for chunk in 0..self.num_bytes.div_ceil(BYTES_PER_CHECKSUM) {
+            let len = cmp::min(BYTES_PER_CHECKSUM,
+                               self.num_bytes - chunk * BYTES_PER_CHECKSUM);
+            let mut crc = if len == BYTES_PER_CHECKSUM {
+                chunk_crc
+            } else {
+                crc32c(&vec![0u8; len as usize])
+            };
+            if corrupt && chunk == 0 {
+                crc = !crc;
+            }
+            try!(w.write_u32::<BigEndian>(crc));
+        }
+        try!(w.flush());
+        return Result::Ok(());
+    }
+}
+
+// Write a dfsUsed file: the bytes used by the block pool on a volume, and
+// the time in milliseconds when that was measured.
+fn write_dfs_used_file(path: &str, dfs_used: u64, now_ms: u64)
+                       -> Result<(), std::io::Error> {
+    let file = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    let mut w = BufWriter::new(&file);
+    try!(write!(w, "{} {}", dfs_used, now_ms));
+    return Result::Ok(());
+}
+
+// Read the bytes used from a dfsUsed file, or None if there is no such file.
+fn read_dfs_used_file(path: &str) -> Result<Option<u64>, std::io::Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Result::Ok(None),
+        Err(e) => return Err(e),
+    };
+    match text.split_whitespace().next().and_then(|val| val.parse::<u64>().ok()) {
+        Some(dfs_used) => return Result::Ok(Some(dfs_used)),
+        None => return Err(Error::new(ErrorKind::InvalidData, "not a dfsUsed file")),
+    }
+}
+
+// Get the list of replicas in a replicas file, in the format of
+// BlockListAsLongs: the ID, length, genstamp and state of each replica.
+// blocks holds each block with the genstamp of its replica.
+fn replica_list(blocks: &[(&Block, u32)]) -> Vec<u8> {
+    let mut buf : Vec<u8> = vec![];
+    for &(block, genstamp) in blocks {
+        // Block IDs are zig-zag encoded.
+        let id = block.id as u64;
+        write_varint(&mut buf, id << 1);
+        write_varint(&mut buf, block.num_bytes);
+        write_varint(&mut buf, genstamp as u64);
+        // ReplicaState.FINALIZED
+        write_varint(&mut buf, 0);
+    }
+    return buf;
+}
+
+// Write a replicas file, which lists the finalized replicas on a volume in
+// the format of BlockListAsLongs#writeTo: the number of replicas, then a
+// buffer holding the list made by replica_list.
+fn write_replicas_file(path: &str, num_replicas: u64,
+                       replica_list: &[u8]) -> Result<(), std::io::Error> {
+    let mut out : Vec<u8> = vec![];
+    write_key(&mut out, 1, 0);
+    write_varint(&mut out, num_replicas);
+    write_key(&mut out, 2, 2);
+    write_varint(&mut out, replica_list.len() as u64);
+    out.extend_from_slice(replica_list);
+    let mut file = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    try!(file.write_all(&out));
+    return Result::Ok(());
+}
+
+// Read the number of replicas and the replica list from a replicas file, or
+// None if there is no such file.
+fn read_replicas_file(path: &str) -> Result<Option<(u64, Vec<u8>)>, std::io::Error> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Result::Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut num_replicas = 0;
+    let mut replica_list = vec![];
+    let mut fields = ProtoReader::new(&buf);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        match num {
+            1 => num_replicas = try!(val.as_u64()),
+            2 => replica_list = try!(val.as_bytes()).to_vec(),
+            _ => (),
+        }
+    }
+    return Result::Ok(Some((num_replicas, replica_list)));
+}
+
+// Get the replicas in a replica list made by replica_list whose block IDs are
+// below first_new_block_id: their number, their list, and the bytes their
+// block and meta files take.
+fn base_replicas(list: &[u8], first_new_block_id: u64)
+                 -> Result<(u64, Vec<u8>, u64), std::io::Error> {
+    let mut r = list;
+    let mut num_replicas = 0;
+    let mut kept : Vec<u8> = vec![];
+    let mut dfs_used = 0;
+    while let Some(id) = try!(read_varint_from(&mut r)) {
+        let mut fields = [id, 0, 0, 0];
+        for field in fields.iter_mut().skip(1) {
+            *field = match try!(read_varint_from(&mut r)) {
+                Some(val) => val,
+                None => return Err(Error::new(ErrorKind::InvalidData,
+                                              "truncated replica list")),
+            };
+        }
+        if (fields[0] >> 1) >= first_new_block_id {
+            continue;
+        }
+        for field in &fields {
+            write_varint(&mut kept, *field);
+        }
+        num_replicas = num_replicas + 1;
+        dfs_used = dfs_used + fields[1] + meta_file_len(fields[1]);
+    }
+    return Result::Ok((num_replicas, kept, dfs_used));
+}
+
+// The length of the meta file of a block of num_bytes bytes: a 7 byte
+// header, and a CRC32C for each chunk.
+fn meta_file_len(num_bytes: u64) -> u64 {
+    let num_chunks = num_bytes.div_ceil(BYTES_PER_CHECKSUM);
+    return 7 + 4 * num_chunks;
+}
+
+fn remove_if_exists(path: &str) -> Result<(), std::io::Error> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Result::Ok(()),
+        result => return result,
+    }
+}
+
+// Compute the CRC32C (Castagnoli) checksum of some data, the way the DataNode
+// does for block meta files.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc : u32 = 0xffffffff;
+    for b in data {
+        crc = crc ^ (*b as u32);
+        for _ in 0..8 {
+            if (crc & 1) != 0 {
+                crc = (crc >> 1) ^ 0x82f63b78;
+            } else {
+                crc = crc >> 1;
+            }
+        }
+    }
+    return !crc;
+}
+
+// The replicas on one volume of a datanode, with the block of each.
+struct VolumeReplicas<'a> {
+    datanode: u16,
+    storage: u16,
+    replicas: Vec<(&'a Block, DiskReplica)>,
+
+    // The number of replicas in each finalized subdir which are left to
+    // write
+    subdirs_left: HashMap<String, usize>,
+}
+
+// Some of the replicas on one volume of a datanode.
+struct ReplicaTask<'a, 'b> where 'a: 'b {
+    // The index of the volume in the list given to replica_tasks
+    volume_idx: usize,
+
+    replicas: &'b [(&'a Block, DiskReplica)],
+}
+
+// The most replicas which one task writes.
+const REPLICAS_PER_TASK : usize = 1000;
+
+// Split the replicas of each volume into tasks.  The tasks take turns
+// between the volumes, so that the worker threads spread their writes over
+// as many volumes as they can.
+fn replica_tasks<'a, 'b>(volumes: &'b [VolumeReplicas<'a>]) -> Vec<ReplicaTask<'a, 'b>> {
+    let mut chunks : Vec<_> = volumes.iter()
+        .map(|volume| volume.replicas.chunks(REPLICAS_PER_TASK)).collect();
+    let mut tasks = vec![];
+    loop {
+        let num_tasks = tasks.len();
+        for (volume_idx, chunk_iter) in chunks.iter_mut().enumerate() {
+            if let Some(replicas) = chunk_iter.next() {
+                tasks.push(ReplicaTask {
+                    volume_idx: volume_idx,
+                    replicas: replicas,
+                });
+            }
+        }
+        if tasks.len() == num_tasks {
+            return tasks;
+        }
+    }
+}
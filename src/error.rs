@@ -0,0 +1,83 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The errors which stop fsgen, and the exit status of each kind.
+
+use config::ConfigError;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum FsgenError {
+    // A command-line argument which can't be parsed, or which asks for
+    // something fsgen won't do
+    InvalidArgument(String),
+
+    // An I/O error, with the path of the file or directory involved
+    Io(String, io::Error),
+
+    // A configuration which describes a cluster fsgen can't generate
+    Config(ConfigError),
+
+    // A worker thread which panicked
+    Worker(String),
+}
+
+impl FsgenError {
+    pub fn io(path: &str, err: io::Error) -> FsgenError {
+        return FsgenError::Io(path.to_owned(), err);
+    }
+
+    // Get the exit status fsgen uses for this error.  1 is left for fsgen
+    // verify finding problems.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            FsgenError::InvalidArgument(_) => return 2,
+            FsgenError::Config(_) => return 3,
+            FsgenError::Io(_, _) => return 4,
+            FsgenError::Worker(_) => return 5,
+        }
+    }
+}
+
+impl fmt::Display for FsgenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FsgenError::InvalidArgument(ref message) => write!(f, "{}", message),
+            FsgenError::Io(ref path, ref err) => write!(f, "{}: {}", path, err),
+            FsgenError::Config(ref err) => write!(f, "{}", err),
+            FsgenError::Worker(ref message) => write!(f, "a worker thread failed: {}", message),
+        }
+    }
+}
+
+impl error::Error for FsgenError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            FsgenError::Io(_, ref err) => return Some(err),
+            _ => return None,
+        }
+    }
+}
+
+impl From<ConfigError> for FsgenError {
+    fn from(err: ConfigError) -> FsgenError {
+        return FsgenError::Config(err);
+    }
+}
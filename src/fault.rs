@@ -0,0 +1,427 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use namespace::Block;
+use namespace::FSImage;
+use rand::ChaChaRng;
+use rand::Rng;
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+
+// Selects the random stream used to choose which blocks get faults.
+const FAULT_STREAM : u64 = 0x464c5453;
+
+// How many blocks should get a kind of fault: a number of blocks, or a
+// fraction of all the blocks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FaultAmount {
+    Count(u64),
+    Fraction(f64),
+}
+
+impl FaultAmount {
+    // Get the number of blocks, out of num_blocks.
+    pub fn num_blocks(&self, num_blocks: u64) -> u64 {
+        match *self {
+            FaultAmount::Count(count) => return count,
+            FaultAmount::Fraction(fraction) =>
+                return ((num_blocks as f64) * fraction).round() as u64,
+        }
+    }
+}
+
+// A way in which the replicas of a block on disk can differ from the fsimage.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FaultKind {
+    // No replicas at all
+    MissingBlock,
+
+    // One replica fewer than the replication factor
+    MissingReplica,
+
+    // One replica more than the replication factor, on another datanode
+    ExtraReplica,
+
+    // A replica whose meta file has an older genstamp than the fsimage
+    StaleGenstamp,
+
+    // A replica whose meta file has a bad checksum for the first chunk
+    CorruptReplica,
+
+    // An extra replica, on another datanode, with the genstamp the block had
+    // before it was appended to
+    StaleReplica,
+
+    // A replica of a block which the fsimage doesn't have, left over from
+    // after it was deleted
+    OrphanReplica,
+}
+
+// Every kind of fault, in the order they are injected.
+pub static FAULT_KINDS : &[FaultKind] = &[
+    FaultKind::MissingBlock,
+    FaultKind::MissingReplica,
+    FaultKind::ExtraReplica,
+    FaultKind::StaleGenstamp,
+    FaultKind::CorruptReplica,
+    FaultKind::StaleReplica,
+    FaultKind::OrphanReplica,
+];
+
+impl FaultKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FaultKind::MissingBlock => return "missing_blocks",
+            FaultKind::MissingReplica => return "missing_replicas",
+            FaultKind::ExtraReplica => return "extra_replicas",
+            FaultKind::StaleGenstamp => return "stale_genstamps",
+            FaultKind::CorruptReplica => return "corrupt_replicas",
+            FaultKind::StaleReplica => return "stale_replicas",
+            FaultKind::OrphanReplica => return "orphan_replicas",
+        }
+    }
+}
+
+// A fault injected into the replicas of one block on disk.  The fsimage
+// itself is unchanged.
+#[derive(Clone)]
+pub struct BlockFault {
+    pub kind: FaultKind,
+
+    // The datanode and volume of the replica which is missing, extra, stale
+    // or corrupt.  Not used for missing blocks.
+    pub datanode: u16,
+    pub storage: u16,
+
+    // The genstamp of that replica on disk
+    pub genstamp: u32,
+}
+
+// A replica of a block as it is written to disk.
+pub struct DiskReplica {
+    pub datanode: u16,
+
+    // The index of the volume on the datanode
+    pub storage: u16,
+
+    // The genstamp in the name of the meta file
+    pub genstamp: u32,
+
+    // True if the first checksum in the meta file is wrong
+    pub corrupt: bool,
+}
+
+impl Block {
+    // Get the replicas of this block which are written to disk, taking its
+    // fault into account.
+    pub fn disk_replicas(&self) -> Vec<DiskReplica> {
+        let mut replicas : Vec<DiskReplica> = self.datanodes.iter().zip(self.storages.iter())
+            .map(|(datanode, storage)| DiskReplica {
+                datanode: *datanode,
+                storage: *storage,
+                genstamp: self.genstamp,
+                corrupt: false,
+            }).collect();
+        let fault = match self.fault {
+            None => return replicas,
+            Some(ref fault) => fault,
+        };
+        match fault.kind {
+            FaultKind::MissingBlock => replicas.clear(),
+            FaultKind::MissingReplica => replicas.retain(|r| r.datanode != fault.datanode),
+            FaultKind::ExtraReplica | FaultKind::StaleReplica | FaultKind::OrphanReplica =>
+                replicas.push(DiskReplica {
+                    datanode: fault.datanode,
+                    storage: fault.storage,
+                    genstamp: fault.genstamp,
+                    corrupt: false,
+                }),
+            FaultKind::StaleGenstamp => {
+                for replica in replicas.iter_mut().filter(|r| r.datanode == fault.datanode) {
+                    replica.genstamp = fault.genstamp;
+                }
+            },
+            FaultKind::CorruptReplica => {
+                for replica in replicas.iter_mut().filter(|r| r.datanode == fault.datanode) {
+                    replica.corrupt = true;
+                }
+            },
+        }
+        return replicas;
+    }
+}
+
+impl FSImage {
+    // Choose the blocks which get each kind of fault configured in the
+    // [faults] section.  Each block gets at most one fault.  The choices come
+    // from their own random stream, so injecting faults doesn't change the
+    // namespace generated from a seed, apart from the genstamps of blocks
+    // with stale replicas.
+    //
+    // Orphan replicas belong to blocks of their own.  Their IDs and genstamps
+    // are allocated after those of the namespace, as if the blocks had been
+    // created and then deleted, so the NameNode will never reuse them.
+    pub(crate) fn inject_faults(&mut self) {
+        let mut rng = ChaChaRng::new_unseeded();
+        rng.set_counter(self.config.seed, self.config.seed ^ FAULT_STREAM);
+        let mut blocks : Vec<(u32, u32, usize)> = vec![];
+        // The replicas of blocks loaded from an image we are appending to are
+        // already on disk, so only new blocks get faults.
+        for inode in self.inode_map.values() {
+            for (idx, block) in inode.blocks.iter().enumerate() {
+                if block.id >= self.first_new_block_id {
+                    blocks.push((block.id, inode.id, idx));
+                }
+            }
+        }
+        blocks.sort();
+        let num_blocks = blocks.len() as u64;
+        for kind in FAULT_KINDS {
+            let wanted = self.config.fault_amount(*kind).num_blocks(num_blocks);
+            if wanted == 0 {
+                continue;
+            }
+            if *kind == FaultKind::OrphanReplica {
+                self.add_orphan_replicas(&mut rng, wanted);
+                continue;
+            }
+            let mut candidates : Vec<(u32, usize)> = blocks.iter()
+                .filter(|&&(_, inode_id, idx)| {
+                    let block = &self.inode_map[&inode_id].blocks[idx];
+                    return block.fault.is_none() && self.can_inject(*kind, block);
+                })
+                .map(|&(_, inode_id, idx)| (inode_id, idx)).collect();
+            let mut injected = 0;
+            while injected < wanted && !candidates.is_empty() {
+                let pick = (rng.next_u64() % (candidates.len() as u64)) as usize;
+                let (inode_id, idx) = candidates.swap_remove(pick);
+                if let Some(fault) = self.choose_fault(&mut rng, *kind, inode_id, idx) {
+                    let genstamp = self.next_genstamp;
+                    let block = &mut self.inode_map.get_mut(&inode_id).unwrap().blocks[idx];
+                    block.fault = Some(fault);
+                    if *kind == FaultKind::StaleReplica {
+                        // The append gave the block a new genstamp.
+                        block.genstamp = genstamp;
+                        self.next_genstamp = self.next_genstamp + 1;
+                    }
+                    injected = injected + 1;
+                }
+            }
+            if injected < wanted {
+                println!("** WARNING: asked for {} {}, but only {} blocks could have them.",
+                         wanted, kind.name(), injected);
+            }
+        }
+    }
+
+    fn can_inject(&self, kind: FaultKind, block: &Block) -> bool {
+        match kind {
+            FaultKind::MissingBlock => return !block.datanodes.is_empty(),
+            // With only one replica, this would be a missing block.
+            FaultKind::MissingReplica => return block.datanodes.len() >= 2,
+            FaultKind::ExtraReplica | FaultKind::StaleReplica =>
+                return block.datanodes.len() < self.datanode_info.len(),
+            FaultKind::StaleGenstamp => return !block.datanodes.is_empty(),
+            // Empty blocks have no checksums to corrupt.
+            FaultKind::CorruptReplica =>
+                return !block.datanodes.is_empty() && block.num_bytes > 0,
+            FaultKind::OrphanReplica => return false,
+        }
+    }
+
+    // Choose the replica affected by a fault.  Returns None if an extra or
+    // stale replica doesn't fit on any other datanode.
+    fn choose_fault(&mut self, rng: &mut Rng, kind: FaultKind, inode_id: u32,
+                    idx: usize) -> Option<BlockFault> {
+        let (datanodes, storages, genstamp, replica_len) = {
+            let block = &self.inode_map[&inode_id].blocks[idx];
+            (block.datanodes.clone(), block.storages.clone(), block.genstamp,
+             block.num_bytes + block.meta_file_len())
+        };
+        match kind {
+            FaultKind::MissingBlock =>
+                return Some(BlockFault { kind: kind, datanode: 0, storage: 0, genstamp: 0 }),
+            FaultKind::ExtraReplica | FaultKind::StaleReplica => {
+                let (datanode, storage) =
+                    self.choose_other_volume(rng, &datanodes, replica_len)?;
+                return Some(BlockFault {
+                    kind: kind,
+                    datanode: datanode,
+                    storage: storage,
+                    genstamp: genstamp,
+                });
+            },
+            _ => {
+                let replica = (rng.next_u32() as usize) % datanodes.len();
+                return Some(BlockFault {
+                    kind: kind,
+                    datanode: datanodes[replica],
+                    storage: storages[replica],
+                    genstamp: if kind == FaultKind::StaleGenstamp { genstamp - 1 } else { genstamp },
+                });
+            },
+        }
+    }
+
+    // Choose a datanode which isn't in exclude, and a volume on it with room
+    // for len more bytes.  Returns None if there is no room anywhere.
+    fn choose_other_volume(&mut self, rng: &mut Rng, exclude: &[u16],
+                           len: u64) -> Option<(u16, u16)> {
+        let mut others : Vec<u16> = (0..(self.datanode_info.len() as u16))
+            .filter(|datanode| !exclude.contains(datanode)).collect();
+        while !others.is_empty() {
+            let pick = (rng.next_u32() as usize) % others.len();
+            let datanode = others.swap_remove(pick);
+            if let Some(storage) = self.volume_choosers[datanode as usize].choose(rng, len) {
+                return Some((datanode, storage));
+            }
+        }
+        return None;
+    }
+
+    // Add blocks which the fsimage doesn't have, each with one replica on a
+    // random datanode.  Their lengths follow the configured file sizes.
+    fn add_orphan_replicas(&mut self, rng: &mut Rng, wanted: u64) {
+        let range = self.config.max_file_size - self.config.min_file_size;
+        for injected in 0..wanted {
+            let mut block = Block {
+                id: self.next_block_id,
+                genstamp: self.next_genstamp,
+                num_bytes: 0,
+                datanodes: vec![],
+                storages: vec![],
+                fault: None,
+            };
+            let mut num_bytes = self.config.min_file_size;
+            if range > 0 {
+                num_bytes = num_bytes + (rng.next_u64() % range.saturating_add(1));
+            }
+            block.num_bytes = cmp::min(num_bytes, self.config.preferred_block_size);
+            let replica_len = block.num_bytes + block.meta_file_len();
+            let (datanode, storage) = match self.choose_other_volume(rng, &[], replica_len) {
+                Some(volume) => volume,
+                None => {
+                    println!("** WARNING: asked for {} {}, but only {} fit on the datanodes.",
+                             wanted, FaultKind::OrphanReplica.name(), injected);
+                    return;
+                },
+            };
+            block.fault = Some(BlockFault {
+                kind: FaultKind::OrphanReplica,
+                datanode: datanode,
+                storage: storage,
+                genstamp: block.genstamp,
+            });
+            self.orphan_blocks.push(block);
+            self.next_block_id = self.next_block_id + 1;
+            self.next_genstamp = self.next_genstamp + 1;
+        }
+    }
+
+    // Get the blocks which have faults, including the orphans, in block ID
+    // order.  Each comes with the ID of its file, if it has one.
+    fn faulty_blocks(&self) -> Vec<(&Block, Option<u32>)> {
+        let mut faults : Vec<(&Block, Option<u32>)> = vec![];
+        for inode in self.inode_map.values() {
+            for block in &inode.blocks {
+                if block.fault.is_some() {
+                    faults.push((block, Some(inode.id)));
+                }
+            }
+        }
+        for block in &self.orphan_blocks {
+            faults.push((block, None));
+        }
+        faults.sort_by_key(|&(block, _)| block.id);
+        return faults;
+    }
+
+    // Count the blocks with each kind of fault, in the order of FAULT_KINDS.
+    pub fn fault_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0u64; FAULT_KINDS.len()];
+        for (block, _) in self.faulty_blocks() {
+            let kind = block.fault.as_ref().unwrap().kind;
+            let kind_idx = FAULT_KINDS.iter().position(|k| *k == kind).unwrap();
+            counts[kind_idx] = counts[kind_idx] + 1;
+        }
+        return counts;
+    }
+
+    // Write a JSON manifest listing every injected fault: the block, the file
+    // it belongs to, and the datanode and storage of the affected replica.
+    pub fn write_fault_manifest(&self, path: &str) -> Result<(), std::io::Error> {
+        let faults = self.faulty_blocks();
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(write!(w, "{{\"seed\":{},\"blockPoolId\":{},\"counts\":{{", self.config.seed,
+                    json_string(&self.config.block_pool_id)));
+        let counts : Vec<String> = FAULT_KINDS.iter().zip(self.fault_counts().iter())
+            .map(|(kind, count)| format!("\"{}\":{}", kind.name(), count)).collect();
+        try!(write!(w, "{}}},\"faults\":[", counts.join(",")));
+        for (fault_idx, &(block, inode_id)) in faults.iter().enumerate() {
+            let fault = block.fault.as_ref().unwrap();
+            if fault_idx > 0 {
+                try!(write!(w, ","));
+            }
+            let path = match inode_id {
+                Some(inode_id) => json_string(&self.get_full_path(inode_id)),
+                None => "null".to_owned(),
+            };
+            try!(write!(w, "\n{{\"kind\":\"{}\",\"blockId\":{},\"genstamp\":{},\"numBytes\":{},\
+                           \"path\":{}", fault.kind.name(), block.id, block.genstamp,
+                        block.num_bytes, path));
+            if fault.kind == FaultKind::MissingBlock {
+                let datanodes : Vec<String> = block.datanodes.iter()
+                    .map(|datanode| (datanode + 1).to_string()).collect();
+                try!(write!(w, ",\"datanodes\":[{}]}}", datanodes.join(",")));
+                continue;
+            }
+            let dn_info = &self.datanode_info[fault.datanode as usize];
+            try!(write!(w, ",\"datanode\":{},\"datanodeUuid\":{},\"storageId\":{},\
+                           \"volume\":{},\"replicaGenstamp\":{}}}", fault.datanode + 1,
+                        json_string(&dn_info.datanode_uuid),
+                        json_string(&dn_info.storage_ids[fault.storage as usize]),
+                        json_string(&format!("datanode{:>02}/{}", fault.datanode + 1,
+                                             dn_info.volumes[fault.storage as usize].name)),
+                        fault.genstamp));
+        }
+        try!(write!(w, "\n]}}\n"));
+        return Result::Ok(());
+    }
+}
+
+fn json_string(val: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in val.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    return ret;
+}
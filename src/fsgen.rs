@@ -1,4 +1,4 @@
-/**
+/*
  * Licensed to the Apache Software Foundation (ASF) under one
  * or more contributor license agreements.  See the NOTICE file
  * distributed with this work for additional information
@@ -16,845 +16,693 @@
  * limitations under the License.
  */
 
-extern crate byteorder;
-extern crate crossbeam;
+extern crate fsgen;
 extern crate getopts;
-extern crate rand;
-extern crate uuid;
 
-use byteorder::{BigEndian, WriteBytesExt};
+use fsgen::ClusterSpec;
+use fsgen::FSImage;
+use fsgen::FsgenError;
+use fsgen::config::Config;
+use fsgen::config::ConfigSources;
+use fsgen::config::parse_datanode_list;
+use fsgen::config::parse_dir_naming;
+use fsgen::config::parse_file_naming;
+use fsgen::config::parse_image_codec;
+use fsgen::config::parse_link_mode;
+use fsgen::config::parse_name_charset;
+use fsgen::config::parse_volume_choosing_policy;
+use fsgen::config::split_user_list;
+use fsgen::fault::FAULT_KINDS;
+use fsgen::image::LoadedINodeType;
+use fsgen::image::load_image;
+use fsgen::replication::parse_replication_weights;
+use fsgen::verify_output_dir;
+use getopts::Matches;
 use getopts::Options;
-use rand::ChaChaRng;
-use rand::Rng;
-use std::char;
-use std::collections::HashMap;
-use std::collections::LinkedList;
 use std::env;
-use std::fs::File;
-use std::fs::OpenOptions;
+use std::fmt;
 use std::fs;
-use std::io::BufWriter;
 use std::io::ErrorKind;
-use std::io::Write;
 use std::process;
-use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
+use std::str::FromStr;
 use std::vec::Vec;
-use uuid::Uuid;
-
-// Namespace ID of generated fsimage
-// TODO: make configurable
-const NAMESPACE_ID : u64 = 397694258;
-
-// Cluster ID of generated fsimage
-// TODO: make configurable
-static CLUSTER_ID : &'static str = "CID-4d05b066-8649-49c7-80cf-49ed7eac011c";
-
-// Block pool ID of generated fsimage
-// TODO: make configurable
-const BLOCK_POOL_ID : &'static str = "BP-113955101-127.0.0.1-1455743472614";
-
-// The cluster creation time.  This must be the same on NN and DNs.
-// TODO: make configurable
-const CLUSTER_CTIME : u64 = 1455743472614;
-
-// The preferred block size used by files in this fsimage.
-// TODO: make configurable
-const PREFERRED_BLOCK_SIZE : u32 = 134217728;
-
-// The default datanode layout version of the generated fsimage
-const DEFAULT_DATANODE_LAYOUT_VERSION : i32 = -56;
-
-// The default namenode layout version of the generated fsimage
-const DEFAULT_NAMENODE_LAYOUT_VERSION : i32 = -60;
-
-// The first generation stamp to use for blocks.
-const FIRST_GENSTAMP : u32 = 1001;
-
-// See SequentialBlockIdGenerator#LAST_RESERVED_BLOCK_ID
-const FIRST_BLOCK_ID : u32 = 1073741825;
-
-// See INodeId#ROOT_INODE_ID
-const ROOT_INODE_ID : u32 = 16385;
-
-// TODO: make this configurable
-const ENTRIES_PER_DIR : usize = 6;
-
-// TODO: make this configurable
-const DIRS_PER_DIR : usize = 3;
-
-// The last transaction ID we saw.
-const LAST_TXID : u64 = 1;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let result = if args.len() > 1 && args[1] == "verify" {
+        verify_main(&args)
+    } else {
+        generate_main(&args)
+    };
+    if let Err(err) = result {
+        println!("** ERROR: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+// Generate a cluster, as described by the flags and configuration file.
+fn generate_main(args: &[String]) -> Result<(), FsgenError> {
     let mut opts = Options::new();
     let program = args[0].clone();
 
+    opts.optopt("c", "config", "load a TOML configuration file describing the cluster.  \
+                Other flags override its settings", "FILE");
+    opts.optflag("", "dry_run", "generate the namespace and print what would be written, \
+                 without creating or deleting anything");
     opts.optopt("d", "num_datanodes", "set the number of datanodes to generate", "NUM_DATANODES");
+    opts.optopt("", "append", "grow the namespace of an existing fsimage, XML or binary, by \
+                -n more inodes, and add their replicas to the existing datanode directories",
+                "FSIMAGE");
     opts.optflag("h", "help", "print this help menu");
+    opts.optopt("", "manifest", "create the paths listed in a manifest file, instead of -n \
+                random inodes", "FILE");
     opts.optopt("n", "num_inodes", "set the number of inodes to generate", "NUM_INODES");
     opts.optopt("o", "out", "set the output directory", "NAME");
     opts.optopt("r", "repl", "set the replication factor to use", "REPL_FACTOR");
+    opts.optopt("", "repl_weights", "draw the replication factor of each file from weighted \
+                choices, like 1:10,3:85,10:5", "WEIGHTS");
     opts.optopt("S", "storage_dirs_per_dn", "set the number of storage directories per datanode", "NUM_STORAGE_DIRS_PER_DN");
     opts.optopt("s", "seed", "set the random seed to use", "RAND_SEED");
+    opts.optopt("", "volume_choosing_policy", "set how datanodes choose the volume for each \
+                replica: round_robin or available_space", "POLICY");
+    opts.optopt("", "dir_names", "set how directories are named: random, date (2024-01-01) or \
+                hive (dt=2024-01-01)", "MODEL");
+    opts.optopt("", "file_names", "set how files are named: random, or spark \
+                (part-00000-<uuid>.snappy.parquet)", "MODEL");
+    opts.optopt("", "name_charset", "set the characters of random names: ascii or unicode",
+                "CHARSET");
     opts.optopt("t", "num_threads", "set the number of worker threads to use", "NUM_THREADS");
     opts.optopt("L", "namenode_layout_version", "set the NameNode layout version to use", "VERSION");
     opts.optopt("l", "datanode_layout_version", "set the DataNode layout version to use", 
                 "VERSION");
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
-    };
+    opts.optflag("", "force", "delete the output directory even if it doesn't look like \
+                 the output of an earlier fsgen run");
+    opts.optflag("", "resume", "keep the datanode directories written by an earlier run \
+                 with the same settings which died part way through, and only write the \
+                 replicas it didn't finish");
+    opts.optopt("", "num_cache_pools", "set the number of cache pools to generate", "NUM_POOLS");
+    opts.optopt("", "num_cache_directives", "set the number of cache directives to generate",
+                "NUM_DIRECTIVES");
+    opts.optopt("", "cache_pool_owner", "set the owner of generated cache pools", "OWNER");
+    opts.optopt("", "cache_pool_group", "set the group of generated cache pools", "GROUP");
+    opts.optopt("", "cache_pool_mode", "set the octal mode of generated cache pools", "MODE");
+    opts.optopt("", "cache_pool_limit", "set the byte limit of generated cache pools", "BYTES");
+    opts.optopt("", "cache_pool_max_ttl", "set the maximum directive TTL of generated cache \
+                pools, in milliseconds", "MS");
+    opts.optopt("", "cache_directive_repl", "set the replication of generated cache directives",
+                "REPL_FACTOR");
+    opts.optopt("", "cache_directive_ttl", "set the TTL of generated cache directives, in \
+                milliseconds", "MS");
+    opts.optopt("", "num_delegation_keys", "set the number of delegation keys to generate",
+                "NUM_KEYS");
+    opts.optopt("", "num_delegation_tokens", "set the number of delegation tokens to generate",
+                "NUM_TOKENS");
+    opts.optopt("", "delegation_key_ttl", "set the lifetime of generated delegation keys, in \
+                milliseconds", "MS");
+    opts.optopt("", "delegation_token_max_lifetime", "set the maximum lifetime of generated \
+                delegation tokens, in milliseconds", "MS");
+    opts.optopt("", "delegation_token_renew_interval", "set the renewal interval of generated \
+                delegation tokens, in milliseconds", "MS");
+    opts.optopt("", "delegation_token_owners", "set the comma-separated owners of generated \
+                delegation tokens", "USERS");
+    opts.optopt("", "delegation_token_renewers", "set the comma-separated renewers of generated \
+                delegation tokens", "USERS");
+    opts.optopt("", "delegation_token_real_users", "set the comma-separated real users of \
+                generated delegation tokens", "USERS");
+    opts.optopt("", "only_datanodes", "only write the directories of these datanodes, like \
+                \"17\" or \"1-4,7\".  Every run with the same seed and settings generates the \
+                same IDs and placement", "DATANODES");
+    opts.optflag("", "pseudo_distributed", "write configuration files and scripts which run \
+                 the whole cluster on this host, with separate ports for each datanode");
+    opts.optopt("", "install_dir", "set where the output directory will be on the cluster's \
+                hosts, for the paths in the generated Hadoop configuration files", "DIR");
+    opts.optopt("", "link_mode", "set how block and meta files with the same contents are \
+                created: none, hardlink or reflink", "MODE");
+    opts.optflag("", "binary_image", "also write a binary fsimage into the NameNode \
+                 directory, so that it doesn't need converting with hdfs oiv");
+    opts.optopt("", "image_codec", "set the codec which compresses the binary fsimage: none, \
+                default, gzip, snappy or lz4", "CODEC");
+    opts.optflag("", "xml_gzip", "gzip the XML fsimage");
+    opts.optopt("", "stats_json", "write statistics about the generated namespace to a JSON \
+                file", "FILE");
+    opts.optopt("", "progress_interval", "set how often to report progress, in seconds.  0 \
+                only reports when each phase finishes", "SECS");
+    opts.optflag("", "progress_json", "also write progress reports to stderr as JSON, one \
+                 object per line");
+    opts.optopt("", "stall_timeout", "warn about worker threads which make no progress for \
+                this many seconds.  0 disables the warning", "SECS");
+    let matches = try!(parse_args(&opts, &args[1..]));
     if matches.opt_present("h") {
         print_usage(&program, opts);
-        return;
+        return Result::Ok(());
     }
-    let num_datanodes = match matches.opt_str("d") {
-        None => 4 as u16,
-        Some(val) => val.parse::<u16>().unwrap(),
-    };
-    let num_inodes = match matches.opt_str("n") {
-        None => 10000 as u32,
-        Some(val) => val.parse::<u32>().unwrap(),
-    };
-    let out_dir = matches.opt_str("o").unwrap_or("".to_owned());
-    if out_dir == "" {
-        println!("You must specify an output directory with -o.  -h for help.");
-        process::exit(1);
-    };
-    let repl = match matches.opt_str("r") {
-        None => 3 as u16,
-        Some(val) => val.parse::<u16>().unwrap(),
-    };
-    let num_storage_dirs_per_dn = match matches.opt_str("S") {
-        None => 20 as u16,
-        Some(val) => val.parse::<u16>().unwrap(),
-    };
-    let seed = match matches.opt_str("s") {
-        None => 0xdeadbeef as u64,
-        Some(val) => val.parse::<u64>().unwrap(),
-    };
-    let num_threads = match matches.opt_str("t") {
-        None => 16 as u32,
-        Some(val) => val.parse::<u32>().unwrap(),
+    let mut config = Config::new();
+    let mut sources = ConfigSources::new();
+    if let Some(path) = matches.opt_str("c") {
+        try!(config.load_file(&path, &mut sources));
+    }
+    try!(apply_flags(&matches, &mut config, &mut sources));
+    try!(config.validate(&sources));
+    // With a manifest, -n is ignored, so show where the paths come from.
+    let inodes = if config.manifest.is_empty() {
+        format!("num_inodes={}", config.num_inodes)
+    } else {
+        format!("manifest={}", config.manifest)
     };
-    let dn_layout_version = match matches.opt_str("l") {
-        None => DEFAULT_DATANODE_LAYOUT_VERSION,
-        Some(val) => val.parse::<i32>().unwrap(),
-    };
-    let nn_layout_version = match matches.opt_str("L") {
-        None => DEFAULT_NAMENODE_LAYOUT_VERSION,
-        Some(val) => val.parse::<i32>().unwrap(),
-    };
-    if dn_layout_version >= 0 {
-        println!("The datanode layout version must be less than 0.");
-        process::exit(1);
-    }
-    if nn_layout_version >= 0 {
-        println!("The namenode layout version must be less than 0.");
-        process::exit(1);
-    }
-    if num_datanodes < repl {
-        println!("You specified {}x replication, but only {} datanodes.",
-                 repl, num_datanodes);
-        process::exit(1);
-    }
-    let config = Config{num_datanodes: num_datanodes, num_inodes: num_inodes,
-        out_dir: out_dir, repl: repl, num_storage_dirs_per_dn: num_storage_dirs_per_dn,
-        seed: seed, num_threads: num_threads,
-        dn_layout_version: dn_layout_version,
-        nn_layout_version: nn_layout_version};
-    println!("** fsgen: Generating fsimage with num_datanodes={}, num_inodes={}, \
+    println!("** fsgen: Generating fsimage with num_datanodes={}, {}, \
         out_dir={}, repl={}, num_storage_dirs_per_dn={}, seed={}, num_threads={}",
-        config.num_datanodes, config.num_inodes, config.out_dir, config.repl,
+        config.num_datanodes, inodes, config.out_dir, config.repl,
         config.num_storage_dirs_per_dn, config.seed, config.num_threads);
-    let mut rng = ChaChaRng::new_unseeded();
-    rng.set_counter(config.seed, config.seed);
-    match run_main(&config, &mut rng) {
-        Ok(_) => println!("** Done."),
-        Err(err) => {
-            println!("** ERROR: {:?}", err);
-            process::exit(1);
+    let fsimage = if config.append_image.is_empty() {
+        try!(ClusterSpec::from_config(config).generate())
+    } else {
+        let base_path = config.append_image.clone();
+        let base = try!(load_image(&base_path).map_err(|e| FsgenError::io(&base_path, e)));
+        println!("** loaded {} inodes from {}", base.inodes.len(), base_path);
+        if let Some(inode) = base.inodes.iter()
+                .find(|inode| inode.inode_type == LoadedINodeType::Symlink) {
+            return Err(FsgenError::InvalidArgument(format!("{} has symlink {} ({}), \
+                and fsgen can't append to images with symlinks.", base_path, inode.id,
+                inode.name)));
         }
+        try!(ClusterSpec::from_config(config).append(&base))
+    };
+    println!("** generated fsimage...");
+    print!("{}", fsimage.stats().to_text());
+    if fsimage.config().has_faults() {
+        let counts : Vec<String> = FAULT_KINDS.iter().zip(fsimage.fault_counts().iter())
+            .map(|(kind, count)| format!("{}={}", kind.name(), count)).collect();
+        println!("** injected faults: {}", counts.join(", "));
+    }
+    let output_dir = OutputDir::new(fsimage.config());
+    let resume = matches.opt_present("resume");
+    if matches.opt_present("dry_run") {
+        print_plan(&fsimage, &output_dir, matches.opt_present("force"), resume);
+        return Result::Ok(());
     }
+    if !matches.opt_present("force") {
+        try!(output_dir.check_replaceable());
+    }
+    try!(run_main(&fsimage, &output_dir, resume));
+    println!("** Done.");
+    fsimage.progress().finish();
+    return Result::Ok(());
 }
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
-    println!("fsgen: Generates an HDFS fsimage.\n");
-    println!("By using the fsgen tool, you can quickly generate large HDFS");
-    println!("fsimages for testing purposes.  fsgen has two main outputs,");
-    println!("an fsimage.xml file and a block directory.");
-    print!("{}", opts.usage(&brief));
-}
-
-fn run_main(config: &Config, rng: &mut Rng) -> Result<(), std::io::Error> {
-    let output_dir = OutputDir::new(&config.out_dir);
-    try!(output_dir.delete_if_exists());
-    let fsimage = FSImage::new(config, rng);
-    println!("** generated fsimage...");
-    let current_path = &(output_dir.path.clone() + "/name/current");
-    try!(fs::create_dir_all(current_path));
-    println!("** created {}", current_path);
-    let version_path = &(output_dir.path.clone() + "/name/current/VERSION");
-    try!(fsimage.write_namenode_version_file(version_path));
-    println!("** wrote namenode version file {}", version_path);
-    let seen_txid_path = &(output_dir.path.clone() + "/name/current/seen_txid");
-    try!(fsimage.write_seen_txid_file(seen_txid_path, LAST_TXID));
-    println!("** wrote seen_txid file {}", seen_txid_path);
-    let edits_path = &(output_dir.path.clone() +
-            "/name/current/edits_inprogress_0000000000000000001");
-    try!(fsimage.write_edits_file(edits_path, config.nn_layout_version));
-    println!("** wrote edits file {}", edits_path);
-    let fsimage_path = &(output_dir.path.clone() +
-            "/fsimage_0000000000000000001.xml");
-    try!(fsimage.write_xml(fsimage_path));
-    println!("** wrote fsimage file {}", fsimage_path);
-    for datanode_idx in 0..config.num_datanodes {
-        try!(fsimage.generate_datanode_dir(&output_dir.path, datanode_idx));
-    }
-    try!(fsimage.generate_block_files(&output_dir.path));
-    return Result::Ok(());
+fn parse_args(opts: &Options, args: &[String]) -> Result<Matches, FsgenError> {
+    return opts.parse(args).map_err(|f| {
+        FsgenError::InvalidArgument(format!("{}.  -h for help.", f))
+    });
 }
 
-// Represents an fsgen configuration.
-struct Config {
-    num_datanodes: u16,
-    num_inodes: u32,
-    out_dir: String,
-    repl: u16,
-    num_storage_dirs_per_dn: u16,
-    seed: u64,
-    num_threads: u32,
-    dn_layout_version: i32,
-    nn_layout_version: i32,
+// Parse the value of a numeric flag.
+fn parse_flag<T>(flag_name: &str, val: &str) -> Result<T, FsgenError>
+        where T: FromStr, T::Err: fmt::Display {
+    return flag_value(flag_name, val, val.parse::<T>());
 }
 
-// Represents an output directory where we will generate some files.
-struct OutputDir {
-    path: String,
+// Get the value of a flag, or explain what is wrong with it.
+fn flag_value<T, E: fmt::Display>(flag_name: &str, val: &str, result: Result<T, E>)
+                                  -> Result<T, FsgenError> {
+    return result.map_err(|e| {
+        FsgenError::InvalidArgument(format!("Invalid value '{}' for {}: {}", val, flag_name, e))
+    });
 }
 
-impl OutputDir {
-    fn new(path: &str) -> OutputDir {
-        return OutputDir {
-            path: path.to_owned(),
-        }
+// Override the configuration with any flags given on the command line.
+fn apply_flags(matches: &Matches, config: &mut Config, sources: &mut ConfigSources)
+               -> Result<(), FsgenError> {
+    for flag in [ "d", "n", "o", "r", "S", "s", "t", "L", "l", "num_cache_pools",
+            "num_cache_directives", "cache_pool_owner", "cache_pool_group", "cache_pool_mode",
+            "cache_pool_limit", "cache_pool_max_ttl", "cache_directive_repl",
+            "cache_directive_ttl", "num_delegation_keys", "num_delegation_tokens",
+            "delegation_key_ttl", "delegation_token_max_lifetime",
+            "delegation_token_renew_interval", "delegation_token_owners",
+            "delegation_token_renewers", "delegation_token_real_users", "stats_json",
+            "only_datanodes", "volume_choosing_policy", "link_mode", "install_dir",
+            "image_codec", "progress_interval", "stall_timeout", "append",
+            "manifest", "dir_names", "file_names", "name_charset",
+            "repl_weights" ].iter() {
+        let val = match matches.opt_str(flag) {
+            None => continue,
+            Some(val) => val,
+        };
+        let flag_name = if flag.len() == 1 {
+            format!("-{}", flag)
+        } else {
+            format!("--{}", flag)
+        };
+        let key = match *flag {
+            "d" => { config.num_datanodes = try!(parse_flag(&flag_name, &val)); "datanodes.count" }
+            "n" => {
+                config.num_inodes = try!(parse_flag(&flag_name, &val));
+                "namespace.num_inodes"
+            }
+            "o" => { config.out_dir = val; "output.dir" }
+            "r" => { config.repl = try!(parse_flag(&flag_name, &val)); "files.replication" }
+            "repl_weights" => {
+                config.replication_weights =
+                    try!(flag_value(&flag_name, &val, parse_replication_weights(&val)));
+                "files.replication_weights"
+            }
+            "S" => {
+                config.num_storage_dirs_per_dn = try!(parse_flag(&flag_name, &val));
+                "datanodes.storage_dirs_per_datanode"
+            }
+            "s" => { config.seed = try!(parse_flag(&flag_name, &val)); "output.seed" }
+            "t" => { config.num_threads = try!(parse_flag(&flag_name, &val)); "output.threads" }
+            "L" => {
+                config.nn_layout_version = try!(parse_flag(&flag_name, &val));
+                "cluster.namenode_layout_version"
+            }
+            "l" => {
+                config.dn_layout_version = try!(parse_flag(&flag_name, &val));
+                "cluster.datanode_layout_version"
+            }
+            "num_cache_pools" => {
+                config.num_cache_pools = try!(parse_flag(&flag_name, &val));
+                "cache.num_pools"
+            }
+            "num_cache_directives" => {
+                config.num_cache_directives = try!(parse_flag(&flag_name, &val));
+                "cache.num_directives"
+            }
+            "cache_pool_owner" => { config.cache_pool_owner = val; "cache.pool_owner" }
+            "cache_pool_group" => { config.cache_pool_group = val; "cache.pool_group" }
+            "cache_pool_mode" => {
+                config.cache_pool_mode =
+                    try!(flag_value(&flag_name, &val, u16::from_str_radix(&val, 8)));
+                "cache.pool_mode"
+            }
+            "cache_pool_limit" => {
+                config.cache_pool_limit = try!(parse_flag(&flag_name, &val));
+                "cache.pool_limit"
+            }
+            "cache_pool_max_ttl" => {
+                config.cache_pool_max_ttl = try!(parse_flag(&flag_name, &val));
+                "cache.pool_max_ttl"
+            }
+            "cache_directive_repl" => {
+                config.cache_directive_repl = try!(parse_flag(&flag_name, &val));
+                "cache.directive_replication"
+            }
+            "cache_directive_ttl" => {
+                config.cache_directive_ttl = try!(parse_flag(&flag_name, &val));
+                "cache.directive_ttl"
+            }
+            "num_delegation_keys" => {
+                config.num_delegation_keys = try!(parse_flag(&flag_name, &val));
+                "delegation.num_keys"
+            }
+            "num_delegation_tokens" => {
+                config.num_delegation_tokens = try!(parse_flag(&flag_name, &val));
+                "delegation.num_tokens"
+            }
+            "delegation_key_ttl" => {
+                config.delegation_key_ttl = try!(parse_flag(&flag_name, &val));
+                "delegation.key_ttl"
+            }
+            "delegation_token_max_lifetime" => {
+                config.delegation_token_max_lifetime = try!(parse_flag(&flag_name, &val));
+                "delegation.token_max_lifetime"
+            }
+            "delegation_token_renew_interval" => {
+                config.delegation_token_renew_interval = try!(parse_flag(&flag_name, &val));
+                "delegation.token_renew_interval"
+            }
+            "delegation_token_owners" => {
+                config.delegation_token_owners = split_user_list(&val);
+                "delegation.owners"
+            }
+            "delegation_token_renewers" => {
+                config.delegation_token_renewers = split_user_list(&val);
+                "delegation.renewers"
+            }
+            "delegation_token_real_users" => {
+                config.delegation_token_real_users = split_user_list(&val);
+                "delegation.real_users"
+            }
+            "stats_json" => { config.stats_json = val; "output.stats_json" }
+            "volume_choosing_policy" => {
+                config.volume_choosing_policy =
+                    try!(flag_value(&flag_name, &val, parse_volume_choosing_policy(&val)));
+                "datanodes.volume_choosing_policy"
+            }
+            "dir_names" => {
+                config.dir_naming = try!(flag_value(&flag_name, &val, parse_dir_naming(&val)));
+                "names.directories"
+            }
+            "file_names" => {
+                config.file_naming = try!(flag_value(&flag_name, &val, parse_file_naming(&val)));
+                "names.files"
+            }
+            "name_charset" => {
+                config.name_charset =
+                    try!(flag_value(&flag_name, &val, parse_name_charset(&val)));
+                "names.charset"
+            }
+            "install_dir" => { config.hadoop_install_dir = val; "hadoop.install_dir" }
+            "link_mode" => {
+                config.link_mode = try!(flag_value(&flag_name, &val, parse_link_mode(&val)));
+                "output.link_mode"
+            }
+            "image_codec" => {
+                config.image_codec = try!(flag_value(&flag_name, &val, parse_image_codec(&val)));
+                "output.image_codec"
+            }
+            "only_datanodes" => {
+                config.only_datanodes =
+                    try!(flag_value(&flag_name, &val, parse_datanode_list(&val)));
+                "output.only_datanodes"
+            }
+            "append" => { config.append_image = val; "namespace.append_image" }
+            "manifest" => { config.manifest = val; "namespace.manifest" }
+            "progress_interval" => {
+                config.progress_interval = try!(parse_flag(&flag_name, &val));
+                "output.progress_interval"
+            }
+            "stall_timeout" => {
+                config.stall_timeout = try!(parse_flag(&flag_name, &val));
+                "output.stall_timeout"
+            }
+            _ => panic!("unhandled flag {}", flag),
+        };
+        sources.set(key, flag_name);
     }
-
-    // Delete the output directory and all its contents, if it exists.
-    fn delete_if_exists(&self) -> Result<(), std::io::Error> {
-        if fs::metadata(self.path.to_owned()).is_ok() {
-            try!(fs::remove_dir_all(self.path.to_owned()));
-            println!("** deleted existing output directory {}", self.path)
-        }
-        return Result::Ok(());
+    if matches.opt_present("pseudo_distributed") {
+        config.hadoop_pseudo_distributed = true;
+        sources.set("hadoop.pseudo_distributed", "--pseudo_distributed".to_owned());
     }
-}
-
-// Represents the FSImage which we will be writing to disk.
-struct FSImage<'a> {
-    // The FSGen configuration.
-    config: & 'a Config,
-
-    // Maps inode ID to inode information.
-    inode_map: HashMap<u32, INode>,
-
-    // Maps inode ID to inode children.
-    children: HashMap<u32, Vec<u32>>,
-
-    // The next inode ID to use.
-    next_inode_id: u32,
-
-    // The next ID which could be an empty directory.
-    next_possible_empty_dir_id: u32,
-
-    // The number of inodes
-    num_inodes: u32,
-
-    // The next block genstamp to use.
-    next_genstamp: u32,
-
-    // The next block id to use.
-    next_block_id: u32,
-
-    // Information about the datanodes.
-    datanode_info: Vec<DatanodeInfo>,
-}
-
-struct DatanodeInfo {
-    // The UUID of this DataNode.
-    datanode_uuid: String,
-
-    // The IDs of each storage in this DataNode.
-    storage_ids: Vec<String>,
-}
-
-fn random_str(rng: &mut Rng, len: u32) -> String {
-    let mut ret = String::new();
-    for _ in 0..len {
-        let val = rng.next_u32() % 26;
-        ret.push(char::from_u32(val + 0x61).unwrap());
+    if matches.opt_present("binary_image") {
+        config.binary_image = true;
+        sources.set("output.binary_image", "--binary_image".to_owned());
     }
-    return ret;
+    if matches.opt_present("xml_gzip") {
+        config.xml_gzip = true;
+        sources.set("output.xml_gzip", "--xml_gzip".to_owned());
+    }
+    if matches.opt_present("progress_json") {
+        config.progress_json = true;
+        sources.set("output.progress_json", "--progress_json".to_owned());
+    }
+    return Result::Ok(());
 }
 
-fn generate_dn_info(config: &Config) -> Vec<DatanodeInfo> {
-    let mut datanodes : Vec<DatanodeInfo> = vec![];
-    for _ in 0..config.num_datanodes {
-        let mut storage_ids : Vec<String> = vec![];
-        for _ in 0..config.num_storage_dirs_per_dn {
-            storage_ids.push(
-                "DS-".to_owned() + &Uuid::new_v4().to_hyphenated_string());
-        }
-        let datanode = DatanodeInfo {
-            datanode_uuid: Uuid::new_v4().to_hyphenated_string(),
-            storage_ids: storage_ids,
-        };
-        datanodes.push(datanode);
-    }
-    return datanodes;
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options]\n       {} verify [options]", program, program);
+    println!("fsgen: Generates an HDFS fsimage.\n");
+    println!("By using the fsgen tool, you can quickly generate large HDFS");
+    println!("fsimages for testing purposes.  fsgen has two main outputs,");
+    println!("an fsimage.xml file and a block directory.");
+    println!("");
+    println!("fsgen verify checks an existing output directory against its");
+    println!("fsimage, and exits with a non-zero status if it finds problems.");
+    print!("{}", opts.usage(&brief));
 }
 
-impl<'a> FSImage<'a> {
-    fn new(config: &'a Config, rng: &mut Rng) -> FSImage<'a> {
-        let mut fs_image = FSImage {
-            config: config,
-            inode_map: HashMap::new(),
-            children: HashMap::new(),
-            next_inode_id: (ROOT_INODE_ID + 1),
-            next_possible_empty_dir_id: ROOT_INODE_ID,
-            num_inodes: 0,
-            next_genstamp: FIRST_GENSTAMP,
-            next_block_id: FIRST_BLOCK_ID,
-            datanode_info: generate_dn_info(config),
-        };
-        fs_image.generate(rng);
-        return fs_image;
+// Check an existing output directory against its fsimage.
+fn verify_main(args: &[String]) -> Result<(), FsgenError> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("i", "image", "set the fsimage to check against, either XML or binary.  \
+                Defaults to the XML fsimage in the output directory, gzipped or not, or the \
+                binary fsimage in its NameNode directory", "FILE");
+    opts.optopt("o", "out", "set the output directory to check", "NAME");
+    let matches = try!(parse_args(&opts, &args[2..]));
+    if matches.opt_present("h") {
+        print!("{}", opts.usage(&format!("Usage: {} verify [options]", args[0])));
+        return Result::Ok(());
     }
-
-    fn generate(&mut self, rng: &mut Rng) {
-        let root_inode = INode {
-            id: ROOT_INODE_ID,
-            name: "".to_owned(),
-            is_dir: true,
-            blocks: vec![],
-        };
-        let mut parents : LinkedList<u32> = LinkedList::new();
-        self.inode_map.insert(ROOT_INODE_ID, root_inode);
-        parents.push_back(ROOT_INODE_ID);
-        self.num_inodes = self.num_inodes + 1;
-        loop {
-            let parent_id = self.find_shallowest_incomplete_dir();
-            for i in 0..ENTRIES_PER_DIR {
-                if self.num_inodes > self.config.num_inodes {
-                    return;
-                }
-                let id = self.next_inode_id;
-                self.next_inode_id = self.next_inode_id + 1;
-                {
-                    let mut children = self.children.get_mut(&parent_id).unwrap();
-                    children.push(id);
-                }
-                let name = format!("{}{}", (0x61 + i), random_str(rng, 3));
-                let is_dir = i < DIRS_PER_DIR;
-                if is_dir {
-                    let inode = INode {
-                        id: id,
-                        name: name,
-                        is_dir: true,
-                        blocks: vec![],
-                    };
-                    self.inode_map.insert(id, inode);
-                    self.children.insert(id, vec![]);
-                } else {
-                    let inode = INode {
-                        id: id,
-                        name: format!("{}{}", (0x61 + i), random_str(rng, 3)),
-                        is_dir: false,
-                        blocks: vec![self.generate_random_block(rng)],
-                    };
-                    self.inode_map.insert(id, inode);
-                }
-                self.num_inodes = self.num_inodes + 1;
+    let out_dir = match matches.opt_str("o") {
+        None => return Err(FsgenError::InvalidArgument(
+            "You must specify an output directory to check with -o.  -h for help.".to_owned())),
+        Some(out_dir) => out_dir,
+    };
+    let image_path = match matches.opt_str("i") {
+        Some(path) => path,
+        None => {
+            let xml_path = format!("{}/fsimage_0000000000000000001.xml", out_dir);
+            let gzip_path = xml_path.clone() + ".gz";
+            if fs::metadata(&xml_path).is_ok() {
+                xml_path
+            } else if fs::metadata(&gzip_path).is_ok() {
+                gzip_path
+            } else {
+                format!("{}/name/current/fsimage_0000000000000000001", out_dir)
             }
+        },
+    };
+    println!("** fsgen: Verifying {} against {}", out_dir, image_path);
+    let report = try!(verify_output_dir(&out_dir, &image_path));
+    println!("** checked {} blocks and {} replicas in {} storage directories.",
+             report.num_blocks, report.num_replicas, report.num_storage_dirs);
+    for (category, problems) in &report.problems {
+        println!("** {}: {} problem(s)", category, problems.count);
+        for example in &problems.examples {
+            println!("   {}", example);
         }
-    }
-
-    fn find_shallowest_incomplete_dir(&mut self) -> u32 {
-        loop {
-            let id = self.next_possible_empty_dir_id;
-            let inode = self.inode_map.get(&id).unwrap();
-            if inode.is_dir {
-                let children = self.children.entry(id).or_insert(vec![]);
-                if children.len() < ENTRIES_PER_DIR {
-                    return id;
-                }
-            }
-            self.next_possible_empty_dir_id = self.next_possible_empty_dir_id + 1;
+        if problems.count > problems.examples.len() as u64 {
+            println!("   ...");
         }
     }
+    if report.num_problems() > 0 {
+        println!("** FAILED: found {} problem(s).", report.num_problems());
+        process::exit(1);
+    }
+    println!("** OK.");
+    return Result::Ok(());
+}
 
-    fn generate_random_block(&mut self, rng: &mut Rng) -> Block {
-        let mut datanodes : Vec<u16> = Vec::new();
-        for i in 0..self.config.repl {
-            let range = (self.config.num_datanodes - i) as u32;
-            let mut val = (rng.next_u32() % range) as u16;
-            {
-                for datanode in &datanodes {
-                    if val >= *datanode {
-                        val = val + 1;
-                    }
-                }
-            }
-            datanodes.push(val);
+// Write the output directory.  If resume is set, the datanode directories
+// left by an earlier run are finished off rather than deleted.
+fn run_main(fsimage: &FSImage, output_dir: &OutputDir, resume: bool)
+            -> Result<(), FsgenError> {
+    let append = fsimage.num_base_inodes() > 0;
+    if append {
+        try!(fsimage.check_appendable(&output_dir.path));
+    }
+    if resume {
+        try!(fsimage.check_resumable(&output_dir.path));
+    } else if !append {
+        try!(output_dir.delete_if_exists());
+    }
+    // The image we appended to is often the one in the output directory.
+    // Writing the datanode directories before replacing it means that if the
+    // run dies part way through, the old image is still there to append to
+    // again with --resume.
+    if append {
+        try!(write_datanode_dirs(fsimage, output_dir, resume));
+    }
+    if output_dir.writes_namenode() {
+        try!(fsimage.write_namenode_dir(&output_dir.name_dir()));
+        let fsimage_path = &output_dir.fsimage_path();
+        try!(fsimage.write_xml(fsimage_path));
+        println!("** wrote fsimage file {}", fsimage_path);
+        if fsimage.config().binary_image {
+            let binary_path = &output_dir.binary_fsimage_path();
+            try!(fsimage.write_binary_image(binary_path));
+            println!("** wrote binary fsimage file {} (codec: {})", binary_path,
+                     fsimage.config().image_codec.name());
+        }
+        try!(fsimage.write_hadoop_conf(&output_dir.path));
+        println!("** wrote hadoop configuration files to {}/conf", output_dir.path);
+        if fsimage.config().hadoop_pseudo_distributed {
+            println!("** wrote {}/start-cluster.sh and {}/stop-cluster.sh",
+                     output_dir.path, output_dir.path);
         }
-        let id = self.next_block_id;
-        self.next_block_id = self.next_block_id + 1;
-        let genstamp = self.next_genstamp;
-        self.next_genstamp = self.next_genstamp + 1;
-        return Block {
-            id: id,
-            genstamp: genstamp,
-            datanodes: datanodes,
-        };
     }
-
-    // Write the VERSION file which identifies the version of this fsimage.
-    pub fn write_namenode_version_file(&self,
-                        path: &str) -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
-        try!(write!(w, "namespaceID={}\n", NAMESPACE_ID));
-        try!(write!(w, "clusterID={}\n", CLUSTER_ID));
-        try!(write!(w, "cTime={}\n", CLUSTER_CTIME));
-        try!(write!(w, "storageType=NAME_NODE\n"));
-        try!(write!(w, "blockpoolID={}\n", BLOCK_POOL_ID));
-        try!(write!(w, "layoutVersion={}\n", self.config.nn_layout_version));
-        return Result::Ok(());
+    if !append {
+        try!(write_datanode_dirs(fsimage, output_dir, resume));
     }
-
-    // Write the seen_txid file in the namenode storage directory.  This file
-    // identifies the highest transaction id (txid) that we've seen.
-    pub fn write_seen_txid_file(&self, path: &str,
-                               seen_txid: u64) -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        try!(write!(w, "{}\n", seen_txid));
-        return Result::Ok(());
+    let stats_json = &fsimage.config().stats_json;
+    if !stats_json.is_empty() {
+        try!(fsimage.stats().write_json(stats_json)
+             .map_err(|e| FsgenError::io(stats_json, e)));
+        println!("** wrote statistics to {}", stats_json);
     }
-
-    // Write an HDFS edit log file with just the 4-byte layout version and
-    // the 4-byte feature flags int (which is always 0).
-    pub fn write_edits_file(&self, path: &str,
-                nn_layout_version: i32) -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        w.write_i32::<BigEndian>(nn_layout_version).unwrap();
-        let arr = [ 0x00u8, 0x00u8, 0x00u8, 0x00u8,
-                    0x18u8, 0x00u8, 0x00u8, 0x00u8,
-                    0x0cu8, 0x00u8, 0x00u8, 0x00u8,
-                    0x00u8, 0x00u8, 0x00u8, 0x00u8,
-                    0x01u8, 0x70u8, 0x43u8, 0xf0u8,
-                    0xb7u8, 0xffu8, 0xffu8 ];
-        try!(w.write(&arr));
-        return Result::Ok(());
-    }
-
-    // Write the FSImage XML.
-    pub fn write_xml(&self, path: &str) -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        try!(write!(w, "<?xml version=\"1.0\"?>"));
-        try!(write!(w, "<fsimage>"));
-        try!(self.write_version_section(&mut w));
-        try!(self.write_name_section(&mut w));
-        try!(self.write_inode_section(&mut w));
-        try!(write!(w, "<INodeReferenceSection></INodeReferenceSection>"));
-        try!(self.write_snapshot_section(&mut w));
-        try!(self.write_inode_directory_section(&mut w));
-        try!(write!(w, "<FileUnderConstructionSection></FileUnderConstructionSection>"));
-        try!(self.write_snapshot_diff_section(&mut w));
-        try!(self.write_secret_manager_section(&mut w));
-        try!(self.write_cache_manager_section(&mut w));
-        try!(write!(w, "</fsimage>"));
-        return Result::Ok(());
+    if fsimage.config().has_faults() {
+        let manifest_path = &fsimage.config().fault_manifest_path();
+        try!(fsimage.write_fault_manifest(manifest_path)
+             .map_err(|e| FsgenError::io(manifest_path, e)));
+        println!("** wrote fault manifest {}", manifest_path);
     }
+    return Result::Ok(());
+}
 
-    fn write_snapshot_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<SnapshotSection>"));
-        try!(write!(w, "<snapshotCounter>0</snapshotCounter>"));
-        try!(write!(w, "<numSnapshots>0</numSnapshots>"));
-        try!(write!(w, "</SnapshotSection>\n"));
-        return Result::Ok(());
+fn write_datanode_dirs(fsimage: &FSImage, output_dir: &OutputDir, resume: bool)
+                       -> Result<(), FsgenError> {
+    if resume {
+        return fsimage.resume_datanode_dirs(&output_dir.path);
+    } else {
+        return fsimage.write_datanode_dirs(&output_dir.path);
     }
+}
 
-    fn write_snapshot_diff_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<SnapshotDiffSection>"));
-        try!(write!(w, "<dirDiffEntry><inodeId>16385</inodeId><count>0</count></dirDiffEntry>"));
-        try!(write!(w, "</SnapshotDiffSection>\n"));
-        return Result::Ok(());
+// Print what run_main would do, without creating or deleting anything.
+fn print_plan(fsimage: &FSImage, output_dir: &OutputDir, force: bool, resume: bool) {
+    println!("** dry run: nothing will be created or deleted.");
+    let append = fsimage.num_base_inodes() > 0;
+    if append {
+        println!("** would append {} inodes to the {} loaded from {}",
+                 fsimage.num_inodes() - fsimage.num_base_inodes(), fsimage.num_base_inodes(),
+                 fsimage.config().append_image);
+        match fsimage.check_appendable(&output_dir.path) {
+            Ok(()) => println!("** would add the new replicas to any existing datanode \
+                               directories"),
+            Err(err) => println!("** {}", err),
+        }
     }
-
-    pub fn generate_block_files(&self, base_path: &str) -> Result<(), std::io::Error> {
-        let files_processed = Arc::new(AtomicUsize::new(0));
-        let mut threads = vec![];
-        let inode_map = Arc::new(&self.inode_map);
-        let num_threads = self.config.num_threads;
-        let num_storage_dirs_per_dn = self.config.num_storage_dirs_per_dn;
-        {
-            for thread_idx in 0..self.config.num_threads {
-                let inode_map_ref = inode_map.clone();
-                let files_processed_ref = files_processed.clone();
-                // This is actually safe, but the compiler can't prove it to be so.
-                // We join all these threads at the end of the function.
-                //
-                // TODO: it would be nice to avoid this unsafe block.  Probably
-                // the best way to do that would be to move to a channel-based
-                // architecture where worker threads received paths of block
-                // files to create.  Might also be able to do something with
-                // scoped threads?
-                unsafe {
-                    let thread = crossbeam::spawn_unsafe(move|| {
-                        for (_, inode) in inode_map_ref.iter() {
-                            if inode.is_dir {
-                                continue;
-                            }
-                            if inode.id % num_threads != thread_idx {
-                                continue;
-                            }
-                            for block in &inode.blocks {
-                                match block.generate_block_files(
-                                    base_path, num_storage_dirs_per_dn) {
-                                    Ok(()) => (),
-                                    Err(e) => {
-                                        println!("Thread {} failed to create block {}: {}",
-                                                 thread_idx, block.id, e);
-                                        panic!(e);
-                                    }
-                                };
-                            }
-                            let p = files_processed_ref.fetch_add(1, Ordering::Relaxed);
-                            if (p != 0) && (p % 10000) == 0 {
-                                println!("Created {} blocks on disk...", p);
-                            }
-                        }
-                    });
-                    threads.push(thread);
-                }
-            }
-            for i in threads {
-                let _ = i.join();
+    if resume {
+        match fsimage.check_resumable(&output_dir.path) {
+            Ok(()) => println!("** would resume any existing datanode directories"),
+            Err(err) => println!("** {}", err),
+        }
+    } else if !append {
+        for dir in output_dir.replaced_dirs() {
+            if fs::metadata(&dir).is_err() {
+                continue;
+            }
+            match check_replaceable(&dir) {
+                Ok(()) => println!("** would delete existing directory {}", dir),
+                Err(_) if force => {
+                    println!("** would delete existing directory {} (--force)", dir)
+                },
+                Err(err) => println!("** {}", err),
             }
         }
-        println!("** generate_block_files: processed about {} files.",
-                 files_processed.load(Ordering::Relaxed));
-        return Result::Ok(());
     }
-
-    // Write the FSImage XML.
-    fn write_version_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<version>"));
-        try!(write!(w, "<layoutVersion>{}</layoutVersion>", self.config.nn_layout_version));
-        try!(write!(w, "<onDiskVersion>1</onDiskVersion>"));
-        try!(write!(w, "<oivRevision>545bbef596c06af1c3c8dca1ce29096a64608478</oivRevision>"));
-        try!(write!(w, "</version>\n"));
-        return Result::Ok(());
+    if output_dir.writes_namenode() {
+        println!("** would write namenode dir {}", output_dir.name_dir());
+        println!("** would write fsimage file {}", output_dir.fsimage_path());
+        if fsimage.config().binary_image {
+            println!("** would write binary fsimage file {} (codec: {})",
+                     output_dir.binary_fsimage_path(), fsimage.config().image_codec.name());
+        }
+        println!("** would write hadoop configuration files to {}/conf", output_dir.path);
+        if fsimage.config().hadoop_pseudo_distributed {
+            println!("** would write {}/start-cluster.sh and {}/stop-cluster.sh",
+                     output_dir.path, output_dir.path);
+        }
     }
-
-    fn write_name_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<NameSection>"));
-        try!(write!(w, "<namespaceId>{}</namespaceId>", NAMESPACE_ID));
-        try!(write!(w, "<genstampV1>1000</genstampV1>"));
-        try!(write!(w, "<genstampV2>{}</genstampV2>", self.next_genstamp));
-        try!(write!(w, "<genstampV1Limit>0</genstampV1Limit>"));
-        try!(write!(w, "<lastAllocatedBlockId>{}</lastAllocatedBlockId>",
-                    self.next_block_id - 1));
-        try!(write!(w, "<txid>{}</txid>", LAST_TXID));
-        try!(write!(w, "</NameSection>\n"));
-        return Result::Ok(());
+    let mut total_files = 0;
+    let mut total_data_bytes = 0;
+    let mut total_meta_bytes = 0;
+    for usage in fsimage.datanode_usage(&output_dir.path) {
+        println!("** would write datanode dir {} with {} storage dirs: {} files, {} bytes \
+                 of sparse block data, {} bytes of meta data", usage.path,
+                 usage.num_storage_dirs, usage.num_files, usage.data_bytes, usage.meta_bytes);
+        total_files = total_files + usage.num_files;
+        total_data_bytes = total_data_bytes + usage.data_bytes;
+        total_meta_bytes = total_meta_bytes + usage.meta_bytes;
+    }
+    println!("** would write {} datanode files in total: {} bytes of sparse block data, \
+             {} bytes of meta data", total_files, total_data_bytes, total_meta_bytes);
+    let stats_json = &fsimage.config().stats_json;
+    if !stats_json.is_empty() {
+        println!("** would write statistics to {}", stats_json);
+    }
+    if fsimage.config().has_faults() {
+        println!("** would write fault manifest {}", fsimage.config().fault_manifest_path());
     }
+}
 
-    fn write_inode_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<INodeSection>"));
-        try!(write!(w, "<lastInodeId>{}</lastInodeId>", self.next_inode_id - 1));
-        try!(write!(w, "<numInodes>{}</numInodes>", self.num_inodes));
-        for (_, inode) in self.inode_map.iter() {
-            try!(write!(w, "{}", inode.to_xml().to_owned()));
-        }
-        try!(write!(w, "</INodeSection>\n"));
-        return Result::Ok(());
-    }
+// Represents an output directory where we will generate some files.
+struct OutputDir {
+    path: String,
+
+    // If this is not empty, we only write the directories of these datanodes
+    // (0-based), and leave the rest of the output directory alone.
+    only_datanodes: Vec<u16>,
 
-    fn write_inode_directory_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<INodeDirectorySection>"));
-        for (parent_id, id_vec) in self.children.iter() {
-            try!(write!(w, "<directory><parent>{}</parent>", parent_id));
-            for child_id in id_vec {
-                try!(write!(w, "<child>{}</child>", child_id));
-            }
-            try!(write!(w, "</directory>"));
+    // Whether the XML fsimage is gzipped
+    xml_gzip: bool,
+}
+
+impl OutputDir {
+    fn new(config: &Config) -> OutputDir {
+        return OutputDir {
+            path: config.out_dir.clone(),
+            only_datanodes: config.only_datanodes.clone(),
+            xml_gzip: config.xml_gzip,
         }
-        try!(write!(w, "</INodeDirectorySection>\n"));
-        return Result::Ok(());
     }
 
-    fn write_secret_manager_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<SecretManagerSection>"));
-        try!(write!(w, "<currentId>2</currentId>")); // ???
-        try!(write!(w, "<tokenSequenceNumber>1</tokenSequenceNumber>")); // ???
-        try!(write!(w, "<numDelegationKeys>0</numDelegationKeys>"));
-        try!(write!(w, "<numTokens>0</numTokens>"));
-        try!(write!(w, "</SecretManagerSection>\n"));
-        return Result::Ok(());
+    fn writes_namenode(&self) -> bool {
+        return self.only_datanodes.is_empty();
     }
 
-    fn write_cache_manager_section(&self, w: &mut BufWriter<&File>) -> Result<(), std::io::Error> {
-        try!(write!(w, "<CacheManagerSection>"));
-        try!(write!(w, "<nextDirectiveId>1</nextDirectiveId>"));
-        try!(write!(w, "<numDirectives>0</numDirectives>"));
-        try!(write!(w, "<numPools>0</numPools>"));
-        try!(write!(w, "</CacheManagerSection>\n"));
-        return Result::Ok(());
+    fn name_dir(&self) -> String {
+        return self.path.clone() + "/name";
     }
 
-    // The datanode layout looks like this:
-    //
-    // data
-    // data/current
-    // data/current/VERSION
-    // data/current/BP-113955101-127.0.0.1-1455743472614
-    // data/current/BP-113955101-127.0.0.1-1455743472614/tmp [empty dir]
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/VERSION
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/rbw [empty dir]
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741825
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741826
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741828_1004.meta
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741828
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741825_1001.meta
-    // data/current/BP-113955101-127.0.0.1-1455743472614/current/finalized/subdir0/subdir0/blk_1073741826_1002.meta
-    //
-    // Note that block files must be placed based on their IDs. 
-    fn generate_datanode_dir(&self, base_path: &str, datanode_idx: u16)
-            -> Result<(), std::io::Error> {
-        println!("** generating datanode dir {} in {}...",
-                 datanode_idx + 1, base_path);
-        for storage_idx in 0..self.config.num_storage_dirs_per_dn {
-            let dir = format!("{}/datanode{:>02}/storage{:>02}/current",
-                 base_path, datanode_idx + 1, storage_idx + 1);
-            let bp_dir = format!("{}/{}", dir, BLOCK_POOL_ID);
-            try!(fs::create_dir_all(&bp_dir));
-            try!(self.write_datanode_version_file(&format!("{}/VERSION", dir),
-                                                 datanode_idx, storage_idx));
-
-            try!(fs::create_dir(format!("{}/tmp", &bp_dir)));
-            let cdir = format!("{}/current", &bp_dir);
-            try!(fs::create_dir(&cdir));
-            try!(fs::create_dir(format!("{}/rbw", cdir)));
-            try!(fs::create_dir(format!("{}/finalized", cdir)));
-            try!(self.write_blockpool_version_file(&format!("{}/VERSION", cdir)));
+    fn fsimage_path(&self) -> String {
+        if self.xml_gzip {
+            return self.path.clone() + "/fsimage_0000000000000000001.xml.gz";
         }
-        println!("** finished generating datanode dir {} in {}...",
-                 datanode_idx + 1, base_path);
-        return Result::Ok(());
+        return self.path.clone() + "/fsimage_0000000000000000001.xml";
     }
 
-    // Write the VERSION file which identifies the version of this datanode storage directory.
-    fn write_datanode_version_file(&self, path: &str,
-                        datanode_idx: u16, storage_idx: u16)
-                        -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        let dn_info = self.datanode_info.get(datanode_idx as usize).unwrap();
-        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
-        try!(write!(w, "storageID={}\n",
-                    dn_info.storage_ids.get(storage_idx as usize).unwrap()));
-        try!(write!(w, "clusterID={}\n", CLUSTER_ID));
-        try!(write!(w, "cTime={}\n", CLUSTER_CTIME));
-        try!(write!(w, "datanodeUuid={}\n", dn_info.datanode_uuid));
-        try!(write!(w, "storageType=DATA_NODE\n"));
-        try!(write!(w, "layoutVersion={}\n", self.config.dn_layout_version));
-        return Result::Ok(());
+    fn binary_fsimage_path(&self) -> String {
+        return self.name_dir() + "/current/fsimage_0000000000000000001";
     }
 
-    fn write_blockpool_version_file(&self, path: &str)
-                        -> Result<(), std::io::Error> {
-        let file = try!(OpenOptions::new().
-            read(false).
-            write(true).
-            create(true).
-            open(path));
-        let mut w = BufWriter::new(&file);
-        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
-        try!(write!(w, "namespaceID={}\n", NAMESPACE_ID));
-        try!(write!(w, "cTime={}\n", CLUSTER_CTIME));
-        try!(write!(w, "blockpoolID={}\n", BLOCK_POOL_ID));
-        try!(write!(w, "layoutVersion={}\n", self.config.dn_layout_version));
-        return Result::Ok(());
+    // Get the directories which we delete before writing: the whole output
+    // directory, or just the directories of the datanodes we are writing.
+    fn replaced_dirs(&self) -> Vec<String> {
+        if self.only_datanodes.is_empty() {
+            return vec![self.path.clone()];
+        }
+        return self.only_datanodes.iter()
+            .map(|idx| format!("{}/datanode{:>02}", self.path, idx + 1)).collect();
     }
-}
 
-// Represents an HDFS INode (directory or file)
-struct INode {
-    id: u32,
-    name: String,
-    is_dir: bool,
-    blocks: Vec<Block>,
-}
-
-impl INode {
-    fn get_type_name(&self) -> &'static str {
-        if self.is_dir {
-            return "DIRECTORY";
-        } else {
-            return "FILE";
+    // Check that the directories we replace can be deleted without --force.
+    fn check_replaceable(&self) -> Result<(), FsgenError> {
+        for dir in self.replaced_dirs() {
+            try!(check_replaceable(&dir));
         }
+        return Result::Ok(());
     }
 
-    pub fn to_xml(&self) -> String {
-        let mut ret = "<inode>".to_owned();
-        ret.push_str(&format!("<id>{}</id>", self.id));
-        ret.push_str(&format!("<type>{}</type>", self.get_type_name()));
-        ret.push_str(&format!("<name>{}</name>", self.name));
-        ret.push_str(&format!("<mtime>{}</mtime>", 0));
-        if self.is_dir {
-            ret.push_str(&format!("<dsquota>{}</dsquota>", -1));
-            ret.push_str(&format!("<nsquota>{}</nsquota>", -1));
-        } else {
-            ret.push_str(&format!("<atime>{}</atime>", 0));
-            ret.push_str(&format!("<replication>{}</replication>",
-                     self.blocks.len()));
-            ret.push_str(&format!("<preferredBlockSize>{}</preferredBlockSize>",
-                     PREFERRED_BLOCK_SIZE));
-        }
-        ret.push_str(&format!("<permission>{}</permission>", "cmccabe:supergroup:0644"));
-        if !self.is_dir {
-            ret.push_str("<blocks>");
-            for block in &self.blocks {
-                ret.push_str(&block.to_xml());
+    // Delete the directories we replace and all their contents, if they exist.
+    fn delete_if_exists(&self) -> Result<(), FsgenError> {
+        for dir in self.replaced_dirs() {
+            if fs::metadata(&dir).is_ok() {
+                try!(fs::remove_dir_all(&dir).map_err(|e| FsgenError::io(&dir, e)));
+                println!("** deleted existing directory {}", dir)
             }
-            ret.push_str("</blocks>");
         }
-        ret.push_str("</inode>");
-        return ret;
+        return Result::Ok(());
     }
 }
 
-struct Block {
-    // The ID of the block
-    id: u32,
-
-    // The genstamp of the block
-    genstamp: u32,
-
-    // The datanodes which have a replica of this block
-    datanodes: Vec<u16>,
-}
-
-impl Block {
-    pub fn to_xml(&self) -> String {
-        let mut ret = "<block>".to_owned();
-        ret.push_str(&format!("<id>{}</id>", self.id));
-        ret.push_str(&format!("<genstamp>{}</genstamp>", self.genstamp));
-        ret.push_str(&format!("<numBytes>{}</numBytes>", 0));
-        ret.push_str("</block>");
-        return ret;
+// Check that a directory can be deleted without --force.  It can if it
+// doesn't exist, is empty, or looks like the output of an earlier fsgen run:
+// either a whole output directory, or the directory of one datanode.
+fn check_replaceable(dir: &str) -> Result<(), FsgenError> {
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Result::Ok(()),
+        Err(e) => return Err(FsgenError::io(dir, e)),
+    };
+    if entries.next().is_none() {
+        return Result::Ok(());
     }
-
-    pub fn generate_block_files(&self, base_path: &str, num_storage_dirs_per_dn: u16)
-            -> Result<(), std::io::Error> {
-        for datanode in &self.datanodes {
-            let storage_idx = ((self.id as u64) * ((datanode + 1) as u64) * 29) %
-                (num_storage_dirs_per_dn as u64);
-            let finalized_base = format!(
-                "{}/datanode{:>02}/storage{:>02}/current/{}/current/finalized",
-                base_path, datanode + 1, storage_idx + 1, BLOCK_POOL_ID);
-            match self.generate_meta_and_block_file(&finalized_base) {
-                Ok(()) => (),
-                Err(e) => {
-                    println!("Failed to generate meta and block file in {}: {}",
-                             finalized_base, e);
-                    return Result::Err(e);
-                },
-            }
+    for marker in [ "name/current/VERSION", "fsimage_0000000000000000001.xml",
+            "fsimage_0000000000000000001.xml.gz" ].iter() {
+        if fs::metadata(format!("{}/{}", dir, marker)).is_ok() {
+            return Result::Ok(());
         }
-        return Ok(());
     }
-
-    pub fn generate_meta_and_block_file(&self,
-                            finalized_base: &str) -> Result<(), std::io::Error> {
-        let subdir = format!("{}/subdir{}/subdir{}",
-            finalized_base, (self.id >> 16) & 0xff, (self.id >> 8) & 0xff);
-        loop {
-            match fs::create_dir_all(&subdir) {
-                Ok(()) => break,
-                // If we get EEXIST, another worker thread already created this
-                // directory or one of its parents while we were attempting to
-                // do so.  We should retry until we no longer get EEXIST, to
-                // ensure that our full path exists (we might have gotten
-                // EEXIST creating a parent rather than the directory we
-                // wanted).
-                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
-                    println!("Got EEXIST on {} for {}", finalized_base, self.id);
-                },
-                Err(e) => return Err(e),
+    // The directory of a single datanode has volumes with VERSION files.
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if fs::metadata(entry.path().join("current/VERSION")).is_ok() {
+                return Result::Ok(());
             }
         }
-        // Write empty block data file 
-        {
-            let data_path = format!("{}/blk_{}",
-                    &subdir, self.id);
-            let file = try!(OpenOptions::new().
-                read(false).
-                write(true).
-                create(true).
-                open(&data_path));
-            let mut w = BufWriter::new(&file);
-            let arr = [ ];
-            try!(w.write(&arr));
-        }
-        // Write block header file
-        {
-            let meta_path = format!("{}/blk_{}_{}.meta",
-                    &subdir, self.id, self.genstamp);
-            let file = try!(OpenOptions::new().
-                read(false).
-                write(true).
-                create(true).
-                open(&meta_path));
-            let arr = [ 0x00u8, 0x01u8, 0x02u8, 0x00u8,
-                0x00u8, 0x02u8, 0x00u8, 0x96u8,
-                0x26u8, 0x34u8, 0x7bu8 ];
-            let mut w = BufWriter::new(&file);
-            try!(w.write(&arr));
-        }
-        return Result::Ok(());
     }
+    return Err(FsgenError::InvalidArgument(format!("Refusing to delete {}, which is not \
+        empty and doesn't look like the output of an earlier fsgen run.  Use --force to \
+        delete it anyway.", dir)));
 }
@@ -0,0 +1,327 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use codec::ImageCodec;
+use config::DATANODE_PORT_STRIDE;
+use error::FsgenError;
+use namespace::FSImage;
+use namespace::LAST_TXID;
+use std::fs::OpenOptions;
+use std::fs;
+use std::io::BufWriter;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+
+// See ConfiguredFailoverProxyProvider
+const FAILOVER_PROXY_PROVIDER : &str =
+    "org.apache.hadoop.hdfs.server.namenode.ha.ConfiguredFailoverProxyProvider";
+
+// How long the DataNodes trust the replica and dfsUsed caches fsgen writes:
+// ten years, rather than the default of a few minutes.
+const REPLICA_CACHE_EXPIRY : &str = "3650d";
+const CACHED_DFS_USED_CHECK_INTERVAL_MS : u64 = 3650 * 86400000;
+
+impl FSImage {
+    // Write Hadoop configuration files for the cluster in base_path/conf:
+    // core-site.xml and hdfs-site.xml for the NameNode and clients, and a
+    // configuration directory for each datanode in a datanodeNN
+    // subdirectory.  The paths in them are under hadoop.install_dir, or
+    // base_path itself if that isn't set.
+    //
+    // A cluster spread over several hosts also gets a topology script.  A
+    // pseudo-distributed one gets scripts in base_path which start and stop
+    // all of its daemons instead.
+    pub fn write_hadoop_conf(&self, base_path: &str) -> Result<(), FsgenError> {
+        let conf_dir = format!("{}/conf", base_path);
+        try!(create_dir(&conf_dir));
+        let install_dir = self.install_dir(base_path);
+        let core_site = self.core_site_properties(&install_dir);
+        try!(write_site_file(&format!("{}/core-site.xml", conf_dir), &core_site));
+        let mut hdfs_site = self.hdfs_site_properties();
+        hdfs_site.push(("dfs.namenode.name.dir".to_owned(),
+                        format!("file://{}/name", install_dir)));
+        // Have the NameNode compress the images it saves the same way.
+        if self.config.image_codec != ImageCodec::None {
+            hdfs_site.push(("dfs.image.compress".to_owned(), "true".to_owned()));
+            hdfs_site.push(("dfs.image.compression.codec".to_owned(),
+                            self.config.image_codec.class_name().to_owned()));
+        }
+        try!(write_site_file(&format!("{}/hdfs-site.xml", conf_dir), &hdfs_site));
+        for datanode_idx in 0..self.datanode_info.len() {
+            let dn_conf_dir = format!("{}/datanode{:>02}", conf_dir, datanode_idx + 1);
+            try!(create_dir(&dn_conf_dir));
+            try!(write_site_file(&format!("{}/core-site.xml", dn_conf_dir), &core_site));
+            try!(write_site_file(&format!("{}/hdfs-site.xml", dn_conf_dir),
+                    &self.datanode_hdfs_site_properties(&install_dir, datanode_idx as u16)));
+        }
+        if self.config.hadoop_pseudo_distributed {
+            try!(write_script(&format!("{}/start-cluster.sh", base_path),
+                              &self.launcher_script(&install_dir, "start")));
+            try!(write_script(&format!("{}/stop-cluster.sh", base_path),
+                              &self.launcher_script(&install_dir, "stop")));
+        } else {
+            try!(write_script(&format!("{}/topology.sh", conf_dir), &self.topology_script()));
+        }
+        return Result::Ok(());
+    }
+
+    fn install_dir(&self, base_path: &str) -> String {
+        if !self.config.hadoop_install_dir.is_empty() {
+            return self.config.hadoop_install_dir.trim_end_matches('/').to_owned();
+        }
+        match fs::canonicalize(base_path) {
+            Ok(path) => return path.to_string_lossy().into_owned(),
+            Err(_) => return base_path.to_owned(),
+        }
+    }
+
+    // Get the host name of a datanode, for the topology script.
+    pub fn datanode_host(&self, datanode_idx: u16) -> String {
+        match self.config.hadoop_datanode_hosts.get(datanode_idx as usize) {
+            Some(host) => return host.clone(),
+            None => return format!("datanode{:>02}", datanode_idx + 1),
+        }
+    }
+
+    // Get the rack of a datanode.  Datanodes are assigned to racks
+    // round-robin.
+    pub fn datanode_rack(&self, datanode_idx: u16) -> String {
+        return format!("/rack{:>02}", (datanode_idx % self.config.num_racks) + 1);
+    }
+
+    fn namenode_rpc_address(&self, host: &str) -> String {
+        return format!("{}:{}", host, self.config.hadoop_namenode_rpc_port);
+    }
+
+    fn is_ha(&self) -> bool {
+        return self.config.hadoop_namenode_hosts.len() > 1;
+    }
+
+    fn core_site_properties(&self, install_dir: &str) -> Vec<(String, String)> {
+        let default_fs = if self.is_ha() {
+            format!("hdfs://{}", self.config.hadoop_nameservice)
+        } else {
+            format!("hdfs://{}", self.namenode_rpc_address(&self.config.hadoop_namenode_hosts[0]))
+        };
+        let mut properties = vec![("fs.defaultFS".to_owned(), default_fs)];
+        // The datanodes of a pseudo-distributed cluster share an address, so
+        // the NameNode can't tell their racks apart.
+        if !self.config.hadoop_pseudo_distributed {
+            properties.push(("net.topology.script.file.name".to_owned(),
+                             format!("{}/conf/topology.sh", install_dir)));
+        }
+        return properties;
+    }
+
+    // Get the hdfs-site.xml properties which the NameNode, the DataNodes and
+    // clients share.
+    fn hdfs_site_properties(&self) -> Vec<(String, String)> {
+        let mut properties = vec![
+            ("dfs.replication".to_owned(), self.config.repl.to_string()),
+            ("dfs.blocksize".to_owned(), self.config.preferred_block_size.to_string()),
+        ];
+        let nameservice = &self.config.hadoop_nameservice;
+        let hosts = &self.config.hadoop_namenode_hosts;
+        if nameservice.is_empty() {
+            properties.push(("dfs.namenode.rpc-address".to_owned(),
+                             self.namenode_rpc_address(&hosts[0])));
+            return properties;
+        }
+        properties.push(("dfs.nameservices".to_owned(), nameservice.clone()));
+        if !self.is_ha() {
+            properties.push((format!("dfs.namenode.rpc-address.{}", nameservice),
+                             self.namenode_rpc_address(&hosts[0])));
+            return properties;
+        }
+        let namenode_ids : Vec<String> = (0..hosts.len())
+            .map(|idx| format!("nn{}", idx + 1)).collect();
+        properties.push((format!("dfs.ha.namenodes.{}", nameservice), namenode_ids.join(",")));
+        for (namenode_id, host) in namenode_ids.iter().zip(hosts.iter()) {
+            properties.push((format!("dfs.namenode.rpc-address.{}.{}", nameservice, namenode_id),
+                             self.namenode_rpc_address(host)));
+        }
+        properties.push((format!("dfs.client.failover.proxy.provider.{}", nameservice),
+                         FAILOVER_PROXY_PROVIDER.to_owned()));
+        properties.push(("dfs.ha.fencing.methods".to_owned(), "shell(/bin/true)".to_owned()));
+        if !self.config.hadoop_shared_edits_dir.is_empty() {
+            properties.push(("dfs.namenode.shared.edits.dir".to_owned(),
+                             self.config.hadoop_shared_edits_dir.clone()));
+        }
+        return properties;
+    }
+
+    // Get the hdfs-site.xml properties of a datanode: the shared ones, its
+    // storage directories with their storage types, and long expiry times
+    // for the replica caches.
+    fn datanode_hdfs_site_properties(&self, install_dir: &str,
+                                     datanode_idx: u16) -> Vec<(String, String)> {
+        let mut properties = self.hdfs_site_properties();
+        let data_dirs : Vec<String> = self.datanode_info[datanode_idx as usize].volumes.iter()
+            .map(|volume| format!("[{}]file://{}/datanode{:>02}/{}", volume.storage_type.name(),
+                                  install_dir, datanode_idx + 1, volume.name)).collect();
+        properties.push(("dfs.datanode.data.dir".to_owned(), data_dirs.join(",")));
+        properties.push(("dfs.datanode.replica.cache.expiry.time".to_owned(),
+                         REPLICA_CACHE_EXPIRY.to_owned()));
+        properties.push(("dfs.datanode.cached-dfsused.check.interval.ms".to_owned(),
+                         CACHED_DFS_USED_CHECK_INTERVAL_MS.to_string()));
+        if self.config.hadoop_pseudo_distributed {
+            let first_port = (self.config.hadoop_datanode_base_port as u64) +
+                (datanode_idx as u64) * DATANODE_PORT_STRIDE;
+            for (offset, key) in [ "dfs.datanode.address", "dfs.datanode.http.address",
+                    "dfs.datanode.ipc.address", "dfs.datanode.https.address" ].iter().enumerate() {
+                properties.push((key.to_string(),
+                                 format!("0.0.0.0:{}", first_port + (offset as u64))));
+            }
+        }
+        return properties;
+    }
+
+    // Get a script which prints the rack of each datanode host it is given.
+    fn topology_script(&self) -> String {
+        let mut script = String::new();
+        script.push_str("#!/bin/sh\n");
+        script.push_str("# Prints the rack of each datanode host given as an argument.\n");
+        script.push_str("for host in \"$@\"; do\n");
+        script.push_str("    case \"$host\" in\n");
+        for datanode_idx in 0..self.datanode_info.len() {
+            script.push_str(&format!("        '{}') echo {} ;;\n",
+                                     self.datanode_host(datanode_idx as u16).replace('\'', ""),
+                                     self.datanode_rack(datanode_idx as u16)));
+        }
+        script.push_str("        *) echo /default-rack ;;\n");
+        script.push_str("    esac\n");
+        script.push_str("done\n");
+        return script;
+    }
+
+    // Get a script which starts or stops the NameNode and every DataNode of a
+    // pseudo-distributed cluster, each with its own configuration, log and
+    // pid directories.  Before starting the NameNode, it converts the XML
+    // fsimage to a binary one if that hasn't been done yet.
+    fn launcher_script(&self, install_dir: &str, action: &str) -> String {
+        let mut daemons : Vec<(String, &str, String)> =
+            vec![("namenode".to_owned(), "namenode", "conf".to_owned())];
+        for datanode_idx in 0..self.datanode_info.len() {
+            let name = format!("datanode{:>02}", datanode_idx + 1);
+            let conf = format!("conf/{}", name);
+            daemons.push((name, "datanode", conf));
+        }
+        if action == "stop" {
+            daemons.reverse();
+        }
+        let mut script = String::new();
+        script.push_str("#!/bin/sh\n");
+        script.push_str(&format!("# {}s the pseudo-distributed HDFS cluster generated by fsgen.  \
+                                 Set\n# HADOOP_HOME to the Hadoop installation to run.\n",
+                                 if action == "start" { "Start" } else { "Stop" }));
+        script.push_str("set -e\n");
+        script.push_str(": \"${HADOOP_HOME:?Set HADOOP_HOME to your Hadoop installation.}\"\n");
+        script.push_str(&format!("cluster='{}'\n", install_dir));
+        script.push('\n');
+        script.push_str("run() {\n");
+        script.push_str("    name=$1\n");
+        script.push_str("    daemon=$2\n");
+        script.push_str("    conf=\"$cluster/$3\"\n");
+        script.push_str("    if [ ! -e \"$conf/log4j.properties\" ]; then\n");
+        script.push_str("        cp \"$HADOOP_HOME/etc/hadoop/log4j.properties\" \"$conf/\"\n");
+        script.push_str("    fi\n");
+        script.push_str("    mkdir -p \"$cluster/logs/$name\" \"$cluster/pids/$name\"\n");
+        script.push_str("    HADOOP_LOG_DIR=\"$cluster/logs/$name\" \
+                         HADOOP_PID_DIR=\"$cluster/pids/$name\" \\\n");
+        script.push_str("        HADOOP_IDENT_STRING=\"$name\" \\\n");
+        script.push_str(&format!("        \"$HADOOP_HOME/sbin/hadoop-daemon.sh\" \
+                                 --config \"$conf\" {} \"$daemon\"\n", action));
+        script.push_str("}\n");
+        script.push('\n');
+        if action == "start" {
+            script.push_str(&format!("image=\"$cluster/name/current/fsimage_{:019}\"\n",
+                                     LAST_TXID));
+            script.push_str("if [ ! -e \"$image\" ]; then\n");
+            if self.config.xml_gzip {
+                // The offline image viewer can't read gzipped XML.
+                script.push_str("    xml=$(mktemp)\n");
+                script.push_str(&format!("    gunzip -c \"$cluster/fsimage_{:019}.xml.gz\" \
+                                         > \"$xml\"\n", LAST_TXID));
+            } else {
+                script.push_str(&format!("    xml=\"$cluster/fsimage_{:019}.xml\"\n",
+                                         LAST_TXID));
+            }
+            script.push_str("    \"$HADOOP_HOME/bin/hdfs\" oiv -p ReverseXML -i \"$xml\" \
+                             -o \"$image\"\n");
+            if self.config.xml_gzip {
+                script.push_str("    rm -f \"$xml\"\n");
+            }
+            script.push_str("fi\n");
+        }
+        for (name, daemon, conf) in daemons {
+            script.push_str(&format!("run {} {} {}\n", name, daemon, conf));
+        }
+        return script;
+    }
+}
+
+fn create_dir(path: &str) -> Result<(), FsgenError> {
+    return fs::create_dir_all(path).map_err(|e| FsgenError::io(path, e));
+}
+
+// Write an executable script.
+fn write_script(path: &str, script: &str) -> Result<(), FsgenError> {
+    let io_error = |e| FsgenError::io(path, e);
+    {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path).map_err(&io_error));
+        let mut w = BufWriter::new(&file);
+        try!(w.write_all(script.as_bytes()).map_err(&io_error));
+    }
+    return fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(&io_error);
+}
+
+// Write a Hadoop configuration file with the given properties.
+fn write_site_file(path: &str, properties: &[(String, String)]) -> Result<(), FsgenError> {
+    return write_site_properties(path, properties).map_err(|e| FsgenError::io(path, e));
+}
+
+fn write_site_properties(path: &str, properties: &[(String, String)])
+                         -> Result<(), std::io::Error> {
+    let file = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    let mut w = BufWriter::new(&file);
+    try!(write!(w, "<?xml version=\"1.0\"?>\n"));
+    try!(write!(w, "<!-- Generated by fsgen. -->\n"));
+    try!(write!(w, "<configuration>\n"));
+    for (name, value) in properties {
+        try!(write!(w, "  <property>\n"));
+        try!(write!(w, "    <name>{}</name>\n", xml_escape(name)));
+        try!(write!(w, "    <value>{}</value>\n", xml_escape(value)));
+        try!(write!(w, "  </property>\n"));
+    }
+    try!(write!(w, "</configuration>\n"));
+    return Result::Ok(());
+}
+
+pub(crate) fn xml_escape(val: &str) -> String {
+    return val.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+}
@@ -0,0 +1,609 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Loads the parts of an existing fsimage which describe the namespace and its
+// blocks, either from
+// the XML written by fsgen and the offline image viewer, which may be
+// gzipped, or from a binary (protobuf) fsimage, which may be compressed.
+
+use codec::ImageCodec;
+use flate2::read::MultiGzDecoder;
+use protobuf::ProtoReader;
+use protobuf::read_delimited;
+use protobuf::read_varint_from;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+// The magic bytes at the start of every binary fsimage.  See
+// FSImageUtil#MAGIC_HEADER
+const BINARY_MAGIC : &[u8] = b"HDFSIMG1";
+
+// The magic bytes at the start of every gzip file.
+const GZIP_MAGIC : &[u8] = b"\x1f\x8b";
+
+// An fsimage loaded from disk.
+pub struct LoadedImage {
+    // The namespace ID from the NameSection
+    pub namespace_id: u64,
+
+    // The last generation stamp which was handed out
+    pub genstamp_v2: u64,
+
+    // The last block ID which was handed out
+    pub last_allocated_block_id: u64,
+
+    // The last inode ID which was handed out
+    pub last_inode_id: u64,
+
+    pub inodes: Vec<LoadedINode>,
+
+    // The children of every directory which has any
+    pub directories: Vec<LoadedDirectory>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LoadedINodeType {
+    File,
+    Directory,
+    Symlink,
+}
+
+impl LoadedINodeType {
+    // Parse the type the way the offline image viewer writes it.
+    fn parse(name: &str) -> Option<LoadedINodeType> {
+        match name {
+            "FILE" => return Some(LoadedINodeType::File),
+            "DIRECTORY" => return Some(LoadedINodeType::Directory),
+            "SYMLINK" => return Some(LoadedINodeType::Symlink),
+            _ => return None,
+        }
+    }
+
+    // Get the type from its number in INodeSection.INode.Type
+    fn from_protobuf(num: u64) -> Option<LoadedINodeType> {
+        match num {
+            1 => return Some(LoadedINodeType::File),
+            2 => return Some(LoadedINodeType::Directory),
+            3 => return Some(LoadedINodeType::Symlink),
+            _ => return None,
+        }
+    }
+}
+
+pub struct LoadedINode {
+    pub id: u64,
+    pub name: String,
+    pub inode_type: LoadedINodeType,
+
+    // The owner, or an empty string if the image doesn't say
+    pub owner: String,
+
+    // The replication factor of a file.  Always 0 for directories.
+    pub replication: u16,
+
+    pub blocks: Vec<LoadedBlock>,
+}
+
+pub struct LoadedBlock {
+    pub id: u64,
+    pub genstamp: u64,
+    pub num_bytes: u64,
+}
+
+pub struct LoadedDirectory {
+    pub parent: u64,
+    pub children: Vec<u64>,
+}
+
+impl LoadedImage {
+    fn new() -> LoadedImage {
+        return LoadedImage {
+            namespace_id: 0,
+            genstamp_v2: 0,
+            last_allocated_block_id: 0,
+            last_inode_id: 0,
+            inodes: vec![],
+            directories: vec![],
+        }
+    }
+}
+
+impl LoadedINode {
+    fn new() -> LoadedINode {
+        return LoadedINode {
+            id: 0,
+            name: "".to_owned(),
+            inode_type: LoadedINodeType::File,
+            owner: "".to_owned(),
+            replication: 0,
+            blocks: vec![],
+        }
+    }
+}
+
+// Load an fsimage, detecting whether it is a binary image, gzipped XML or
+// XML.
+pub fn load_image(path: &str) -> Result<LoadedImage, Error> {
+    let mut file = try!(File::open(path));
+    let mut magic = vec![];
+    try!((&mut file).take(BINARY_MAGIC.len() as u64).read_to_end(&mut magic));
+    try!(file.seek(SeekFrom::Start(0)));
+    if magic == BINARY_MAGIC {
+        return load_binary_image(file);
+    } else if magic.starts_with(GZIP_MAGIC) {
+        return load_xml_image(MultiGzDecoder::new(BufReader::new(file)));
+    } else {
+        return load_xml_image(file);
+    }
+}
+
+fn load_xml_image<R: Read>(r: R) -> Result<LoadedImage, Error> {
+    let mut image = LoadedImage::new();
+    let mut reader = XmlReader::new(BufReader::new(r));
+    let mut path : Vec<String> = vec![];
+    let mut inode = LoadedINode::new();
+    let mut block = LoadedBlock { id: 0, genstamp: 0, num_bytes: 0 };
+    let mut directory = LoadedDirectory { parent: 0, children: vec![] };
+    while let Some(event) = try!(reader.next()) {
+        match event {
+            XmlEvent::Start(name) => path.push(name),
+            XmlEvent::End(name) => {
+                if path.pop().as_ref() != Some(&name) {
+                    return Err(invalid_xml(&format!("unexpected </{}>", name)));
+                }
+                if path.len() == 2 && path[1] == "INodeSection" && name == "inode" {
+                    image.inodes.push(inode);
+                    inode = LoadedINode::new();
+                } else if path.len() == 4 && path[1] == "INodeSection" && name == "block" {
+                    inode.blocks.push(block);
+                    block = LoadedBlock { id: 0, genstamp: 0, num_bytes: 0 };
+                } else if path.len() == 2 && path[1] == "INodeDirectorySection" &&
+                        name == "directory" {
+                    image.directories.push(directory);
+                    directory = LoadedDirectory { parent: 0, children: vec![] };
+                }
+            },
+            XmlEvent::Text(text) => {
+                let p : Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+                match &p[..] {
+                    ["fsimage", "NameSection", "namespaceId"] =>
+                        image.namespace_id = try!(parse_xml_u64(&text)),
+                    ["fsimage", "NameSection", "genstampV2"] =>
+                        image.genstamp_v2 = try!(parse_xml_u64(&text)),
+                    ["fsimage", "NameSection", "lastAllocatedBlockId"] =>
+                        image.last_allocated_block_id = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeSection", "lastInodeId"] =>
+                        image.last_inode_id = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeSection", "inode", "id"] =>
+                        inode.id = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeSection", "inode", "type"] =>
+                        inode.inode_type = match LoadedINodeType::parse(&text) {
+                            Some(inode_type) => inode_type,
+                            None => return Err(invalid_xml(
+                                &format!("unknown inode type '{}'", text))),
+                        },
+                    // The permission is owner:group:mode
+                    ["fsimage", "INodeSection", "inode", "permission"] =>
+                        inode.owner = text.split(':').next().unwrap_or("").to_owned(),
+                    ["fsimage", "INodeSection", "inode", "name"] => inode.name = text,
+                    ["fsimage", "INodeSection", "inode", "replication"] =>
+                        inode.replication = try!(parse_xml_u64(&text)) as u16,
+                    ["fsimage", "INodeSection", "inode", "blocks", "block", "id"] =>
+                        block.id = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeSection", "inode", "blocks", "block", "genstamp"] =>
+                        block.genstamp = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeSection", "inode", "blocks", "block", "numBytes"] =>
+                        block.num_bytes = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeDirectorySection", "directory", "parent"] =>
+                        directory.parent = try!(parse_xml_u64(&text)),
+                    ["fsimage", "INodeDirectorySection", "directory", "child"] =>
+                        directory.children.push(try!(parse_xml_u64(&text))),
+                    _ => (),
+                }
+            },
+        }
+    }
+    return Result::Ok(image);
+}
+
+fn parse_xml_u64(text: &str) -> Result<u64, Error> {
+    match text.trim().parse::<u64>() {
+        Ok(val) => return Result::Ok(val),
+        Err(_) => return Err(invalid_xml(&format!("expected an integer, got '{}'", text))),
+    }
+}
+
+fn invalid_xml(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("invalid fsimage XML: {}", message));
+}
+
+// The binary fsimage is a magic header, followed by a series of sections, and
+// then a FileSummary message giving the offset and length of each section.
+// The last 4 bytes of the file hold the length of the FileSummary.  See
+// FSImageFormatProtobuf.
+fn load_binary_image(mut file: File) -> Result<LoadedImage, Error> {
+    let file_len = try!(file.metadata()).len();
+    if file_len < (BINARY_MAGIC.len() as u64) + 4 {
+        return Err(invalid_binary("the file is too short"));
+    }
+    try!(file.seek(SeekFrom::Start(file_len - 4)));
+    let mut len_buf = [0u8; 4];
+    try!(file.read_exact(&mut len_buf));
+    let summary_len = ((len_buf[0] as u64) << 24) | ((len_buf[1] as u64) << 16) |
+        ((len_buf[2] as u64) << 8) | (len_buf[3] as u64);
+    if summary_len + 4 > file_len {
+        return Err(invalid_binary("bad FileSummary length"));
+    }
+    try!(file.seek(SeekFrom::Start(file_len - 4 - summary_len)));
+    let summary = try!(read_delimited(&mut file));
+
+    // Find the sections we care about, and the codec which compressed them.
+    let mut codec = ImageCodec::None;
+    let mut ns_info = None;
+    let mut inodes = None;
+    let mut directories = None;
+    let mut string_table = None;
+    let mut fields = ProtoReader::new(&summary);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        match num {
+            3 => {
+                let class_name = try!(val.as_string());
+                codec = match ImageCodec::from_class_name(&class_name) {
+                    Some(codec) => codec,
+                    None => return Err(invalid_binary(
+                        &format!("unsupported compression codec {}", class_name))),
+                };
+            },
+            4 => {
+                let mut name = "".to_owned();
+                let mut length = 0;
+                let mut offset = 0;
+                let mut sfields = ProtoReader::new(try!(val.as_bytes()));
+                while let Some((snum, sval)) = try!(sfields.next_field()) {
+                    match snum {
+                        1 => name = try!(sval.as_string()),
+                        2 => length = try!(sval.as_u64()),
+                        3 => offset = try!(sval.as_u64()),
+                        _ => (),
+                    }
+                }
+                match name.as_ref() {
+                    "NS_INFO" => ns_info = Some((offset, length)),
+                    "INODE" => inodes = Some((offset, length)),
+                    "INODE_DIR" => directories = Some((offset, length)),
+                    "STRING_TABLE" => string_table = Some((offset, length)),
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+    let mut image = LoadedImage::new();
+    match ns_info {
+        None => return Err(invalid_binary("no NS_INFO section")),
+        Some((offset, length)) => {
+            try!(file.seek(SeekFrom::Start(offset)));
+            if codec == ImageCodec::None {
+                try!(load_name_system_section(&mut file, &mut image));
+            } else {
+                let section = try!(read_compressed_section(&mut file, length, codec));
+                try!(load_name_system_section(&mut &section[..], &mut image));
+            }
+        },
+    }
+    // The owners of the inodes are IDs in the string table.
+    let mut strings = HashMap::new();
+    if let Some((offset, length)) = string_table {
+        try!(file.seek(SeekFrom::Start(offset)));
+        let section = if codec == ImageCodec::None {
+            let mut section = vec![];
+            try!((&mut file).take(length).read_to_end(&mut section));
+            section
+        } else {
+            try!(read_compressed_section(&mut file, length, codec))
+        };
+        strings = try!(load_string_table_section(&section));
+    }
+    match inodes {
+        None => return Err(invalid_binary("no INODE section")),
+        Some((offset, length)) => {
+            try!(file.seek(SeekFrom::Start(offset)));
+            if codec == ImageCodec::None {
+                try!(load_inode_section(&mut BufReader::new(&mut file), &strings,
+                                        &mut image));
+            } else {
+                let section = try!(read_compressed_section(&mut file, length, codec));
+                try!(load_inode_section(&mut &section[..], &strings, &mut image));
+            }
+        },
+    }
+    // Images without directories may leave this section out.
+    if let Some((offset, length)) = directories {
+        try!(file.seek(SeekFrom::Start(offset)));
+        let section = if codec == ImageCodec::None {
+            let mut section = vec![];
+            try!((&mut file).take(length).read_to_end(&mut section));
+            section
+        } else {
+            try!(read_compressed_section(&mut file, length, codec))
+        };
+        try!(load_inode_directory_section(&section, &mut image));
+    }
+    return Result::Ok(image);
+}
+
+// Read a compressed section of a binary fsimage, starting at the current
+// position of file, and decompress it.  Each section is compressed
+// separately.
+fn read_compressed_section(file: &mut File, length: u64, codec: ImageCodec)
+                           -> Result<Vec<u8>, Error> {
+    let mut compressed = vec![];
+    try!(file.take(length).read_to_end(&mut compressed));
+    if (compressed.len() as u64) < length {
+        return Err(invalid_binary("a section is truncated"));
+    }
+    return codec.decompress(&compressed);
+}
+
+fn load_name_system_section(r: &mut Read, image: &mut LoadedImage) -> Result<(), Error> {
+    let msg = try!(read_delimited(r));
+    let mut fields = ProtoReader::new(&msg);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        match num {
+            1 => image.namespace_id = try!(val.as_u64()),
+            3 => image.genstamp_v2 = try!(val.as_u64()),
+            5 => image.last_allocated_block_id = try!(val.as_u64()),
+            _ => (),
+        }
+    }
+    return Result::Ok(());
+}
+
+fn load_inode_section(r: &mut Read, strings: &HashMap<u64, String>,
+                      image: &mut LoadedImage) -> Result<(), Error> {
+    let header = try!(read_delimited(r));
+    let mut num_inodes = 0;
+    let mut fields = ProtoReader::new(&header);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        match num {
+            1 => image.last_inode_id = try!(val.as_u64()),
+            2 => num_inodes = try!(val.as_u64()),
+            _ => (),
+        }
+    }
+    for _ in 0..num_inodes {
+        let msg = try!(read_delimited(r));
+        let mut inode = LoadedINode::new();
+        let mut permission = None;
+        let mut fields = ProtoReader::new(&msg);
+        while let Some((num, val)) = try!(fields.next_field()) {
+            match num {
+                1 => {
+                    let num = try!(val.as_u64());
+                    inode.inode_type = match LoadedINodeType::from_protobuf(num) {
+                        Some(inode_type) => inode_type,
+                        None => return Err(invalid_binary(
+                            &format!("unknown inode type {}", num))),
+                    };
+                },
+                2 => inode.id = try!(val.as_u64()),
+                3 => inode.name = try!(val.as_string()),
+                4 => permission = try!(load_inode_file(try!(val.as_bytes()), &mut inode)),
+                // INodeDirectory and INodeSymlink
+                5 => permission = try!(find_fixed64_field(try!(val.as_bytes()), 4)),
+                6 => permission = try!(find_fixed64_field(try!(val.as_bytes()), 1)),
+                _ => (),
+            }
+        }
+        // The owner is the top 24 bits of the permission.  See
+        // FSImageFormatPBINode#loadPermission
+        if let Some(permission) = permission {
+            if let Some(owner) = strings.get(&(permission >> 40)) {
+                inode.owner = owner.clone();
+            }
+        }
+        image.inodes.push(inode);
+    }
+    return Result::Ok(());
+}
+
+// Load an INodeFile message, returning its permission.
+fn load_inode_file(msg: &[u8], inode: &mut LoadedINode) -> Result<Option<u64>, Error> {
+    let mut permission = None;
+    let mut fields = ProtoReader::new(msg);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        match num {
+            1 => inode.replication = try!(val.as_u64()) as u16,
+            5 => permission = Some(try!(val.as_u64())),
+            6 => {
+                let mut block = LoadedBlock { id: 0, genstamp: 0, num_bytes: 0 };
+                let mut bfields = ProtoReader::new(try!(val.as_bytes()));
+                while let Some((bnum, bval)) = try!(bfields.next_field()) {
+                    match bnum {
+                        1 => block.id = try!(bval.as_u64()),
+                        2 => block.genstamp = try!(bval.as_u64()),
+                        3 => block.num_bytes = try!(bval.as_u64()),
+                        _ => (),
+                    }
+                }
+                inode.blocks.push(block);
+            },
+            _ => (),
+        }
+    }
+    return Result::Ok(permission);
+}
+
+// Find the value of an integer field of a message.
+fn find_fixed64_field(msg: &[u8], field_num: u32) -> Result<Option<u64>, Error> {
+    let mut fields = ProtoReader::new(msg);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        if num == field_num {
+            return Result::Ok(Some(try!(val.as_u64())));
+        }
+    }
+    return Result::Ok(None);
+}
+
+// The STRING_TABLE section is a StringTableSection message giving the number
+// of entries, followed by an Entry message for each.
+fn load_string_table_section(section: &[u8]) -> Result<HashMap<u64, String>, Error> {
+    let mut r = section;
+    let header = try!(read_delimited(&mut r));
+    let mut num_entries = 0;
+    let mut fields = ProtoReader::new(&header);
+    while let Some((num, val)) = try!(fields.next_field()) {
+        if num == 1 {
+            num_entries = try!(val.as_u64());
+        }
+    }
+    let mut strings = HashMap::new();
+    for _ in 0..num_entries {
+        let msg = try!(read_delimited(&mut r));
+        let mut id = 0;
+        let mut string = "".to_owned();
+        let mut fields = ProtoReader::new(&msg);
+        while let Some((num, val)) = try!(fields.next_field()) {
+            match num {
+                1 => id = try!(val.as_u64()),
+                2 => string = try!(val.as_string()),
+                _ => (),
+            }
+        }
+        strings.insert(id, string);
+    }
+    return Result::Ok(strings);
+}
+
+// The INODE_DIR section is a series of DirEntry messages, each with the ID of
+// a directory and the packed IDs of its children.
+fn load_inode_directory_section(section: &[u8], image: &mut LoadedImage)
+                                -> Result<(), Error> {
+    let mut r = section;
+    while !r.is_empty() {
+        let msg = try!(read_delimited(&mut r));
+        let mut directory = LoadedDirectory { parent: 0, children: vec![] };
+        let mut fields = ProtoReader::new(&msg);
+        while let Some((num, val)) = try!(fields.next_field()) {
+            match num {
+                1 => directory.parent = try!(val.as_u64()),
+                2 => {
+                    let mut children = try!(val.as_bytes());
+                    while let Some(child) = try!(read_varint_from(&mut children)) {
+                        directory.children.push(child);
+                    }
+                },
+                _ => (),
+            }
+        }
+        image.directories.push(directory);
+    }
+    return Result::Ok(());
+}
+
+fn invalid_binary(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("invalid binary fsimage: {}", message));
+}
+
+enum XmlEvent {
+    Start(String),
+    End(String),
+    Text(String),
+}
+
+// A minimal streaming XML reader.  It handles the subset of XML that the
+// offline image viewer writes: elements and text, with no attributes.
+// Declarations, comments and whitespace between elements are skipped.
+struct XmlReader<R: BufRead> {
+    r: R,
+    buf: Vec<u8>,
+    in_tag: bool,
+    pending_end: Option<String>,
+}
+
+impl<R: BufRead> XmlReader<R> {
+    fn new(r: R) -> XmlReader<R> {
+        return XmlReader {
+            r: r,
+            buf: vec![],
+            in_tag: false,
+            pending_end: None,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<XmlEvent>, Error> {
+        loop {
+            if let Some(name) = self.pending_end.take() {
+                return Result::Ok(Some(XmlEvent::End(name)));
+            }
+            self.buf.clear();
+            if !self.in_tag {
+                if try!(self.r.read_until(b'<', &mut self.buf)) == 0 {
+                    return Result::Ok(None);
+                }
+                if self.buf.last() == Some(&b'<') {
+                    self.buf.pop();
+                    self.in_tag = true;
+                }
+                let text = String::from_utf8_lossy(&self.buf).into_owned();
+                if text.trim().is_empty() {
+                    continue;
+                }
+                return Result::Ok(Some(XmlEvent::Text(unescape(&text))));
+            }
+            if try!(self.r.read_until(b'>', &mut self.buf)) == 0 ||
+                    self.buf.last() != Some(&b'>') {
+                return Err(invalid_xml("unterminated tag"));
+            }
+            self.buf.pop();
+            self.in_tag = false;
+            let tag = String::from_utf8_lossy(&self.buf).into_owned();
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                return Result::Ok(Some(XmlEvent::End(name.trim().to_owned())));
+            }
+            let self_closing = tag.ends_with('/');
+            let name = tag.trim_end_matches('/').split_whitespace().next()
+                .unwrap_or("").to_owned();
+            if self_closing {
+                self.pending_end = Some(name.clone());
+            }
+            return Result::Ok(Some(XmlEvent::Start(name)));
+        }
+    }
+}
+
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_owned();
+    }
+    return text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+}
@@ -0,0 +1,165 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The progress journal of a datanode directory, which lets a run that died
+// part way through writing the block files be resumed.  It looks like this:
+//
+// fsgen-progress 1
+// fingerprint 0b5e0f3d2c5b2ee2d1a0e1cb48c1f1e9
+// subdir 0 subdir0/subdir0
+// subdir 1 subdir0/subdir0
+// subdir 0 subdir0/subdir1
+//
+// The fingerprint identifies the replicas which belong in the directory, so
+// that we never resume with different settings.  Each subdir line is added
+// once every replica in that finalized subdir of a volume has been written.
+
+use error::FsgenError;
+use std::collections::HashSet;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Mutex;
+
+// The name of the journal file in each datanode directory.
+pub const JOURNAL_FILE_NAME : &str = "fsgen_progress";
+
+const JOURNAL_HEADER : &str = "fsgen-progress 1";
+
+pub struct ProgressJournal {
+    path: String,
+
+    // The finalized subdirs which were complete when the journal was
+    // opened, as (storage index, subdir) pairs
+    completed: HashSet<(u16, String)>,
+
+    file: Mutex<File>,
+}
+
+impl ProgressJournal {
+    // Start a new, empty journal at path.
+    pub fn create(path: &str, fingerprint: &str) -> Result<ProgressJournal, FsgenError> {
+        let mut file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path).map_err(|e| FsgenError::io(path, e)));
+        try!(write!(file, "{}\nfingerprint {}\n", JOURNAL_HEADER, fingerprint)
+             .map_err(|e| FsgenError::io(path, e)));
+        return Result::Ok(ProgressJournal {
+            path: path.to_owned(),
+            completed: HashSet::new(),
+            file: Mutex::new(file),
+        });
+    }
+
+    // Open an existing journal so that more subdirs can be added to it.  The
+    // journal must have been written for the same replicas.
+    pub fn open(path: &str, fingerprint: &str) -> Result<ProgressJournal, FsgenError> {
+        let (completed, torn) = try!(read_journal(path, fingerprint));
+        let mut file = try!(OpenOptions::new().
+            append(true).
+            open(path).map_err(|e| FsgenError::io(path, e)));
+        // Finish off a line which was cut short, so that it doesn't run into
+        // the next one.
+        if torn {
+            try!(write!(file, "\n").map_err(|e| FsgenError::io(path, e)));
+        }
+        return Result::Ok(ProgressJournal {
+            path: path.to_owned(),
+            completed: completed,
+            file: Mutex::new(file),
+        });
+    }
+
+    // Check whether a finalized subdir of a volume was complete when the
+    // journal was opened.
+    pub fn is_complete(&self, storage_idx: u16, subdir: &str) -> bool {
+        return self.completed.contains(&(storage_idx, subdir.to_owned()));
+    }
+
+    pub fn num_completed(&self) -> usize {
+        return self.completed.len();
+    }
+
+    // Record that every replica in a finalized subdir of a volume has been
+    // written.
+    pub fn record(&self, storage_idx: u16, subdir: &str) -> Result<(), FsgenError> {
+        let mut file = self.file.lock().unwrap();
+        try!(write!(file, "subdir {} {}\n", storage_idx, subdir)
+             .map_err(|e| FsgenError::io(&self.path, e)));
+        return Result::Ok(());
+    }
+}
+
+// Check that the journal at path was written for the replicas with the
+// given fingerprint, without opening it for writing.
+pub fn check_journal(path: &str, fingerprint: &str) -> Result<(), FsgenError> {
+    try!(read_journal(path, fingerprint));
+    return Result::Ok(());
+}
+
+// Read the completed subdirs from the journal at path, and whether its last
+// line was cut short by a run which died while writing it.
+fn read_journal(path: &str, fingerprint: &str)
+                -> Result<(HashSet<(u16, String)>, bool), FsgenError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FsgenError::InvalidArgument(format!("{} doesn't exist, so the \
+                directory it belongs to can't be resumed.  Run without --resume to start \
+                again.", path)));
+        },
+        Err(e) => return Err(FsgenError::io(path, e)),
+    };
+    let mut text = String::new();
+    try!(BufReader::new(file).read_to_string(&mut text).map_err(|e| FsgenError::io(path, e)));
+    // Only whole lines count.  A line which was cut short could name the
+    // wrong subdir.
+    let whole_len = match text.rfind('\n') {
+        Some(idx) => idx + 1,
+        None => 0,
+    };
+    let lines : Vec<&str> = text[..whole_len].lines().collect();
+    if lines.len() < 2 || lines[0] != JOURNAL_HEADER ||
+            !lines[1].starts_with("fingerprint ") {
+        return Err(FsgenError::io(path, Error::new(ErrorKind::InvalidData,
+                                                   "not an fsgen progress journal")));
+    }
+    if lines[1]["fingerprint ".len()..] != *fingerprint {
+        return Err(FsgenError::InvalidArgument(format!("{} was written for different \
+            replicas.  Resume with the same seed and settings as the first run, or run \
+            without --resume to start again.", path)));
+    }
+    let mut completed = HashSet::new();
+    for line in &lines[2..] {
+        let fields : Vec<&str> = line.split(' ').collect();
+        if fields.len() != 3 || fields[0] != "subdir" {
+            continue;
+        }
+        if let Ok(storage_idx) = fields[1].parse::<u16>() {
+            completed.insert((storage_idx, fields[2].to_owned()));
+        }
+    }
+    return Result::Ok((completed, whole_len < text.len()));
+}
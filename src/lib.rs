@@ -0,0 +1,82 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// fsgen generates large HDFS fsimages, and the NameNode and DataNode
+// directories which go with them, for testing purposes.
+//
+// A cluster is described by a `ClusterSpec`.  Generating it produces an
+// in-memory `FSImage`, which can be inspected before any of the output
+// writers put it on disk:
+//
+//     use fsgen::ClusterSpec;
+//
+//     let fsimage = ClusterSpec::new()
+//         .num_datanodes(4)
+//         .num_inodes(1000)
+//         .replication(3)
+//         .seed(123)
+//         .generate()
+//         .unwrap();
+//     assert_eq!(fsimage.num_inodes(), 1001);
+//     fsimage.write_namenode_dir("/tmp/foo/name").unwrap();
+//     fsimage.write_xml("/tmp/foo/fsimage_0000000000000000001.xml").unwrap();
+//     fsimage.write_datanode_dirs("/tmp/foo").unwrap();
+
+extern crate byteorder;
+extern crate crossbeam;
+extern crate flate2;
+extern crate libc;
+extern crate lz4_flex;
+extern crate md5;
+extern crate rand;
+extern crate snap;
+extern crate toml_edit;
+extern crate uuid;
+
+pub mod codec;
+pub mod config;
+pub mod datanode;
+pub mod error;
+pub mod fault;
+pub mod hadoop;
+pub mod image;
+pub mod journal;
+pub mod manifest;
+pub mod namenode;
+pub mod naming;
+pub mod namespace;
+mod parallel;
+pub mod pbimage;
+pub mod progress;
+mod protobuf;
+pub mod replication;
+pub mod spec;
+pub mod stats;
+pub mod template;
+pub mod verify;
+pub mod volume;
+pub mod xml;
+
+pub use config::Config;
+pub use config::ConfigError;
+pub use error::FsgenError;
+pub use namespace::Block;
+pub use namespace::FSImage;
+pub use namespace::INode;
+pub use spec::ClusterSpec;
+pub use verify::verify_output_dir;
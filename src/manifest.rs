@@ -0,0 +1,502 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// A manifest lists the paths of the namespace to generate, instead of the
+// synthetic directory tree.  Each line holds a path, followed by optional
+// key=value settings for it:
+//
+// # Hourly partitions of a table, and some home directories.
+// /data/{2024-01-01..2024-01-07}/{00..23}/part-{00000..00019}.parquet size=64M..256M
+// /user/{alice,bob}/ owner=hdfs
+// /tmp/big.bin size=10G replication=2 owner=alice
+//
+// A path which ends in / is a directory, and anything else is a file.
+// Parent directories are created as needed.  Braces in a path expand it into
+// one path for each value they describe:
+//
+//   {a,b,c}                   each of the strings
+//   {0..99}                   each number in the range.  {00..99} pads the
+//                             numbers to the width of the first one
+//   {2024-01-01..2024-01-31}  each date in the range
+//
+// The settings are:
+//
+//   size         the length of a file in bytes, with an optional K, M, G or
+//                T suffix.  A range like 64M..256M picks a random length in
+//                it.  Defaults to files.min_size..files.max_size
+//   replication  the replication factor of a file.  Defaults to the first
+//                [[replication]] rule which matches the path, then
+//                files.replication_weights, then files.replication
+//   owner        the owner of the file or directory.  Defaults to cmccabe
+//
+// Blank lines and lines starting with # are ignored.  Paths can't contain
+// whitespace.
+
+use config::ConfigError;
+use namespace::Block;
+use namespace::FSImage;
+use namespace::INode;
+use namespace::ROOT_INODE_ID;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use xml::civil_from_days;
+
+pub struct Manifest {
+    path: String,
+    entries: Vec<ManifestEntry>,
+}
+
+// A line of the manifest.
+struct ManifestEntry {
+    line: usize,
+    pattern: Vec<PatternPart>,
+    is_dir: bool,
+
+    // The smallest and largest length of a file, if set
+    size: Option<(u64, u64)>,
+
+    replication: Option<u16>,
+    owner: Option<String>,
+}
+
+// A piece of a path pattern.
+enum PatternPart {
+    Literal(String),
+    Choices(Vec<String>),
+
+    // count numbers starting at first, zero-padded to width digits
+    Numbers { first: u64, count: u64, width: usize },
+
+    // count dates starting at first_day, in days since the epoch
+    Dates { first_day: i64, count: u64 },
+}
+
+impl PatternPart {
+    // The number of values this part can take.
+    fn len(&self) -> u64 {
+        match *self {
+            PatternPart::Literal(_) => return 1,
+            PatternPart::Choices(ref choices) => return choices.len() as u64,
+            PatternPart::Numbers { count, .. } => return count,
+            PatternPart::Dates { count, .. } => return count,
+        }
+    }
+
+    // Append value idx of this part to path.
+    fn push_value(&self, idx: u64, path: &mut String) {
+        match *self {
+            PatternPart::Literal(ref text) => path.push_str(text),
+            PatternPart::Choices(ref choices) => path.push_str(&choices[idx as usize]),
+            PatternPart::Numbers { first, width, .. } =>
+                path.push_str(&format!("{:0width$}", first + idx, width = width)),
+            PatternPart::Dates { first_day, .. } => {
+                let (year, month, day) = civil_from_days(first_day + idx as i64);
+                path.push_str(&format!("{:04}-{:02}-{:02}", year, month, day));
+            },
+        }
+    }
+}
+
+impl ManifestEntry {
+    // The number of paths this entry expands to.
+    fn num_paths(&self) -> u64 {
+        return self.pattern.iter().fold(1u64, |total, part| total.saturating_mul(part.len()));
+    }
+
+    // Call f on each path this entry expands to, in order.
+    fn for_each_path<F, E>(&self, mut f: F) -> Result<(), E>
+            where F: FnMut(&str) -> Result<(), E> {
+        if self.num_paths() == 0 {
+            return Result::Ok(());
+        }
+        // Count through the values of the parts like an odometer, with the
+        // last part turning fastest.
+        let mut idx = vec![0u64; self.pattern.len()];
+        let mut path = String::new();
+        loop {
+            path.clear();
+            for (part, part_idx) in self.pattern.iter().zip(idx.iter()) {
+                part.push_value(*part_idx, &mut path);
+            }
+            try!(f(&path));
+            let mut pos = idx.len();
+            loop {
+                if pos == 0 {
+                    return Result::Ok(());
+                }
+                pos = pos - 1;
+                idx[pos] = idx[pos] + 1;
+                if idx[pos] < self.pattern[pos].len() {
+                    break;
+                }
+                idx[pos] = 0;
+            }
+        }
+    }
+}
+
+impl Manifest {
+    // Load the manifest at path.
+    pub fn load(path: &str) -> Result<Manifest, ConfigError> {
+        let mut text = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(e) => return Err(ConfigError::new(Some(path.to_owned()),
+                        format!("failed to read manifest: {}", e))),
+        }
+        return Manifest::parse(path, &text);
+    }
+
+    // Parse the text of a manifest.  path is only used in error messages.
+    pub fn parse(path: &str, text: &str) -> Result<Manifest, ConfigError> {
+        let mut entries = vec![];
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = try!(parse_entry(line, idx + 1).map_err(|e| {
+                ConfigError::new(Some(format!("{}:{}", path, idx + 1)), e)
+            }));
+            entries.push(entry);
+        }
+        return Result::Ok(Manifest {
+            path: path.to_owned(),
+            entries: entries,
+        });
+    }
+
+    // The number of paths in the manifest, once their patterns are
+    // expanded.  This doesn't count the parent directories which are created
+    // along the way.
+    pub fn num_paths(&self) -> u64 {
+        return self.entries.iter()
+            .fold(0u64, |total, entry| total.saturating_add(entry.num_paths()));
+    }
+
+    fn error(&self, entry: &ManifestEntry, message: String) -> ConfigError {
+        return ConfigError::new(Some(format!("{}:{}", self.path, entry.line)), message);
+    }
+}
+
+fn parse_entry(line: &str, line_num: usize) -> Result<ManifestEntry, String> {
+    let mut fields = line.split_whitespace();
+    let path = fields.next().unwrap();
+    if !path.starts_with('/') {
+        return Err(format!("{} is not an absolute path", path));
+    }
+    let mut entry = ManifestEntry {
+        line: line_num,
+        pattern: try!(parse_pattern(path)),
+        is_dir: path.ends_with('/'),
+        size: None,
+        replication: None,
+        owner: None,
+    };
+    for field in fields {
+        let (key, val) = match field.find('=') {
+            Some(idx) => (&field[..idx], &field[(idx + 1)..]),
+            None => return Err(format!("expected key=value, got {}", field)),
+        };
+        match key {
+            "size" => {
+                if entry.is_dir {
+                    return Err("a directory can't have a size".to_owned());
+                }
+                entry.size = Some(try!(parse_size_range(val)));
+            },
+            "replication" => {
+                if entry.is_dir {
+                    return Err("a directory can't have a replication factor".to_owned());
+                }
+                entry.replication = match val.parse::<u16>() {
+                    Ok(repl) if repl > 0 => Some(repl),
+                    _ => return Err(format!("invalid replication factor {}", val)),
+                };
+            },
+            "owner" => {
+                if val.is_empty() {
+                    return Err("the owner can't be empty".to_owned());
+                }
+                if val.contains(':') {
+                    return Err(format!("{} is not a valid owner", val));
+                }
+                entry.owner = Some(val.to_owned());
+            },
+            _ => return Err(format!("unknown setting {}; expected size, replication or \
+                                    owner", key)),
+        }
+    }
+    return Result::Ok(entry);
+}
+
+// Split a path into literal text and brace expressions.
+fn parse_pattern(path: &str) -> Result<Vec<PatternPart>, String> {
+    let mut parts = vec![];
+    let mut rest = path;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(PatternPart::Literal(rest[..start].to_owned()));
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unterminated {{ in {}", path)),
+        };
+        parts.push(try!(parse_braces(&rest[(start + 1)..end])));
+        rest = &rest[(end + 1)..];
+    }
+    if rest.contains('}') {
+        return Err(format!("unexpected }} in {}", path));
+    }
+    if !rest.is_empty() {
+        parts.push(PatternPart::Literal(rest.to_owned()));
+    }
+    return Result::Ok(parts);
+}
+
+// Parse the inside of a brace expression: a list, or a range of numbers or
+// dates.
+fn parse_braces(expr: &str) -> Result<PatternPart, String> {
+    if let Some(idx) = expr.find("..") {
+        let (first, last) = (&expr[..idx], &expr[(idx + 2)..]);
+        if let (Ok(first_num), Ok(last_num)) = (first.parse::<u64>(), last.parse::<u64>()) {
+            if first_num > last_num {
+                return Err(format!("the range {{{}}} is backwards", expr));
+            }
+            let width = if first.len() > 1 && first.starts_with('0') { first.len() } else { 0 };
+            return Result::Ok(PatternPart::Numbers {
+                first: first_num,
+                count: last_num - first_num + 1,
+                width: width,
+            });
+        }
+        if let (Some(first_day), Some(last_day)) = (parse_date(first), parse_date(last)) {
+            if first_day > last_day {
+                return Err(format!("the range {{{}}} is backwards", expr));
+            }
+            return Result::Ok(PatternPart::Dates {
+                first_day: first_day,
+                count: (last_day - first_day + 1) as u64,
+            });
+        }
+        return Err(format!("{{{}}} is not a range of numbers or yyyy-mm-dd dates", expr));
+    }
+    if expr.contains(',') {
+        return Result::Ok(PatternPart::Choices(expr.split(',').map(|s| s.to_owned()).collect()));
+    }
+    return Err(format!("{{{}}} should be a list like {{a,b}} or a range like {{1..9}}", expr));
+}
+
+// Parse a yyyy-mm-dd date into days since the epoch.
+pub(crate) fn parse_date(text: &str) -> Option<i64> {
+    let fields : Vec<&str> = text.split('-').collect();
+    if fields.len() != 3 || fields[0].len() != 4 || fields[1].len() != 2 ||
+            fields[2].len() != 2 {
+        return None;
+    }
+    let year = match fields[0].parse::<i64>() { Ok(v) => v, Err(_) => return None };
+    let month = match fields[1].parse::<i64>() { Ok(v) => v, Err(_) => return None };
+    let day = match fields[2].parse::<i64>() { Ok(v) => v, Err(_) => return None };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // Reject dates like 2023-02-30, which don't come back the same.
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    return Some(days);
+}
+
+// See Howard Hinnant's days_from_civil algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+// Parse a size like 128M, or a range of sizes like 64M..256M.
+fn parse_size_range(text: &str) -> Result<(u64, u64), String> {
+    let (min, max) = match text.find("..") {
+        Some(idx) => (try!(parse_size(&text[..idx])), try!(parse_size(&text[(idx + 2)..]))),
+        None => {
+            let size = try!(parse_size(text));
+            (size, size)
+        },
+    };
+    if min > max {
+        return Err(format!("the size range {} is backwards", text));
+    }
+    return Result::Ok((min, max));
+}
+
+// Parse a number of bytes, with an optional K, M, G or T suffix.
+fn parse_size(text: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match text.chars().last() {
+        Some('K') | Some('k') => (&text[..(text.len() - 1)], 1u64 << 10),
+        Some('M') | Some('m') => (&text[..(text.len() - 1)], 1u64 << 20),
+        Some('G') | Some('g') => (&text[..(text.len() - 1)], 1u64 << 30),
+        Some('T') | Some('t') => (&text[..(text.len() - 1)], 1u64 << 40),
+        _ => (text, 1),
+    };
+    return digits.parse::<u64>().ok()
+        .and_then(|val| val.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {}", text));
+}
+
+impl FSImage {
+    // Add the paths listed in a manifest to the namespace.
+    pub(crate) fn generate_from_manifest(&mut self, manifest: &Manifest,
+                                         rng: &mut Rng) -> Result<(), ConfigError> {
+        // Index the entries of every directory by name.
+        let mut names : HashMap<(u32, String), u32> = HashMap::new();
+        for (parent_id, children) in &self.children {
+            for child_id in children {
+                if let Some(child) = self.inode_map.get(child_id) {
+                    names.insert((*parent_id, child.name.clone()), *child_id);
+                }
+            }
+        }
+        let mut owners : HashMap<String, u16> = self.owners.iter().enumerate()
+            .map(|(idx, owner)| (owner.clone(), idx as u16)).collect();
+        for entry in &manifest.entries {
+            if let Some(repl) = entry.replication {
+                if repl > self.config.num_datanodes {
+                    return Err(manifest.error(entry, format!("replication={}, but there are \
+                        only {} datanodes.", repl, self.config.num_datanodes)));
+                }
+            }
+            let owner = match entry.owner {
+                None => 0,
+                Some(ref owner) => match owners.get(owner) {
+                    Some(idx) => *idx,
+                    None => {
+                        if self.owners.len() > u16::MAX as usize {
+                            return Err(manifest.error(entry, "There are too many owners."
+                                                      .to_owned()));
+                        }
+                        let idx = self.owners.len() as u16;
+                        self.owners.push(owner.clone());
+                        owners.insert(owner.clone(), idx);
+                        idx
+                    },
+                },
+            };
+            try!(entry.for_each_path(|path| {
+                return self.add_manifest_path(entry, path, owner, &mut names, rng)
+                    .map_err(|e| manifest.error(entry, e));
+            }));
+        }
+        return Result::Ok(());
+    }
+
+    // Add a path from a manifest entry, and any parent directories it
+    // needs.
+    fn add_manifest_path(&mut self, entry: &ManifestEntry, path: &str, owner: u16,
+                         names: &mut HashMap<(u32, String), u32>,
+                         rng: &mut Rng) -> Result<(), String> {
+        let components : Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() {
+            if !entry.is_dir {
+                return Err("the root directory can't be a file".to_owned());
+            }
+            self.inode_map.get_mut(&ROOT_INODE_ID).unwrap().owner = owner;
+            return Result::Ok(());
+        }
+        let mut parent_id = ROOT_INODE_ID;
+        for (idx, name) in components.iter().enumerate() {
+            let last = idx + 1 == components.len();
+            if *name == "." || *name == ".." || name.contains(':') {
+                return Err(format!("{} is not a valid HDFS path", path));
+            }
+            let key = (parent_id, (*name).to_owned());
+            if let Some(id) = names.get(&key).cloned() {
+                let inode = self.inode_map.get_mut(&id).unwrap();
+                if !last {
+                    if !inode.is_dir {
+                        return Err(format!("{} is under a file", path));
+                    }
+                    parent_id = id;
+                    continue;
+                }
+                // Listing a directory again is fine, so that it can be given
+                // an owner after its children were listed.
+                if !(inode.is_dir && entry.is_dir) {
+                    return Err(format!("{} is listed twice", path));
+                }
+                if entry.owner.is_some() {
+                    inode.owner = owner;
+                }
+                break;
+            }
+            if self.next_inode_id == u32::MAX {
+                return Err("the namespace has run out of inode IDs".to_owned());
+            }
+            let id = self.next_inode_id;
+            self.next_inode_id = self.next_inode_id + 1;
+            let is_dir = !last || entry.is_dir;
+            let (replication, blocks) = if is_dir {
+                (0, vec![])
+            } else {
+                let repl = match entry.replication {
+                    Some(repl) => repl,
+                    None => self.choose_replication(rng, || path.to_owned()),
+                };
+                let (min_size, max_size) = entry.size
+                    .unwrap_or((self.config.min_file_size, self.config.max_file_size));
+                let size = random_file_size(rng, min_size, max_size);
+                let blocks : Vec<Block> = try!(self.generate_file_blocks(rng, size, repl)
+                                               .map_err(|e| e.to_string()));
+                (repl, blocks)
+            };
+            self.inode_map.insert(id, INode {
+                id: id,
+                name: (*name).to_owned(),
+                is_dir: is_dir,
+                replication: replication,
+                blocks: blocks,
+                owner: if last { owner } else { 0 },
+            });
+            if is_dir {
+                self.children.insert(id, vec![]);
+            }
+            self.children.get_mut(&parent_id).unwrap().push(id);
+            self.parents.insert(id, parent_id);
+            self.num_inodes = self.num_inodes + 1;
+            self.progress.add(1, 0);
+            names.insert(key, id);
+            parent_id = id;
+        }
+        return Result::Ok(());
+    }
+}
+
+// Pick a file length between min_size and max_size, inclusive.
+pub(crate) fn random_file_size(rng: &mut Rng, min_size: u64, max_size: u64) -> u64 {
+    let range = max_size - min_size;
+    if range == 0 {
+        return min_size;
+    }
+    return min_size + (rng.next_u64() % range.saturating_add(1));
+}
@@ -0,0 +1,109 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use byteorder::{BigEndian, WriteBytesExt};
+use error::FsgenError;
+use namespace::FSImage;
+use namespace::LAST_TXID;
+use std::fs::OpenOptions;
+use std::fs;
+use std::io::BufWriter;
+use std::io::Write;
+
+impl FSImage {
+    // Write the NameNode storage directory, name_dir/current, containing
+    // everything but the fsimage itself.
+    pub fn write_namenode_dir(&self, name_dir: &str) -> Result<(), FsgenError> {
+        self.progress.start_phase("namenode", "files", 3);
+        let current_path = &(name_dir.to_owned() + "/current");
+        try!(fs::create_dir_all(current_path).map_err(|e| FsgenError::io(current_path, e)));
+        println!("** created {}", current_path);
+        let version_path = &(name_dir.to_owned() + "/current/VERSION");
+        try!(self.write_namenode_version_file(version_path)
+             .map_err(|e| FsgenError::io(version_path, e)));
+        self.progress.add(1, 0);
+        println!("** wrote namenode version file {}", version_path);
+        let seen_txid_path = &(name_dir.to_owned() + "/current/seen_txid");
+        try!(self.write_seen_txid_file(seen_txid_path, LAST_TXID)
+             .map_err(|e| FsgenError::io(seen_txid_path, e)));
+        self.progress.add(1, 0);
+        println!("** wrote seen_txid file {}", seen_txid_path);
+        let edits_path = &(name_dir.to_owned() +
+                "/current/edits_inprogress_0000000000000000001");
+        try!(self.write_edits_file(edits_path, self.config.nn_layout_version)
+             .map_err(|e| FsgenError::io(edits_path, e)));
+        self.progress.add(1, 0);
+        println!("** wrote edits file {}", edits_path);
+        self.progress.finish_phase();
+        return Result::Ok(());
+    }
+
+    // Write the VERSION file which identifies the version of this fsimage.
+    pub fn write_namenode_version_file(&self,
+                        path: &str) -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(write!(w, "#Thu Feb 18 11:20:35 PST 2016\n"));
+        try!(write!(w, "namespaceID={}\n", self.config.namespace_id));
+        try!(write!(w, "clusterID={}\n", self.config.cluster_id));
+        try!(write!(w, "cTime={}\n", self.config.cluster_ctime));
+        try!(write!(w, "storageType=NAME_NODE\n"));
+        try!(write!(w, "blockpoolID={}\n", self.config.block_pool_id));
+        try!(write!(w, "layoutVersion={}\n", self.config.nn_layout_version));
+        return Result::Ok(());
+    }
+
+    // Write the seen_txid file in the namenode storage directory.  This file
+    // identifies the highest transaction id (txid) that we've seen.
+    pub fn write_seen_txid_file(&self, path: &str,
+                               seen_txid: u64) -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(write!(w, "{}\n", seen_txid));
+        return Result::Ok(());
+    }
+
+    // Write an HDFS edit log file with just the 4-byte layout version and
+    // the 4-byte feature flags int (which is always 0).
+    pub fn write_edits_file(&self, path: &str,
+                nn_layout_version: i32) -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(w.write_i32::<BigEndian>(nn_layout_version));
+        let arr = [ 0x00u8, 0x00u8, 0x00u8, 0x00u8,
+                    0x18u8, 0x00u8, 0x00u8, 0x00u8,
+                    0x0cu8, 0x00u8, 0x00u8, 0x00u8,
+                    0x00u8, 0x00u8, 0x00u8, 0x00u8,
+                    0x01u8, 0x70u8, 0x43u8, 0xf0u8,
+                    0xb7u8, 0xffu8, 0xffu8 ];
+        try!(w.write(&arr));
+        return Result::Ok(());
+    }
+}
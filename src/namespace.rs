@@ -0,0 +1,844 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use config::Config;
+use config::ConfigError;
+use fault::BlockFault;
+use image::LoadedImage;
+use image::LoadedINodeType;
+use manifest::Manifest;
+use manifest::random_file_size;
+use naming::NameGenerator;
+use progress::Progress;
+use rand::ChaChaRng;
+use rand::Rng;
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::hash_map::Values;
+use uuid::Uuid;
+use volume::Volume;
+use volume::VolumeChooser;
+
+// The first generation stamp to use for blocks.
+const FIRST_GENSTAMP : u32 = 1001;
+
+// See SequentialBlockIdGenerator#LAST_RESERVED_BLOCK_ID
+const FIRST_BLOCK_ID : u32 = 1073741825;
+
+// See INodeId#ROOT_INODE_ID
+pub const ROOT_INODE_ID : u32 = 16385;
+
+// The owner of every inode which isn't given one by a manifest.
+pub const DEFAULT_OWNER : &str = "cmccabe";
+
+// The last transaction ID we saw.
+pub const LAST_TXID : u64 = 1;
+
+// The length of a delegation key, in bytes.  See SecretManager#KEY_LENGTH
+const DELEGATION_KEY_LENGTH : usize = 8;
+
+// Selects the random stream used for datanode and storage IDs.
+const DATANODE_ID_STREAM : u64 = 0x444e4944;
+
+// Represents the FSImage which we will be writing to disk.
+pub struct FSImage {
+    // The FSGen configuration.
+    pub(crate) config: Config,
+
+    // Maps inode ID to inode information.
+    pub(crate) inode_map: HashMap<u32, INode>,
+
+    // Maps inode ID to inode children.
+    pub(crate) children: HashMap<u32, Vec<u32>>,
+
+    // Maps inode ID to the ID of its parent directory.
+    pub(crate) parents: HashMap<u32, u32>,
+
+    // The next inode ID to use.
+    pub(crate) next_inode_id: u32,
+
+    // The next ID which could be an empty directory.
+    pub(crate) next_possible_empty_dir_id: u32,
+
+    // The number of inodes
+    pub(crate) num_inodes: u32,
+
+    // The next block genstamp to use.
+    pub(crate) next_genstamp: u32,
+
+    // The next block id to use.
+    pub(crate) next_block_id: u32,
+
+    // Information about the datanodes.
+    pub(crate) datanode_info: Vec<DatanodeInfo>,
+
+    // Chooses the volume for each new replica, for each datanode.
+    pub(crate) volume_choosers: Vec<VolumeChooser>,
+
+    // Blocks which aren't in the fsimage, but have orphan replicas on the
+    // datanodes.
+    pub(crate) orphan_blocks: Vec<Block>,
+
+    // The cache pools in the CacheManagerSection.
+    pub(crate) cache_pools: Vec<CachePool>,
+
+    // The cache directives in the CacheManagerSection.
+    pub(crate) cache_directives: Vec<CacheDirective>,
+
+    // The delegation keys in the SecretManagerSection.
+    pub(crate) delegation_keys: Vec<DelegationKey>,
+
+    // The delegation tokens in the SecretManagerSection.
+    pub(crate) delegation_tokens: Vec<DelegationToken>,
+
+    // Reports the progress of generating and writing the image
+    pub(crate) progress: Progress,
+
+    // The number of inodes loaded from the image we appended to, or 0 if
+    // the whole namespace was generated
+    pub(crate) num_base_inodes: u32,
+
+    // The ID of the first block we generated.  Blocks with lower IDs were
+    // loaded from the image we appended to, and their replicas are already
+    // on disk.
+    pub(crate) first_new_block_id: u32,
+
+    // The owners of the inodes.  Each inode has an index into this list.
+    // The first owner is DEFAULT_OWNER.
+    pub(crate) owners: Vec<String>,
+}
+
+pub struct DatanodeInfo {
+    // The UUID of this DataNode.
+    pub datanode_uuid: String,
+
+    // The IDs of each storage in this DataNode.
+    pub storage_ids: Vec<String>,
+
+    // The volumes of this DataNode, in the same order as storage_ids.
+    pub volumes: Vec<Volume>,
+}
+
+// Generate a random (version 4) UUID from rng.
+pub(crate) fn random_uuid(rng: &mut Rng) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    return Uuid::from_bytes(&bytes).unwrap().to_hyphenated_string();
+}
+
+// The datanode and storage IDs come from their own random stream, so that
+// generating them doesn't change the namespace generated from a seed.  Every
+// run with the same seed gets the same IDs, which lets each host generate
+// just its own datanode directories.
+fn generate_dn_info(config: &Config) -> Vec<DatanodeInfo> {
+    let mut rng = ChaChaRng::new_unseeded();
+    rng.set_counter(config.seed, config.seed ^ DATANODE_ID_STREAM);
+    let mut datanodes : Vec<DatanodeInfo> = vec![];
+    for datanode_idx in 0..config.num_datanodes {
+        let volumes = config.datanode_volumes(datanode_idx);
+        let mut storage_ids : Vec<String> = vec![];
+        for _ in 0..volumes.len() {
+            storage_ids.push("DS-".to_owned() + &random_uuid(&mut rng));
+        }
+        let datanode = DatanodeInfo {
+            datanode_uuid: random_uuid(&mut rng),
+            storage_ids: storage_ids,
+            volumes: volumes,
+        };
+        datanodes.push(datanode);
+    }
+    return datanodes;
+}
+
+impl FSImage {
+    // Generate the namespace described by config.  If base is set, the
+    // namespace is loaded from it and grown by config.num_inodes inodes, or
+    // by the paths of config.manifest if there is one.
+    pub(crate) fn new(config: Config, base: Option<&LoadedImage>, rng: &mut Rng)
+                      -> Result<FSImage, ConfigError> {
+        let datanode_info = generate_dn_info(&config);
+        let volume_choosers = datanode_info.iter()
+            .map(|dn| VolumeChooser::new(config.volume_choosing_policy, &dn.volumes))
+            .collect();
+        let progress = Progress::new(&config);
+        let mut fs_image = FSImage {
+            config: config,
+            inode_map: HashMap::new(),
+            children: HashMap::new(),
+            parents: HashMap::new(),
+            next_inode_id: (ROOT_INODE_ID + 1),
+            next_possible_empty_dir_id: ROOT_INODE_ID,
+            num_inodes: 0,
+            next_genstamp: FIRST_GENSTAMP,
+            next_block_id: FIRST_BLOCK_ID,
+            datanode_info: datanode_info,
+            volume_choosers: volume_choosers,
+            orphan_blocks: vec![],
+            cache_pools: vec![],
+            cache_directives: vec![],
+            delegation_keys: vec![],
+            delegation_tokens: vec![],
+            progress: progress,
+            num_base_inodes: 0,
+            first_new_block_id: FIRST_BLOCK_ID,
+            owners: vec![DEFAULT_OWNER.to_owned()],
+        };
+        let manifest = if fs_image.config.manifest.is_empty() {
+            None
+        } else {
+            let manifest = try!(Manifest::load(&fs_image.config.manifest));
+            println!("** read {} paths from manifest {}", manifest.num_paths(),
+                     fs_image.config.manifest);
+            Some(manifest)
+        };
+        let num_base_inodes = match base {
+            None => 1,
+            Some(base) => base.inodes.len() as u64,
+        };
+        // We don't know how many parent directories the paths of a manifest
+        // need until we create them.
+        let total_inodes = match manifest {
+            None => num_base_inodes + fs_image.config.num_inodes as u64,
+            Some(_) => 0,
+        };
+        fs_image.progress.start_phase("namespace", "inodes", total_inodes);
+        match base {
+            None => fs_image.add_root_dir(),
+            Some(base) => try!(fs_image.load_base_image(base)),
+        }
+        match manifest {
+            None => try!(fs_image.generate(rng)),
+            Some(ref manifest) => try!(fs_image.generate_from_manifest(manifest, rng)),
+        }
+        fs_image.inject_faults();
+        fs_image.generate_cache_manager_state(rng);
+        fs_image.generate_secret_manager_state(rng);
+        fs_image.progress.finish_phase();
+        return Result::Ok(fs_image);
+    }
+
+    // Get the configuration this image was generated from.
+    pub fn config(&self) -> &Config {
+        return &self.config;
+    }
+
+    // Get the progress reporter, which keeps the timings of each phase.
+    pub fn progress(&self) -> &Progress {
+        return &self.progress;
+    }
+
+    // Get the number of inodes which were loaded from the image we appended
+    // to, including the root directory.  This is 0 if the whole namespace was
+    // generated.
+    pub fn num_base_inodes(&self) -> u32 {
+        return self.num_base_inodes;
+    }
+
+    // Get an inode by ID.
+    pub fn inode(&self, id: u32) -> Option<&INode> {
+        return self.inode_map.get(&id);
+    }
+
+    // Iterate over all the inodes, in no particular order.
+    pub fn inodes(&self) -> Values<'_, u32, INode> {
+        return self.inode_map.values();
+    }
+
+    // Get the IDs of the children of a directory.
+    pub fn children(&self, id: u32) -> Option<&[u32]> {
+        return self.children.get(&id).map(|children| &children[..]);
+    }
+
+    // Get the ID of the parent of an inode, or None for the root.
+    pub fn parent(&self, id: u32) -> Option<u32> {
+        return self.parents.get(&id).cloned();
+    }
+
+    // Get the total number of inodes, including the root.
+    pub fn num_inodes(&self) -> u32 {
+        return self.num_inodes;
+    }
+
+    // Get the highest inode ID in use.
+    pub fn last_inode_id(&self) -> u32 {
+        return self.next_inode_id - 1;
+    }
+
+    // Get the highest block ID in use.
+    pub fn last_block_id(&self) -> u32 {
+        return self.next_block_id - 1;
+    }
+
+    // Get the highest block genstamp in use.
+    pub fn last_genstamp(&self) -> u32 {
+        return self.next_genstamp - 1;
+    }
+
+    // Get information about the datanodes.
+    pub fn datanodes(&self) -> &[DatanodeInfo] {
+        return &self.datanode_info;
+    }
+
+    pub fn cache_pools(&self) -> &[CachePool] {
+        return &self.cache_pools;
+    }
+
+    pub fn cache_directives(&self) -> &[CacheDirective] {
+        return &self.cache_directives;
+    }
+
+    pub fn delegation_keys(&self) -> &[DelegationKey] {
+        return &self.delegation_keys;
+    }
+
+    pub fn delegation_tokens(&self) -> &[DelegationToken] {
+        return &self.delegation_tokens;
+    }
+
+    // Load the namespace of the image we are appending to.  Its blocks have
+    // no datanodes, since their replicas are already on disk.  IDs and
+    // generation stamps carry on from where the image left off.
+    fn load_base_image(&mut self, base: &LoadedImage) -> Result<(), ConfigError> {
+        let max_id = u32::MAX as u64;
+        if base.last_inode_id + self.config.num_inodes as u64 > max_id {
+            return Err(ConfigError::new(None, format!("The image's last inode ID is {}, \
+                so it can only have {} more inodes.", base.last_inode_id,
+                max_id - base.last_inode_id)));
+        }
+        if base.last_allocated_block_id >= max_id || base.genstamp_v2 >= max_id {
+            return Err(ConfigError::new(None, format!("The image's last block ID ({}) or \
+                generation stamp ({}) is too big for fsgen.", base.last_allocated_block_id,
+                base.genstamp_v2)));
+        }
+        let mut owners : HashMap<String, u16> = self.owners.iter().enumerate()
+            .map(|(idx, owner)| (owner.clone(), idx as u16)).collect();
+        for loaded in &base.inodes {
+            let is_dir = match loaded.inode_type {
+                LoadedINodeType::File => false,
+                LoadedINodeType::Directory => true,
+                LoadedINodeType::Symlink => return Err(ConfigError::new(None, format!(
+                    "The image has symlink {} ({}), and fsgen can't append to images \
+                    with symlinks.", loaded.id, loaded.name))),
+            };
+            if loaded.id > base.last_inode_id {
+                return Err(ConfigError::new(None, format!("The image has inode {}, which is \
+                    beyond its last inode ID, {}.", loaded.id, base.last_inode_id)));
+            }
+            let mut blocks = vec![];
+            for block in &loaded.blocks {
+                if block.id > base.last_allocated_block_id || block.genstamp > base.genstamp_v2 {
+                    return Err(ConfigError::new(None, format!("The image has block {} with \
+                        generation stamp {}, which is beyond its last block ID or generation \
+                        stamp.  fsgen can't append to images with legacy block IDs.",
+                        block.id, block.genstamp)));
+                }
+                blocks.push(Block {
+                    id: block.id as u32,
+                    genstamp: block.genstamp as u32,
+                    num_bytes: block.num_bytes,
+                    datanodes: vec![],
+                    storages: vec![],
+                    fault: None,
+                });
+            }
+            let owner = match owners.get(&loaded.owner) {
+                Some(idx) => *idx,
+                None if loaded.owner.is_empty() => 0,
+                None => {
+                    if self.owners.len() > u16::MAX as usize {
+                        return Err(ConfigError::new(None,
+                            "The image has too many owners.".to_owned()));
+                    }
+                    let idx = self.owners.len() as u16;
+                    self.owners.push(loaded.owner.clone());
+                    owners.insert(loaded.owner.clone(), idx);
+                    idx
+                },
+            };
+            let id = loaded.id as u32;
+            if is_dir {
+                self.children.insert(id, vec![]);
+            }
+            self.inode_map.insert(id, INode {
+                id: id,
+                name: loaded.name.clone(),
+                is_dir: is_dir,
+                replication: loaded.replication,
+                blocks: blocks,
+                owner: owner,
+            });
+            self.progress.add(1, 0);
+        }
+        match self.inode_map.get(&ROOT_INODE_ID) {
+            Some(root) if root.is_dir => (),
+            _ => return Err(ConfigError::new(None,
+                "The image has no root directory.".to_owned())),
+        }
+        for directory in &base.directories {
+            let parent_id = directory.parent as u32;
+            let children : Vec<u32> = directory.children.iter().map(|id| *id as u32).collect();
+            for child_id in &children {
+                self.parents.insert(*child_id, parent_id);
+            }
+            self.children.insert(parent_id, children);
+        }
+        self.config.namespace_id = base.namespace_id;
+        self.num_inodes = base.inodes.len() as u32;
+        self.num_base_inodes = self.num_inodes;
+        self.next_inode_id = base.last_inode_id as u32 + 1;
+        self.next_block_id = base.last_allocated_block_id as u32 + 1;
+        self.next_genstamp = base.genstamp_v2 as u32 + 1;
+        self.first_new_block_id = self.next_block_id;
+        return Result::Ok(());
+    }
+
+    // Start an empty namespace, with just the root directory.
+    fn add_root_dir(&mut self) {
+        let root_inode = INode {
+            id: ROOT_INODE_ID,
+            name: "".to_owned(),
+            is_dir: true,
+            replication: 0,
+            blocks: vec![],
+            owner: 0,
+        };
+        self.inode_map.insert(ROOT_INODE_ID, root_inode);
+        self.children.insert(ROOT_INODE_ID, vec![]);
+        self.num_inodes = self.num_inodes + 1;
+        self.progress.add(1, 0);
+    }
+
+    // Add config.num_inodes inodes to the namespace, filling each directory
+    // with config.entries_per_dir entries, shallowest first.
+    fn generate(&mut self, rng: &mut Rng) -> Result<(), ConfigError> {
+        let last_num_inodes = self.num_inodes + self.config.num_inodes;
+        let namer = NameGenerator::new(&self.config);
+        loop {
+            let parent_id = self.find_shallowest_incomplete_dir();
+            // A directory in a loaded image may already have some entries.
+            // If it is full, it must be the root, which gets a whole new
+            // batch.  See find_shallowest_incomplete_dir
+            let num_children = self.children[&parent_id].len();
+            let first = if num_children < self.config.entries_per_dir { num_children } else { 0 };
+            let taken : HashSet<String> = self.children[&parent_id].iter()
+                .map(|id| self.inode_map[id].name.clone()).collect();
+            let mut names = namer.start_dir(rng, taken);
+            for i in first..self.config.entries_per_dir {
+                if self.num_inodes >= last_num_inodes {
+                    return Result::Ok(());
+                }
+                let id = self.next_inode_id;
+                self.next_inode_id = self.next_inode_id + 1;
+                {
+                    let mut children = self.children.get_mut(&parent_id).unwrap();
+                    children.push(id);
+                }
+                self.parents.insert(id, parent_id);
+                let is_dir = i < self.config.dirs_per_dir;
+                let file_idx = i.saturating_sub(self.config.dirs_per_dir);
+                let name = namer.name(rng, &mut names, is_dir, i, file_idx);
+                if is_dir {
+                    let inode = INode {
+                        id: id,
+                        name: name,
+                        is_dir: true,
+                        replication: 0,
+                        blocks: vec![],
+                        owner: 0,
+                    };
+                    self.inode_map.insert(id, inode);
+                    self.children.insert(id, vec![]);
+                } else {
+                    let size = random_file_size(rng, self.config.min_file_size,
+                                                self.config.max_file_size);
+                    let repl = self.choose_replication(rng, || {
+                        self.get_full_path(parent_id).trim_end_matches('/').to_owned() + "/" +
+                            &name
+                    });
+                    let inode = INode {
+                        id: id,
+                        name: name,
+                        is_dir: false,
+                        replication: repl,
+                        blocks: try!(self.generate_file_blocks(rng, size, repl)),
+                        owner: 0,
+                    };
+                    self.inode_map.insert(id, inode);
+                }
+                self.num_inodes = self.num_inodes + 1;
+                self.progress.add(1, 0);
+            }
+        }
+    }
+
+    fn find_shallowest_incomplete_dir(&mut self) -> u32 {
+        loop {
+            let id = self.next_possible_empty_dir_id;
+            if id >= self.next_inode_id {
+                // Every directory is full, which can only happen in a loaded
+                // image.
+                return ROOT_INODE_ID;
+            }
+            // The IDs in a loaded image may have gaps.
+            let is_dir = match self.inode_map.get(&id) {
+                None => false,
+                Some(inode) => inode.is_dir,
+            };
+            if is_dir {
+                let children = self.children.entry(id).or_insert(vec![]);
+                if children.len() < self.config.entries_per_dir {
+                    return id;
+                }
+            }
+            self.next_possible_empty_dir_id = self.next_possible_empty_dir_id + 1;
+        }
+    }
+
+    // Generate the cache pools, and the cache directives which refer to
+    // randomly chosen paths in the namespace.
+    fn generate_cache_manager_state(&mut self, rng: &mut Rng) {
+        for i in 0..self.config.num_cache_pools {
+            self.cache_pools.push(CachePool {
+                name: format!("pool{}", i + 1),
+                owner: self.config.cache_pool_owner.clone(),
+                group: self.config.cache_pool_group.clone(),
+                mode: self.config.cache_pool_mode,
+                limit: self.config.cache_pool_limit,
+                max_relative_expiry: self.config.cache_pool_max_ttl,
+            });
+        }
+        if self.config.num_cache_directives == 0 {
+            return;
+        }
+        // The IDs in a loaded image may have gaps, so choose from the inodes
+        // which exist.  Sorting them keeps the choice the same for a seed.
+        let mut inode_ids : Vec<u32> = self.inode_map.keys().cloned().collect();
+        inode_ids.sort();
+        for i in 0..self.config.num_cache_directives {
+            let inode_id = inode_ids[(rng.next_u32() as usize) % inode_ids.len()];
+            let path = self.get_full_path(inode_id);
+            let pool_idx = (i % self.config.num_cache_pools) as usize;
+            self.cache_directives.push(CacheDirective {
+                id: (i as u64) + 1,
+                path: path,
+                replication: self.config.cache_directive_repl,
+                pool: self.cache_pools[pool_idx].name.clone(),
+                expiry_time: self.config.cluster_ctime + self.config.cache_directive_ttl,
+            });
+        }
+    }
+
+    // Generate the delegation keys, and the delegation tokens signed by them.
+    // Keys and tokens are all issued at the cluster creation time.
+    fn generate_secret_manager_state(&mut self, rng: &mut Rng) {
+        for i in 0..self.config.num_delegation_keys {
+            let mut key = vec![0u8; DELEGATION_KEY_LENGTH];
+            rng.fill_bytes(&mut key);
+            self.delegation_keys.push(DelegationKey {
+                id: i + 1,
+                key: key,
+                expiry_date: self.config.cluster_ctime + self.config.delegation_key_ttl,
+            });
+        }
+        for i in 0..self.config.num_delegation_tokens {
+            let idx = i as usize;
+            let owners = &self.config.delegation_token_owners;
+            let renewers = &self.config.delegation_token_renewers;
+            let real_users = &self.config.delegation_token_real_users;
+            let real_user = if real_users.is_empty() {
+                "".to_owned()
+            } else {
+                real_users[idx % real_users.len()].clone()
+            };
+            self.delegation_tokens.push(DelegationToken {
+                owner: owners[idx % owners.len()].clone(),
+                renewer: renewers[idx % renewers.len()].clone(),
+                real_user: real_user,
+                issue_date: self.config.cluster_ctime,
+                max_date: self.config.cluster_ctime +
+                    self.config.delegation_token_max_lifetime,
+                sequence_number: i + 1,
+                master_key_id: (i % self.config.num_delegation_keys) + 1,
+                expiry_date: self.config.cluster_ctime +
+                    self.config.delegation_token_renew_interval,
+            });
+        }
+    }
+
+    // Get the full path of an inode, starting from the root directory.
+    pub fn get_full_path(&self, id: u32) -> String {
+        if id == ROOT_INODE_ID {
+            return "/".to_owned();
+        }
+        let mut components : Vec<&str> = vec![];
+        let mut cur = id;
+        while cur != ROOT_INODE_ID {
+            components.push(&self.inode_map.get(&cur).unwrap().name);
+            cur = *self.parents.get(&cur).unwrap();
+        }
+        let mut path = String::new();
+        for component in components.iter().rev() {
+            path.push('/');
+            path.push_str(component);
+        }
+        return path;
+    }
+
+    // Generate the blocks of a file of size bytes, each with repl replicas.
+    // Even an empty file gets one (empty) block.
+    pub(crate) fn generate_file_blocks(&mut self, rng: &mut Rng, size: u64,
+                                       repl: u16) -> Result<Vec<Block>, ConfigError> {
+        let mut remaining = size;
+        let mut blocks : Vec<Block> = vec![];
+        loop {
+            let num_bytes = cmp::min(remaining, self.config.preferred_block_size);
+            blocks.push(try!(self.generate_random_block(rng, num_bytes, repl)));
+            remaining = remaining - num_bytes;
+            if remaining == 0 {
+                return Result::Ok(blocks);
+            }
+        }
+    }
+
+    fn generate_random_block(&mut self, rng: &mut Rng, num_bytes: u64,
+                             repl: u16) -> Result<Block, ConfigError> {
+        let datanodes = if self.config.num_racks > 1 {
+            self.choose_rack_aware_datanodes(rng, repl)
+        } else {
+            self.choose_random_datanodes(rng, repl)
+        };
+        let id = self.next_block_id;
+        self.next_block_id = self.next_block_id + 1;
+        let genstamp = self.next_genstamp;
+        self.next_genstamp = self.next_genstamp + 1;
+        let mut block = Block {
+            id: id,
+            genstamp: genstamp,
+            num_bytes: num_bytes,
+            datanodes: datanodes,
+            storages: vec![],
+            fault: None,
+        };
+        // Replicas take up space for their data and their checksums.
+        let replica_len = num_bytes + block.meta_file_len();
+        for datanode in &block.datanodes {
+            match self.volume_choosers[*datanode as usize].choose(rng, replica_len) {
+                Some(storage) => block.storages.push(storage),
+                None => return Err(ConfigError::new(None,
+                    format!("Datanode {} ran out of volume space for block {} ({} bytes).  \
+                            Give its volumes more capacity, or generate less data.",
+                            datanode + 1, id, num_bytes))),
+            }
+        }
+        return Result::Ok(block);
+    }
+
+    fn choose_random_datanodes(&self, rng: &mut Rng, repl: u16) -> Vec<u16> {
+        let mut datanodes : Vec<u16> = Vec::new();
+        // The datanodes chosen so far, in ascending order.  We must skip over
+        // them in order, or we can end up choosing the same datanode twice.
+        let mut chosen : Vec<u16> = Vec::new();
+        for i in 0..repl {
+            let range = (self.config.num_datanodes - i) as u32;
+            let mut val = (rng.next_u32() % range) as u16;
+            for datanode in &chosen {
+                if val >= *datanode {
+                    val = val + 1;
+                }
+            }
+            datanodes.push(val);
+            let pos = chosen.binary_search(&val).unwrap_or_else(|p| p);
+            chosen.insert(pos, val);
+        }
+        return datanodes;
+    }
+
+    // Choose datanodes for the replicas of a block the way
+    // BlockPlacementPolicyDefault does.  The second replica goes on a
+    // different rack than the first, and the third goes on the same rack as
+    // the second.  Any other replicas go anywhere.  Datanodes are assigned to
+    // racks round-robin.
+    fn choose_rack_aware_datanodes(&self, rng: &mut Rng, repl: u16) -> Vec<u16> {
+        let num_racks = self.config.num_racks;
+        let mut datanodes : Vec<u16> = Vec::new();
+        for i in 0..repl {
+            let rack = match i {
+                1 => {
+                    let first_rack = datanodes[0] % num_racks;
+                    let offset = 1 + (rng.next_u32() % ((num_racks - 1) as u32)) as u16;
+                    Some((first_rack + offset) % num_racks)
+                },
+                2 => Some(datanodes[1] % num_racks),
+                _ => None,
+            };
+            // If the rack we wanted is full, fall back on any rack.
+            let val = match self.choose_unused_datanode(rng, rack, &datanodes) {
+                Some(val) => val,
+                None => self.choose_unused_datanode(rng, None, &datanodes).unwrap(),
+            };
+            datanodes.push(val);
+        }
+        return datanodes;
+    }
+
+    // Choose a random datanode which is not already in use, on the given rack
+    // if there is one.  Returns None if there is no such datanode.
+    fn choose_unused_datanode(&self, rng: &mut Rng, rack: Option<u16>,
+                              used: &[u16]) -> Option<u16> {
+        let mut candidates : Vec<u16> = vec![];
+        for datanode in 0..self.config.num_datanodes {
+            if rack.is_some() && (Some(datanode % self.config.num_racks) != rack) {
+                continue;
+            }
+            if !used.contains(&datanode) {
+                candidates.push(datanode);
+            }
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        return Some(candidates[(rng.next_u32() as usize) % candidates.len()]);
+    }
+}
+
+// Represents an HDFS INode (directory or file)
+pub struct INode {
+    pub id: u32,
+    pub name: String,
+    pub is_dir: bool,
+
+    // The replication factor of a file.  Always 0 for directories.
+    pub replication: u16,
+
+    pub blocks: Vec<Block>,
+
+    // The index of the owner in FSImage#owners
+    pub owner: u16,
+}
+
+impl INode {
+    pub fn get_type_name(&self) -> &'static str {
+        if self.is_dir {
+            return "DIRECTORY";
+        } else {
+            return "FILE";
+        }
+    }
+}
+
+pub struct Block {
+    // The ID of the block
+    pub id: u32,
+
+    // The genstamp of the block
+    pub genstamp: u32,
+
+    // The length of the block, in bytes
+    pub num_bytes: u64,
+
+    // The datanodes which have a replica of this block
+    pub datanodes: Vec<u16>,
+
+    // The index of the volume holding each replica on its datanode, in the
+    // same order as datanodes
+    pub storages: Vec<u16>,
+
+    // How the replicas on disk differ from the fsimage, if at all
+    pub fault: Option<BlockFault>,
+}
+
+// Represents an HDFS cache pool.
+pub struct CachePool {
+    // The name of the pool
+    pub name: String,
+
+    // The owner of the pool
+    pub owner: String,
+
+    // The group of the pool
+    pub group: String,
+
+    // The permission bits of the pool
+    pub mode: u16,
+
+    // The maximum number of bytes which may be cached in the pool
+    pub limit: u64,
+
+    // The maximum TTL of directives in the pool, in milliseconds
+    pub max_relative_expiry: u64,
+}
+
+// Represents an HDFS cache directive.
+pub struct CacheDirective {
+    // The ID of the directive
+    pub id: u64,
+
+    // The path which the directive caches
+    pub path: String,
+
+    // The number of cached replicas requested
+    pub replication: u16,
+
+    // The name of the pool which contains the directive
+    pub pool: String,
+
+    // The absolute time at which the directive expires, in milliseconds
+    pub expiry_time: u64,
+}
+
+// Represents an HDFS delegation key.
+pub struct DelegationKey {
+    // The ID of the key
+    pub id: u32,
+
+    // The secret key bytes
+    pub key: Vec<u8>,
+
+    // The time at which the key expires, in milliseconds
+    pub expiry_date: u64,
+}
+
+// Represents a persisted HDFS delegation token.
+pub struct DelegationToken {
+    // The user who owns the token
+    pub owner: String,
+
+    // The user who may renew the token
+    pub renewer: String,
+
+    // The user who requested the token on behalf of the owner, or empty
+    pub real_user: String,
+
+    // The time at which the token was issued, in milliseconds
+    pub issue_date: u64,
+
+    // The time after which the token may no longer be renewed, in milliseconds
+    pub max_date: u64,
+
+    // The sequence number of the token
+    pub sequence_number: u32,
+
+    // The ID of the delegation key which signed the token
+    pub master_key_id: u32,
+
+    // The time at which the token expires unless renewed, in milliseconds
+    pub expiry_date: u64,
+}
@@ -0,0 +1,269 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The names of the generated files and directories.  The NameNode keeps
+// every name in a byte[], so their lengths matter as much as their number
+// when sizing the heap.
+
+use config::Config;
+use namespace::random_uuid;
+use rand::Rng;
+use std::char;
+use std::collections::HashSet;
+use xml::civil_from_days;
+
+// HDFS rejects path components longer than this, in bytes.  See
+// dfs.namenode.fs-limits.max-component-length
+pub const MAX_NAME_BYTES : u32 = 255;
+
+// The most bytes a character of a unicode name takes in UTF-8.
+pub const MAX_UNICODE_CHAR_BYTES : u32 = 3;
+
+// How many times to draw a new random name when it is already taken, before
+// giving up and adding a suffix to it.  Taken dates are skipped instead.
+const MAX_NAME_RETRIES : usize = 8;
+
+// The ranges of characters which unicode names are made of: accented Latin,
+// Greek, Cyrillic, Hiragana and CJK letters.
+const UNICODE_RANGES : &[(u32, u32)] = &[ (0xe0, 0xf6), (0x3b1, 0x3c9), (0x430, 0x44f),
+    (0x3041, 0x3096), (0x4e00, 0x9fff) ];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DirNaming {
+    // Random names.
+    Random,
+
+    // Consecutive dates, like 2024-01-01.
+    Date,
+
+    // Hive partitions of consecutive dates, like dt=2024-01-01.
+    Hive,
+}
+
+impl DirNaming {
+    pub fn parse(name: &str) -> Option<DirNaming> {
+        match name {
+            "random" => return Some(DirNaming::Random),
+            "date" => return Some(DirNaming::Date),
+            "hive" => return Some(DirNaming::Hive),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DirNaming::Random => return "random",
+            DirNaming::Date => return "date",
+            DirNaming::Hive => return "hive",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileNaming {
+    // Random names.
+    Random,
+
+    // The output of a Spark job: part-00000-<job uuid>.snappy.parquet, with
+    // one job uuid per directory.
+    Spark,
+}
+
+impl FileNaming {
+    pub fn parse(name: &str) -> Option<FileNaming> {
+        match name {
+            "random" => return Some(FileNaming::Random),
+            "spark" => return Some(FileNaming::Spark),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FileNaming::Random => return "random",
+            FileNaming::Spark => return "spark",
+        }
+    }
+}
+
+// The characters of random names.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NameCharset {
+    // Lowercase ASCII letters.
+    Ascii,
+
+    // Letters from several scripts, which take 2 or 3 bytes each.
+    Unicode,
+}
+
+impl NameCharset {
+    pub fn parse(name: &str) -> Option<NameCharset> {
+        match name {
+            "ascii" => return Some(NameCharset::Ascii),
+            "unicode" => return Some(NameCharset::Unicode),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NameCharset::Ascii => return "ascii",
+            NameCharset::Unicode => return "unicode",
+        }
+    }
+
+    // The most bytes a character takes in UTF-8.
+    pub fn max_char_bytes(&self) -> u32 {
+        match *self {
+            NameCharset::Ascii => return 1,
+            NameCharset::Unicode => return MAX_UNICODE_CHAR_BYTES,
+        }
+    }
+}
+
+pub struct NameGenerator {
+    dir_naming: DirNaming,
+    file_naming: FileNaming,
+    charset: NameCharset,
+    min_length: u32,
+    max_length: u32,
+    partition_key: String,
+    start_day: i64,
+}
+
+// The names in one directory, and what its new entries are named after.
+pub struct DirNames {
+    // The names of the entries so far
+    taken: HashSet<String>,
+
+    // The uuid of the Spark job which wrote the files, if any
+    job_id: String,
+}
+
+impl NameGenerator {
+    pub fn new(config: &Config) -> NameGenerator {
+        return NameGenerator {
+            dir_naming: config.dir_naming,
+            file_naming: config.file_naming,
+            charset: config.name_charset,
+            min_length: config.min_name_length,
+            max_length: config.max_name_length,
+            partition_key: config.partition_key.clone(),
+            start_day: config.partition_start_day,
+        };
+    }
+
+    // Start naming new entries in a directory which already has the given
+    // names.
+    pub fn start_dir(&self, rng: &mut Rng, taken: HashSet<String>) -> DirNames {
+        let job_id = match self.file_naming {
+            FileNaming::Random => "".to_owned(),
+            FileNaming::Spark => random_uuid(rng),
+        };
+        return DirNames {
+            taken: taken,
+            job_id: job_id,
+        };
+    }
+
+    // Name the entry with the given index in a directory.  The name is
+    // different from every other name in the directory.
+    pub fn name(&self, rng: &mut Rng, dir: &mut DirNames, is_dir: bool,
+                idx: usize, file_idx: usize) -> String {
+        let mut name = self.new_name(rng, dir, is_dir, idx, file_idx);
+        let mut retries : usize = 0;
+        while dir.taken.contains(&name) {
+            if is_dir && self.dir_naming != DirNaming::Random {
+                // A directory we appended to may already have these dates.
+                name = self.new_name(rng, dir, is_dir, idx + retries + 1, file_idx);
+            } else if retries < MAX_NAME_RETRIES {
+                name = self.new_name(rng, dir, is_dir, idx, file_idx);
+            } else {
+                break;
+            }
+            retries = retries + 1;
+        }
+        if dir.taken.contains(&name) {
+            let mut suffix = 1;
+            while dir.taken.contains(&suffixed_name(&name, suffix)) {
+                suffix = suffix + 1;
+            }
+            name = suffixed_name(&name, suffix);
+        }
+        dir.taken.insert(name.clone());
+        return name;
+    }
+
+    fn new_name(&self, rng: &mut Rng, dir: &DirNames, is_dir: bool,
+                idx: usize, file_idx: usize) -> String {
+        if is_dir {
+            match self.dir_naming {
+                DirNaming::Random => return self.random_name(rng),
+                DirNaming::Date => return format_date(self.start_day + idx as i64),
+                DirNaming::Hive => return format!("{}={}", self.partition_key,
+                                                  format_date(self.start_day + idx as i64)),
+            }
+        }
+        match self.file_naming {
+            FileNaming::Random => return self.random_name(rng),
+            FileNaming::Spark => return format!("part-{:05}-{}.snappy.parquet",
+                                                file_idx, dir.job_id),
+        }
+    }
+
+    fn random_name(&self, rng: &mut Rng) -> String {
+        let mut len = self.min_length;
+        if self.max_length > self.min_length {
+            len = len + rng.next_u32() % (self.max_length - self.min_length + 1);
+        }
+        let mut ret = String::new();
+        for _ in 0..len {
+            match self.charset {
+                NameCharset::Ascii => {
+                    ret.push(char::from_u32(0x61 + rng.next_u32() % 26).unwrap());
+                },
+                NameCharset::Unicode => {
+                    let (first, last) =
+                        UNICODE_RANGES[(rng.next_u32() as usize) % UNICODE_RANGES.len()];
+                    let val = first + rng.next_u32() % (last - first + 1);
+                    ret.push(char::from_u32(val).unwrap());
+                },
+            }
+        }
+        return ret;
+    }
+}
+
+// Add a numeric suffix to a name, cutting the name short on a character
+// boundary if the result would be longer than MAX_NAME_BYTES.
+fn suffixed_name(name: &str, suffix: usize) -> String {
+    let suffix = format!("_{}", suffix);
+    let mut end = name.len();
+    while end + suffix.len() > MAX_NAME_BYTES as usize {
+        end = end - 1;
+        while !name.is_char_boundary(end) {
+            end = end - 1;
+        }
+    }
+    return format!("{}{}", &name[..end], suffix);
+}
+
+fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    return format!("{:04}-{:02}-{:02}", year, month, day);
+}
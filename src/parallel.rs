@@ -0,0 +1,144 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Worker thread pools for the output writers.
+
+use crossbeam;
+use error::FsgenError;
+use progress::MONITOR_TICK_MS;
+use progress::Progress;
+use std::any::Any;
+use std::cmp;
+use std::io::Error;
+use std::io::Write;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+// How many chunks write_chunks formats per worker thread before writing them
+// out.  This bounds how much formatted output is held in memory at once.
+const CHUNKS_PER_THREAD : usize = 4;
+
+// Run f on every task, using up to num_threads worker threads.  Each worker
+// takes the next task from the queue until there are none left, or a task
+// has failed.  Returns the first error.  A task which panics fails with
+// FsgenError::Worker.  While the workers run, a monitor thread reports the
+// progress of the current phase and watches for stalled workers.
+pub fn run_tasks<T, F>(progress: &Progress, num_threads: usize, tasks: &[T],
+                       f: F) -> Result<(), FsgenError>
+        where T: Sync, F: Fn(&T) -> Result<(), FsgenError> + Sync {
+    let next_task = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let num_threads = cmp::max(1, cmp::min(num_threads, tasks.len()));
+    return crossbeam::scope(|scope| {
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+        scope.spawn(move || {
+            // Wake up every tick until the sender is dropped.
+            while let Err(RecvTimeoutError::Timeout) = done_receiver
+                    .recv_timeout(Duration::from_millis(MONITOR_TICK_MS)) {
+                progress.report_if_due();
+                progress.check_stalls();
+            }
+        });
+        let (next_task, failed, f) = (&next_task, &failed, &f);
+        let workers : Vec<_> = (0..num_threads).map(|worker_idx| scope.spawn(move || {
+            progress.enter_worker(worker_idx);
+            let mut worker_result = Result::Ok(());
+            while !failed.load(Ordering::Relaxed) {
+                let task_idx = next_task.fetch_add(1, Ordering::Relaxed);
+                if task_idx >= tasks.len() {
+                    break;
+                }
+                let task_result = match panic::catch_unwind(AssertUnwindSafe(|| {
+                    f(&tasks[task_idx])
+                })) {
+                    Ok(task_result) => task_result,
+                    Err(payload) => Err(FsgenError::Worker(panic_message(payload))),
+                };
+                if let Err(e) = task_result {
+                    failed.store(true, Ordering::Relaxed);
+                    worker_result = Result::Err(e);
+                    break;
+                }
+            }
+            progress.leave_worker();
+            return worker_result;
+        })).collect();
+        let mut result = Result::Ok(());
+        for worker in workers {
+            let worker_result = worker.join();
+            if result.is_ok() {
+                result = worker_result;
+            }
+        }
+        drop(done_sender);
+        return result;
+    });
+}
+
+// Format items into w, in order, using up to num_threads worker threads.
+// The items are split into chunks of chunk_len, and each worker formats a
+// whole chunk into its own buffer with format_item.  The buffers are written
+// to w in order, and reused for the next round of chunks.  I/O errors are
+// reported against path, the file w ends up in.  The items formatted and the
+// bytes written are added to progress.
+pub fn write_chunks<W, T, F>(w: &mut W, path: &str, progress: &Progress, num_threads: usize,
+                             items: &[T], chunk_len: usize,
+                             format_item: F) -> Result<(), FsgenError>
+        where W: Write, T: Sync, F: Fn(&mut Vec<u8>, &T) -> Result<(), Error> + Sync {
+    let chunks : Vec<&[T]> = items.chunks(chunk_len).collect();
+    let window_len = cmp::max(1, num_threads) * CHUNKS_PER_THREAD;
+    let buffers : Vec<Mutex<Vec<u8>>> = (0..cmp::min(window_len, chunks.len()))
+        .map(|_| Mutex::new(vec![])).collect();
+    for window in chunks.chunks(window_len) {
+        let tasks : Vec<(&[T], &Mutex<Vec<u8>>)> = window.iter().cloned()
+            .zip(buffers.iter()).collect();
+        try!(run_tasks(progress, num_threads, &tasks, |&(chunk, buffer)| {
+            let mut buf = buffer.lock().unwrap();
+            buf.clear();
+            for item in chunk {
+                try!(format_item(&mut buf, item).map_err(|e| FsgenError::io(path, e)));
+            }
+            progress.add(chunk.len() as u64, 0);
+            return Result::Ok(());
+        }));
+        for buffer in &buffers[0..window.len()] {
+            let buf = buffer.lock().unwrap();
+            try!(w.write_all(&buf).map_err(|e| FsgenError::io(path, e)));
+            progress.add(0, buf.len() as u64);
+        }
+    }
+    return Result::Ok(());
+}
+
+// Get the message a worker thread panicked with.
+fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    return "unknown panic".to_owned();
+}
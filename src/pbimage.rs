@@ -0,0 +1,337 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Writes a binary (protobuf) fsimage, which the NameNode can load without
+// converting the XML with the offline image viewer first.  It holds the same
+// namespace as the XML.  See FSImageFormatProtobuf and fsimage.proto
+
+use error::FsgenError;
+use md5;
+use namespace::DEFAULT_OWNER;
+use namespace::FSImage;
+use namespace::INode;
+use namespace::LAST_TXID;
+use namespace::ROOT_INODE_ID;
+use parallel::run_tasks;
+use parallel::write_chunks;
+use protobuf::write_bytes_field;
+use protobuf::write_delimited;
+use protobuf::write_fixed64_field;
+use protobuf::write_uint_field;
+use protobuf::write_varint;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// The magic bytes at the start of every binary fsimage.  See
+// FSImageUtil#MAGIC_HEADER
+const MAGIC_HEADER : &[u8] = b"HDFSIMG1";
+
+// The version of the binary fsimage format.  See FSImageUtil#FILE_VERSION
+const FILE_VERSION : u64 = 1;
+
+// The IDs of the default owner and the group of every inode in the string
+// table.  Any other owners come after the group.
+const USER_STRING_ID : u64 = 1;
+const GROUP_STRING_ID : u64 = 2;
+
+// The permission bits of every inode.
+const INODE_MODE : u64 = 0o644;
+
+// The number of inodes or directories each worker thread encodes at a time.
+const PB_CHUNK_LEN : usize = 1024;
+
+// The sections of the image, in the order the NameNode saves them.  See
+// FSImageFormatProtobuf.SectionName
+static SECTION_NAMES : [&'static str; 10] = [ "NS_INFO", "INODE", "INODE_DIR",
+    "FILES_UNDERCONSTRUCTION", "SNAPSHOT", "SNAPSHOT_DIFF", "INODE_REFERENCE",
+    "SECRET_MANAGER", "CACHE_MANAGER", "STRING_TABLE" ];
+
+impl FSImage {
+    // Write the binary fsimage to path, and its MD5 checksum to path.md5.
+    // The sections are compressed with output.image_codec, each on its own
+    // worker thread.
+    pub fn write_binary_image(&self, path: &str) -> Result<(), FsgenError> {
+        self.progress.start_phase("binary_image", "entries",
+                                  (self.inode_map.len() + self.children.len()) as u64);
+        let io_error = |e| FsgenError::io(path, e);
+        let num_threads = self.config.num_threads as usize;
+        let mut sections = vec![];
+        for name in SECTION_NAMES.iter() {
+            let section = try!(self.binary_section(name, path, num_threads));
+            sections.push((*name, Mutex::new(section)));
+        }
+        let codec = self.config.image_codec;
+        try!(run_tasks(&self.progress, num_threads, &sections, |(_, section)| {
+            let mut data = section.lock().unwrap();
+            let compressed = try!(codec.compress(&data).map_err(|e| FsgenError::io(path, e)));
+            *data = compressed;
+            return Result::Ok(());
+        }));
+
+        let mut summary = vec![];
+        write_uint_field(&mut summary, 1, FILE_VERSION);
+        // The layout version is negative, but the field is a uint32.
+        write_uint_field(&mut summary, 2, self.config.nn_layout_version as u32 as u64);
+        if !codec.class_name().is_empty() {
+            write_bytes_field(&mut summary, 3, codec.class_name().as_bytes());
+        }
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path).map_err(&io_error));
+        let mut w = BufWriter::new(&file);
+        let mut digest = md5::Context::new();
+        try!(w.write_all(MAGIC_HEADER).map_err(&io_error));
+        digest.consume(MAGIC_HEADER);
+        let mut offset = MAGIC_HEADER.len() as u64;
+        for &(name, ref section) in &sections {
+            let data = section.lock().unwrap();
+            let mut section_summary = vec![];
+            write_bytes_field(&mut section_summary, 1, name.as_bytes());
+            write_uint_field(&mut section_summary, 2, data.len() as u64);
+            write_uint_field(&mut section_summary, 3, offset);
+            write_bytes_field(&mut summary, 4, &section_summary);
+            try!(w.write_all(&data).map_err(&io_error));
+            digest.consume(&data[..]);
+            offset = offset + data.len() as u64;
+        }
+        // The FileSummary goes at the end, followed by its length, including
+        // the varint length prefix, as a 4-byte big-endian integer.
+        let mut trailer = vec![];
+        write_delimited(&mut trailer, &summary);
+        let summary_len = trailer.len();
+        trailer.extend_from_slice(&[ (summary_len >> 24) as u8, (summary_len >> 16) as u8,
+                                     (summary_len >> 8) as u8, summary_len as u8 ]);
+        try!(w.write_all(&trailer).map_err(&io_error));
+        digest.consume(&trailer);
+        try!(w.flush().map_err(&io_error));
+        let md5_path = format!("{}.md5", path);
+        try!(write_md5_file(&md5_path, path, &format!("{:x}", digest.compute()))
+             .map_err(|e| FsgenError::io(&md5_path, e)));
+        self.progress.finish_phase();
+        return Result::Ok(());
+    }
+
+    // Get the uncompressed contents of a section of the binary fsimage at
+    // path.
+    fn binary_section(&self, name: &str, path: &str, num_threads: usize)
+            -> Result<Vec<u8>, FsgenError> {
+        let mut buf = vec![];
+        match name {
+            "NS_INFO" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, self.config.namespace_id);
+                write_uint_field(&mut msg, 2, 1000);
+                write_uint_field(&mut msg, 3, self.next_genstamp as u64);
+                write_uint_field(&mut msg, 4, 0);
+                write_uint_field(&mut msg, 5, (self.next_block_id - 1) as u64);
+                write_uint_field(&mut msg, 6, LAST_TXID);
+                write_delimited(&mut buf, &msg);
+            },
+            "INODE" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, (self.next_inode_id - 1) as u64);
+                write_uint_field(&mut msg, 2, self.num_inodes as u64);
+                write_delimited(&mut buf, &msg);
+                let inodes : Vec<&INode> = self.inode_map.values().collect();
+                let preferred_block_size = self.config.preferred_block_size;
+                try!(write_chunks(&mut buf, path, &self.progress, num_threads, &inodes,
+                                  PB_CHUNK_LEN, |chunk_buf, inode| {
+                    write_delimited(chunk_buf, &inode.to_protobuf(preferred_block_size));
+                    return Result::Ok(());
+                }));
+            },
+            "INODE_DIR" => {
+                let directories : Vec<(&u32, &Vec<u32>)> = self.children.iter().collect();
+                try!(write_chunks(&mut buf, path, &self.progress, num_threads, &directories,
+                                  PB_CHUNK_LEN, |chunk_buf, &(parent_id, id_vec)| {
+                    let mut msg = vec![];
+                    write_uint_field(&mut msg, 1, *parent_id as u64);
+                    let mut children = vec![];
+                    for child_id in id_vec {
+                        write_varint(&mut children, *child_id as u64);
+                    }
+                    if !children.is_empty() {
+                        write_bytes_field(&mut msg, 2, &children);
+                    }
+                    write_delimited(chunk_buf, &msg);
+                    return Result::Ok(());
+                }));
+            },
+            "SNAPSHOT" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, 0);
+                write_uint_field(&mut msg, 3, 0);
+                write_delimited(&mut buf, &msg);
+            },
+            "SNAPSHOT_DIFF" => {
+                // An empty DIRECTORYDIFF list for the root directory.
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, 2);
+                write_uint_field(&mut msg, 2, ROOT_INODE_ID as u64);
+                write_uint_field(&mut msg, 3, 0);
+                write_delimited(&mut buf, &msg);
+            },
+            "SECRET_MANAGER" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, self.delegation_keys.len() as u64);
+                write_uint_field(&mut msg, 2, self.delegation_tokens.len() as u64);
+                write_uint_field(&mut msg, 3, self.delegation_keys.len() as u64);
+                write_uint_field(&mut msg, 4, self.delegation_tokens.len() as u64);
+                write_delimited(&mut buf, &msg);
+                for key in &self.delegation_keys {
+                    let mut msg = vec![];
+                    write_uint_field(&mut msg, 1, key.id as u64);
+                    write_uint_field(&mut msg, 2, key.expiry_date);
+                    write_bytes_field(&mut msg, 3, &key.key);
+                    write_delimited(&mut buf, &msg);
+                }
+                for token in &self.delegation_tokens {
+                    let mut msg = vec![];
+                    write_bytes_field(&mut msg, 2, token.owner.as_bytes());
+                    write_bytes_field(&mut msg, 3, token.renewer.as_bytes());
+                    write_bytes_field(&mut msg, 4, token.real_user.as_bytes());
+                    write_uint_field(&mut msg, 5, token.issue_date);
+                    write_uint_field(&mut msg, 6, token.max_date);
+                    write_uint_field(&mut msg, 7, token.sequence_number as u64);
+                    write_uint_field(&mut msg, 8, token.master_key_id as u64);
+                    write_uint_field(&mut msg, 9, token.expiry_date);
+                    write_delimited(&mut buf, &msg);
+                }
+            },
+            "CACHE_MANAGER" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, (self.cache_directives.len() + 1) as u64);
+                write_uint_field(&mut msg, 2, self.cache_pools.len() as u64);
+                write_uint_field(&mut msg, 3, self.cache_directives.len() as u64);
+                write_delimited(&mut buf, &msg);
+                for pool in &self.cache_pools {
+                    let mut msg = vec![];
+                    write_bytes_field(&mut msg, 1, pool.name.as_bytes());
+                    write_bytes_field(&mut msg, 2, pool.owner.as_bytes());
+                    write_bytes_field(&mut msg, 3, pool.group.as_bytes());
+                    write_uint_field(&mut msg, 4, pool.mode as u64);
+                    write_uint_field(&mut msg, 5, pool.limit);
+                    write_uint_field(&mut msg, 6, pool.max_relative_expiry);
+                    write_delimited(&mut buf, &msg);
+                }
+                for directive in &self.cache_directives {
+                    let mut expiration = vec![];
+                    write_uint_field(&mut expiration, 1, directive.expiry_time);
+                    write_uint_field(&mut expiration, 2, 0);
+                    let mut msg = vec![];
+                    write_uint_field(&mut msg, 1, directive.id);
+                    write_bytes_field(&mut msg, 2, directive.path.as_bytes());
+                    write_uint_field(&mut msg, 3, directive.replication as u64);
+                    write_bytes_field(&mut msg, 4, directive.pool.as_bytes());
+                    write_bytes_field(&mut msg, 5, &expiration);
+                    write_delimited(&mut buf, &msg);
+                }
+            },
+            "STRING_TABLE" => {
+                let mut msg = vec![];
+                write_uint_field(&mut msg, 1, 1 + self.owners.len() as u64);
+                write_delimited(&mut buf, &msg);
+                let mut strings = vec![ (USER_STRING_ID, DEFAULT_OWNER),
+                        (GROUP_STRING_ID, "supergroup") ];
+                for (idx, owner) in self.owners.iter().enumerate().skip(1) {
+                    strings.push((owner_string_id(idx as u16), owner.as_str()));
+                }
+                for &(id, string) in strings.iter() {
+                    let mut msg = vec![];
+                    write_uint_field(&mut msg, 1, id);
+                    write_bytes_field(&mut msg, 2, string.as_bytes());
+                    write_delimited(&mut buf, &msg);
+                }
+            },
+            // FILES_UNDERCONSTRUCTION and INODE_REFERENCE have no entries.
+            _ => (),
+        }
+        return Result::Ok(buf);
+    }
+}
+
+// The string table ID of the owner with the given index in FSImage#owners.
+fn owner_string_id(owner: u16) -> u64 {
+    if owner == 0 {
+        return USER_STRING_ID;
+    }
+    return GROUP_STRING_ID + owner as u64;
+}
+
+impl INode {
+    // Encode this inode as an INodeSection.INode message.
+    pub fn to_protobuf(&self, preferred_block_size: u64) -> Vec<u8> {
+        // The owner and group are string table IDs.  See
+        // FSImageFormatPBINode#buildPermissionStatus
+        let permission = (owner_string_id(self.owner) << 40) | (GROUP_STRING_ID << 16) |
+            INODE_MODE;
+        let mut msg = vec![];
+        let mut inode = vec![];
+        if self.is_dir {
+            write_uint_field(&mut inode, 1, 0);
+            // No namespace or space quota.
+            write_uint_field(&mut inode, 2, -1i64 as u64);
+            write_uint_field(&mut inode, 3, -1i64 as u64);
+            write_fixed64_field(&mut inode, 4, permission);
+            write_uint_field(&mut msg, 1, 2);
+        } else {
+            write_uint_field(&mut inode, 1, self.replication as u64);
+            write_uint_field(&mut inode, 2, 0);
+            write_uint_field(&mut inode, 3, 0);
+            write_uint_field(&mut inode, 4, preferred_block_size);
+            write_fixed64_field(&mut inode, 5, permission);
+            for block in &self.blocks {
+                let mut block_msg = vec![];
+                write_uint_field(&mut block_msg, 1, block.id as u64);
+                write_uint_field(&mut block_msg, 2, block.genstamp as u64);
+                write_uint_field(&mut block_msg, 3, block.num_bytes);
+                write_bytes_field(&mut inode, 6, &block_msg);
+            }
+            write_uint_field(&mut msg, 1, 1);
+        }
+        write_uint_field(&mut msg, 2, self.id as u64);
+        write_bytes_field(&mut msg, 3, self.name.as_bytes());
+        write_bytes_field(&mut msg, if self.is_dir { 5 } else { 4 }, &inode);
+        return msg;
+    }
+}
+
+// Write the MD5 file which the NameNode checks the fsimage at image_path
+// against.  See MD5FileUtils#saveMD5File
+fn write_md5_file(path: &str, image_path: &str, digest: &str) -> Result<(), Error> {
+    let file_name = match Path::new(image_path).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => image_path.to_owned(),
+    };
+    let file = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    let mut w = BufWriter::new(&file);
+    try!(write!(w, "{} *{}\n", digest, file_name));
+    return Result::Ok(());
+}
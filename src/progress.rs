@@ -0,0 +1,348 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Progress reporting for the phases of a run: how long each one takes, how
+// fast it is going and when it should finish.  Reports are printed every
+// output.progress_interval seconds, and when each phase finishes.  With
+// output.progress_json, each report is also written to stderr as a JSON
+// object on a line of its own, for scripts which drive fsgen:
+//
+// {"event":"phaseStart","phase":"block_files","unit":"files","totalItems":40000}
+// {"event":"progress","phase":"block_files","elapsedSecs":10.002,"items":12000,...}
+// {"event":"stall","phase":"block_files","thread":3,"idleSecs":60.011}
+// {"event":"phaseEnd","phase":"block_files","secs":31.7,"items":40000,...}
+// {"event":"done","elapsedSecs":40.2,"phases":{"namespace":1.204,...}}
+//
+// While worker threads are running tasks, a monitor thread makes the
+// periodic reports, and warns about any worker which has made no progress
+// for output.stall_timeout seconds.
+
+use config::Config;
+use std::cell::Cell;
+use std::cmp;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+// How often the monitor thread checks on the workers, in milliseconds.
+pub const MONITOR_TICK_MS : u64 = 1000;
+
+thread_local! {
+    // The index of the worker thread we are running on, if any.
+    static WORKER_IDX : Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// The phase which is running.
+struct Phase {
+    name: &'static str,
+
+    // What the items of the phase are, like "files" or "inodes"
+    unit: &'static str,
+
+    // The number of items we expect to process, or 0 if we don't know
+    total_items: u64,
+
+    // When the phase started, in milliseconds since the run started
+    start_ms: u64,
+}
+
+// How long a phase took, and how much it did.
+#[derive(Clone)]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub items: u64,
+    pub bytes: u64,
+    pub secs: f64,
+}
+
+// The state of a worker thread, for stall detection.
+struct Worker {
+    busy: AtomicBool,
+
+    // When the worker last made progress, in milliseconds since the run
+    // started
+    last_progress_ms: AtomicU64,
+
+    // Set once we have warned that the worker is stalled
+    stalled: AtomicBool,
+}
+
+pub struct Progress {
+    interval_ms: u64,
+    json: bool,
+    stall_timeout_ms: u64,
+    start: Instant,
+    phase: Mutex<Option<Phase>>,
+
+    // The items and bytes processed so far in this phase
+    items: AtomicU64,
+    bytes: AtomicU64,
+
+    // When we last reported, in milliseconds since the run started
+    last_report_ms: AtomicU64,
+
+    timings: Mutex<Vec<PhaseTiming>>,
+    workers: Vec<Worker>,
+}
+
+impl Progress {
+    pub fn new(config: &Config) -> Progress {
+        return Progress {
+            interval_ms: config.progress_interval as u64 * 1000,
+            json: config.progress_json,
+            stall_timeout_ms: config.stall_timeout as u64 * 1000,
+            start: Instant::now(),
+            phase: Mutex::new(None),
+            items: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            last_report_ms: AtomicU64::new(0),
+            timings: Mutex::new(vec![]),
+            workers: (0..cmp::max(1, config.num_threads)).map(|_| Worker {
+                busy: AtomicBool::new(false),
+                last_progress_ms: AtomicU64::new(0),
+                stalled: AtomicBool::new(false),
+            }).collect(),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        let elapsed = self.start.elapsed();
+        return elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+    }
+
+    // Start a phase, which expects to process total_items items of the given
+    // unit.  total_items may be 0 if we don't know.
+    pub fn start_phase(&self, name: &'static str, unit: &'static str, total_items: u64) {
+        let now_ms = self.elapsed_ms();
+        *self.phase.lock().unwrap() = Some(Phase {
+            name: name,
+            unit: unit,
+            total_items: total_items,
+            start_ms: now_ms,
+        });
+        self.items.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.last_report_ms.store(now_ms, Ordering::Relaxed);
+        if self.json {
+            eprintln!("{{\"event\":\"phaseStart\",\"phase\":\"{}\",\"unit\":\"{}\",\
+                       \"totalItems\":{}}}", name, unit, total_items);
+        }
+    }
+
+    // Count some items and bytes processed by the current phase.  On a
+    // worker thread, this also shows that the worker isn't stalled.
+    pub fn add(&self, items: u64, bytes: u64) {
+        self.items.fetch_add(items, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        match WORKER_IDX.with(|idx| idx.get()) {
+            Some(worker_idx) => self.heartbeat(worker_idx),
+            // Nothing is monitoring the main thread, so it reports for
+            // itself.
+            None => self.report_if_due(),
+        }
+    }
+
+    // Finish the current phase, and report how long it took.
+    pub fn finish_phase(&self) {
+        let now_ms = self.elapsed_ms();
+        let phase = match self.phase.lock().unwrap().take() {
+            None => return,
+            Some(phase) => phase,
+        };
+        let timing = PhaseTiming {
+            name: phase.name,
+            unit: phase.unit,
+            items: self.items.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            secs: (now_ms - phase.start_ms) as f64 / 1000.0,
+        };
+        let mut line = format!("** {} took {}: {} {}", timing.name,
+                               format_secs(timing.secs), timing.items, timing.unit);
+        if timing.secs > 0.0 {
+            line.push_str(&format!(", {:.0} {}/s", timing.items as f64 / timing.secs,
+                                   timing.unit));
+            if timing.bytes > 0 {
+                line.push_str(&format!(", {:.1} MB/s", mb(timing.bytes) / timing.secs));
+            }
+        }
+        println!("{}", line);
+        if self.json {
+            eprintln!("{{\"event\":\"phaseEnd\",\"phase\":\"{}\",\"secs\":{:.3},\"items\":{},\
+                       \"bytes\":{}}}", timing.name, timing.secs, timing.items, timing.bytes);
+        }
+        self.timings.lock().unwrap().push(timing);
+    }
+
+    // Get the timings of the phases which have finished, in order.
+    pub fn timings(&self) -> Vec<PhaseTiming> {
+        return self.timings.lock().unwrap().clone();
+    }
+
+    // Report the time each phase took, at the end of a run.
+    pub fn finish(&self) {
+        let secs = self.elapsed_ms() as f64 / 1000.0;
+        let timings = self.timings();
+        let phases : Vec<String> = timings.iter()
+            .map(|timing| format!("{} {}", timing.name, format_secs(timing.secs))).collect();
+        println!("** phase timings: {}; total {}", phases.join(", "), format_secs(secs));
+        if self.json {
+            let phases : Vec<String> = timings.iter()
+                .map(|timing| format!("\"{}\":{:.3}", timing.name, timing.secs)).collect();
+            eprintln!("{{\"event\":\"done\",\"elapsedSecs\":{:.3},\"phases\":{{{}}}}}",
+                      secs, phases.join(","));
+        }
+    }
+
+    // Report the progress of the current phase if it has been
+    // output.progress_interval seconds since the last report.
+    pub fn report_if_due(&self) {
+        if self.interval_ms == 0 {
+            return;
+        }
+        let now_ms = self.elapsed_ms();
+        let last_ms = self.last_report_ms.load(Ordering::Relaxed);
+        if now_ms < last_ms + self.interval_ms {
+            return;
+        }
+        // Only one thread reports at a time.
+        if self.last_report_ms.compare_exchange(last_ms, now_ms, Ordering::Relaxed,
+                                                Ordering::Relaxed).is_err() {
+            return;
+        }
+        self.report(now_ms);
+    }
+
+    fn report(&self, now_ms: u64) {
+        let phase = self.phase.lock().unwrap();
+        let phase = match *phase {
+            None => return,
+            Some(ref phase) => phase,
+        };
+        let items = self.items.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let secs = cmp::max(1, now_ms - phase.start_ms) as f64 / 1000.0;
+        let items_per_sec = items as f64 / secs;
+        let bytes_per_sec = bytes as f64 / secs;
+        let eta_secs = if (phase.total_items > items) && (items > 0) {
+            Some((phase.total_items - items) as f64 / items_per_sec)
+        } else {
+            None
+        };
+        let mut line = if phase.total_items > 0 {
+            format!("** {}: {}/{} {} ({:.0}%)", phase.name, items, phase.total_items,
+                    phase.unit, 100.0 * items as f64 / phase.total_items as f64)
+        } else {
+            format!("** {}: {} {}", phase.name, items, phase.unit)
+        };
+        line.push_str(&format!(", {:.0} {}/s", items_per_sec, phase.unit));
+        if bytes > 0 {
+            line.push_str(&format!(", {:.1} MB/s", mb(bytes) / secs));
+        }
+        if let Some(eta_secs) = eta_secs {
+            line.push_str(&format!(", ETA {}", format_secs(eta_secs)));
+        }
+        println!("{}", line);
+        if self.json {
+            let eta = match eta_secs {
+                None => "null".to_owned(),
+                Some(eta_secs) => format!("{:.0}", eta_secs),
+            };
+            eprintln!("{{\"event\":\"progress\",\"phase\":\"{}\",\"elapsedSecs\":{:.3},\
+                       \"items\":{},\"totalItems\":{},\"bytes\":{},\"itemsPerSec\":{:.1},\
+                       \"bytesPerSec\":{:.1},\"etaSecs\":{}}}", phase.name, secs, items,
+                      phase.total_items, bytes, items_per_sec, bytes_per_sec, eta);
+        }
+    }
+
+    // Mark this thread as worker worker_idx until leave_worker is called.
+    pub fn enter_worker(&self, worker_idx: usize) {
+        WORKER_IDX.with(|idx| idx.set(Some(worker_idx)));
+        if let Some(worker) = self.workers.get(worker_idx) {
+            worker.busy.store(true, Ordering::Relaxed);
+        }
+        self.heartbeat(worker_idx);
+    }
+
+    pub fn leave_worker(&self) {
+        if let Some(worker_idx) = WORKER_IDX.with(|idx| idx.replace(None)) {
+            if let Some(worker) = self.workers.get(worker_idx) {
+                worker.busy.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Note that a worker thread has made progress.
+    fn heartbeat(&self, worker_idx: usize) {
+        let worker = match self.workers.get(worker_idx) {
+            None => return,
+            Some(worker) => worker,
+        };
+        worker.last_progress_ms.store(self.elapsed_ms(), Ordering::Relaxed);
+        if worker.stalled.swap(false, Ordering::Relaxed) {
+            println!("** worker thread {} is making progress again.", worker_idx + 1);
+        }
+    }
+
+    // Warn about any busy worker thread which has made no progress for
+    // output.stall_timeout seconds.  Each stall is reported once.
+    pub fn check_stalls(&self) {
+        if self.stall_timeout_ms == 0 {
+            return;
+        }
+        let now_ms = self.elapsed_ms();
+        for (worker_idx, worker) in self.workers.iter().enumerate() {
+            if !worker.busy.load(Ordering::Relaxed) {
+                continue;
+            }
+            let idle_ms = now_ms.saturating_sub(worker.last_progress_ms.load(Ordering::Relaxed));
+            if (idle_ms < self.stall_timeout_ms) || worker.stalled.swap(true, Ordering::Relaxed) {
+                continue;
+            }
+            let phase_name = match *self.phase.lock().unwrap() {
+                None => "",
+                Some(ref phase) => phase.name,
+            };
+            println!("** WARNING: worker thread {} has made no progress in {} for {}.",
+                     worker_idx + 1, phase_name, format_secs(idle_ms as f64 / 1000.0));
+            if self.json {
+                eprintln!("{{\"event\":\"stall\",\"phase\":\"{}\",\"thread\":{},\
+                           \"idleSecs\":{:.3}}}", phase_name, worker_idx + 1,
+                          idle_ms as f64 / 1000.0);
+            }
+        }
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    return bytes as f64 / (1024.0 * 1024.0);
+}
+
+// Format a number of seconds like 4.2s, 3m05s or 2h07m.
+fn format_secs(secs: f64) -> String {
+    if secs < 60.0 {
+        return format!("{:.1}s", secs);
+    }
+    let secs = secs as u64;
+    if secs < 3600 {
+        return format!("{}m{:02}s", secs / 60, secs % 60);
+    }
+    return format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60);
+}
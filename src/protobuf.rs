@@ -0,0 +1,209 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Just enough of the protocol buffer wire format to read and write the
+// messages in a binary fsimage, and to write the DataNode replica cache.  See
+// https://developers.google.com/protocol-buffers/docs/encoding
+
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+
+// The value of a field in a protobuf message.
+pub enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> ProtoValue<'a> {
+    pub fn as_u64(&self) -> Result<u64, Error> {
+        match *self {
+            ProtoValue::Varint(val) => return Result::Ok(val),
+            ProtoValue::Fixed64(val) => return Result::Ok(val),
+            ProtoValue::Fixed32(val) => return Result::Ok(val as u64),
+            ProtoValue::Bytes(_) => return Err(invalid("expected an integer field")),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&'a [u8], Error> {
+        match *self {
+            ProtoValue::Bytes(val) => return Result::Ok(val),
+            _ => return Err(invalid("expected a length-delimited field")),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String, Error> {
+        let bytes = try!(self.as_bytes());
+        return Result::Ok(String::from_utf8_lossy(bytes).into_owned());
+    }
+}
+
+// Reads the fields of a single protobuf message, in order.
+pub struct ProtoReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    pub fn new(buf: &'a [u8]) -> ProtoReader<'a> {
+        return ProtoReader {
+            buf: buf,
+            pos: 0,
+        }
+    }
+
+    // Get the next field number and value, or None at the end of the message.
+    pub fn next_field(&mut self) -> Result<Option<(u32, ProtoValue<'a>)>, Error> {
+        if self.pos >= self.buf.len() {
+            return Result::Ok(None);
+        }
+        let key = try!(self.read_varint());
+        let field_num = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(try!(self.read_varint())),
+            1 => {
+                let bytes = try!(self.read_bytes(8));
+                let val = bytes.iter().rev().fold(0u64, |val, b| (val << 8) | (*b as u64));
+                ProtoValue::Fixed64(val)
+            },
+            2 => {
+                let len = try!(self.read_varint()) as usize;
+                ProtoValue::Bytes(try!(self.read_bytes(len)))
+            },
+            5 => {
+                let bytes = try!(self.read_bytes(4));
+                let val = bytes.iter().rev().fold(0u32, |val, b| (val << 8) | (*b as u32));
+                ProtoValue::Fixed32(val)
+            },
+            wire_type => return Err(invalid(&format!("unsupported wire type {}", wire_type))),
+        };
+        return Result::Ok(Some((field_num, value)));
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut val = 0u64;
+        let mut shift = 0;
+        loop {
+            if self.pos >= self.buf.len() {
+                return Err(invalid("truncated varint"));
+            }
+            let b = self.buf[self.pos];
+            self.pos = self.pos + 1;
+            if shift < 64 {
+                val = val | (((b & 0x7f) as u64) << shift);
+            }
+            if (b & 0x80) == 0 {
+                return Result::Ok(val);
+            }
+            shift = shift + 7;
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() - self.pos < len {
+            return Err(invalid("truncated field"));
+        }
+        let bytes = &self.buf[self.pos..(self.pos + len)];
+        self.pos = self.pos + len;
+        return Result::Ok(bytes);
+    }
+}
+
+// Read a varint from a stream.  Returns None at the end of the stream.
+pub fn read_varint_from<R: Read + ?Sized>(r: &mut R) -> Result<Option<u64>, Error> {
+    let mut val = 0u64;
+    let mut shift = 0;
+    let mut first = true;
+    loop {
+        let mut b = [0u8; 1];
+        if try!(r.read(&mut b)) == 0 {
+            if first {
+                return Result::Ok(None);
+            }
+            return Err(invalid("truncated varint"));
+        }
+        first = false;
+        if shift < 64 {
+            val = val | (((b[0] & 0x7f) as u64) << shift);
+        }
+        if (b[0] & 0x80) == 0 {
+            return Result::Ok(Some(val));
+        }
+        shift = shift + 7;
+    }
+}
+
+// Read a length-prefixed message, as written by writeDelimitedTo.
+pub fn read_delimited<R: Read + ?Sized>(r: &mut R) -> Result<Vec<u8>, Error> {
+    let len = match try!(read_varint_from(r)) {
+        None => return Err(Error::new(ErrorKind::UnexpectedEof, "expected a message")),
+        Some(len) => len as usize,
+    };
+    let mut buf = vec![0u8; len];
+    try!(r.read_exact(&mut buf));
+    return Result::Ok(buf);
+}
+
+// Append a varint to buf.
+pub fn write_varint(buf: &mut Vec<u8>, val: u64) {
+    let mut val = val;
+    while val >= 0x80 {
+        buf.push(((val & 0x7f) as u8) | 0x80);
+        val = val >> 7;
+    }
+    buf.push(val as u8);
+}
+
+// Append the key of a field to buf.
+pub fn write_key(buf: &mut Vec<u8>, field_num: u32, wire_type: u8) {
+    write_varint(buf, ((field_num as u64) << 3) | (wire_type as u64));
+}
+
+// Append a varint field to buf.  Signed values are written as their two's
+// complement, like int64 fields.
+pub fn write_uint_field(buf: &mut Vec<u8>, field_num: u32, val: u64) {
+    write_key(buf, field_num, 0);
+    write_varint(buf, val);
+}
+
+// Append a fixed64 field to buf.
+pub fn write_fixed64_field(buf: &mut Vec<u8>, field_num: u32, val: u64) {
+    write_key(buf, field_num, 1);
+    for shift in 0..8 {
+        buf.push((val >> (8 * shift)) as u8);
+    }
+}
+
+// Append a bytes, string or embedded message field to buf.
+pub fn write_bytes_field(buf: &mut Vec<u8>, field_num: u32, val: &[u8]) {
+    write_key(buf, field_num, 2);
+    write_varint(buf, val.len() as u64);
+    buf.extend_from_slice(val);
+}
+
+// Append a length-prefixed message to buf, like writeDelimitedTo.
+pub fn write_delimited(buf: &mut Vec<u8>, msg: &[u8]) {
+    write_varint(buf, msg.len() as u64);
+    buf.extend_from_slice(msg);
+}
+
+fn invalid(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("invalid protobuf: {}", message));
+}
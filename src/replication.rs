@@ -0,0 +1,142 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The replication factor of each file.  The first [[replication]] rule whose
+// pattern matches the path of a file sets it.  Otherwise it is drawn from
+// files.replication_weights, if there are any, or is files.replication.
+
+use namespace::FSImage;
+use rand::Rng;
+
+// A rule which sets the replication factor of the files matching a pattern.
+pub struct ReplicationRule {
+    // A glob of the paths the rule applies to.  * matches any part of one
+    // path component, and ** any number of whole components.
+    pub pattern: String,
+
+    pub replication: u16,
+
+    // Where the rule was configured, for errors
+    pub location: String,
+}
+
+impl ReplicationRule {
+    pub fn matches(&self, path: &str) -> bool {
+        let pattern : Vec<&str> = self.pattern.split('/').filter(|c| !c.is_empty()).collect();
+        let components : Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        return glob_matches(&pattern, &components);
+    }
+}
+
+// Check whether the components of a path match the components of a glob.
+fn glob_matches(pattern: &[&str], components: &[&str]) -> bool {
+    if pattern.is_empty() {
+        return components.is_empty();
+    }
+    if pattern[0] == "**" {
+        for skip in 0..(components.len() + 1) {
+            if glob_matches(&pattern[1..], &components[skip..]) {
+                return true;
+            }
+        }
+        return false;
+    }
+    if components.is_empty() || !component_matches(pattern[0], components[0]) {
+        return false;
+    }
+    return glob_matches(&pattern[1..], &components[1..]);
+}
+
+// Check whether a path component matches a glob component, in which *
+// matches any run of characters.
+fn component_matches(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => return pattern == name,
+        Some(idx) => {
+            let prefix = &pattern[..idx];
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let rest = &pattern[idx + 1..];
+            let name = &name[prefix.len()..];
+            for (start, _) in name.char_indices().chain(Some((name.len(), ' '))) {
+                if component_matches(rest, &name[start..]) {
+                    return true;
+                }
+            }
+            return false;
+        },
+    }
+}
+
+// Parse a list of replication factors and their weights, like
+// 1:10,3:85,10:5.
+pub fn parse_replication_weights(text: &str) -> Result<Vec<(u16, u32)>, String> {
+    let mut weights = vec![];
+    if text.is_empty() {
+        return Result::Ok(weights);
+    }
+    for item in text.split(',') {
+        let fields : Vec<&str> = item.trim().split(':').collect();
+        if fields.len() != 2 {
+            return Err(format!("expected REPLICATION:WEIGHT, got {}", item));
+        }
+        let replication = match fields[0].parse::<u16>() {
+            Ok(val) if val > 0 => val,
+            _ => return Err(format!("{} is not a valid replication factor", fields[0])),
+        };
+        let weight = match fields[1].parse::<u32>() {
+            Ok(val) => val,
+            Err(_) => return Err(format!("{} is not a valid weight", fields[1])),
+        };
+        weights.push((replication, weight));
+    }
+    if weights.iter().all(|&(_, weight)| weight == 0) {
+        return Err("at least one weight must be more than 0".to_owned());
+    }
+    return Result::Ok(weights);
+}
+
+impl FSImage {
+    // Choose the replication factor of a new file.  The path is only needed
+    // when there are rules.
+    pub(crate) fn choose_replication<F>(&self, rng: &mut Rng, path: F) -> u16
+            where F: FnOnce() -> String {
+        if !self.config.replication_rules.is_empty() {
+            let path = path();
+            for rule in &self.config.replication_rules {
+                if rule.matches(&path) {
+                    return rule.replication;
+                }
+            }
+        }
+        let weights = &self.config.replication_weights;
+        if weights.is_empty() {
+            return self.config.repl;
+        }
+        let total : u64 = weights.iter().map(|&(_, weight)| weight as u64).sum();
+        let mut val = rng.next_u64() % total;
+        for &(replication, weight) in weights {
+            if val < weight as u64 {
+                return replication;
+            }
+            val = val - weight as u64;
+        }
+        return self.config.repl;
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use config::Config;
+use config::ConfigError;
+use config::ConfigSources;
+use image::LoadedImage;
+use namespace::FSImage;
+use rand::ChaChaRng;
+use volume::VolumeChoosingPolicy;
+use volume::VolumeGroup;
+
+// Builds a description of the cluster to generate.
+//
+// Every setting starts out with the same default as the fsgen command-line
+// tool.  Settings which have no builder method can be changed through
+// config_mut().
+pub struct ClusterSpec {
+    config: Config,
+}
+
+impl ClusterSpec {
+    pub fn new() -> ClusterSpec {
+        return ClusterSpec::from_config(Config::new());
+    }
+
+    pub fn from_config(config: Config) -> ClusterSpec {
+        return ClusterSpec {
+            config: config,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        return &self.config;
+    }
+
+    pub fn config_mut(&mut self) -> &mut Config {
+        return &mut self.config;
+    }
+
+    pub fn namespace_id(mut self, namespace_id: u64) -> ClusterSpec {
+        self.config.namespace_id = namespace_id;
+        return self;
+    }
+
+    pub fn cluster_id(mut self, cluster_id: &str) -> ClusterSpec {
+        self.config.cluster_id = cluster_id.to_owned();
+        return self;
+    }
+
+    pub fn block_pool_id(mut self, block_pool_id: &str) -> ClusterSpec {
+        self.config.block_pool_id = block_pool_id.to_owned();
+        return self;
+    }
+
+    pub fn cluster_ctime(mut self, cluster_ctime: u64) -> ClusterSpec {
+        self.config.cluster_ctime = cluster_ctime;
+        return self;
+    }
+
+    pub fn num_datanodes(mut self, num_datanodes: u16) -> ClusterSpec {
+        self.config.num_datanodes = num_datanodes;
+        return self;
+    }
+
+    pub fn num_racks(mut self, num_racks: u16) -> ClusterSpec {
+        self.config.num_racks = num_racks;
+        return self;
+    }
+
+    pub fn storage_dirs_per_datanode(mut self, num_storage_dirs: u16) -> ClusterSpec {
+        self.config.num_storage_dirs_per_dn = num_storage_dirs;
+        return self;
+    }
+
+    pub fn volume_choosing_policy(mut self, policy: VolumeChoosingPolicy) -> ClusterSpec {
+        self.config.volume_choosing_policy = policy;
+        return self;
+    }
+
+    // Add a group of volumes to some of the datanodes.  Datanodes which
+    // aren't in any group get storage_dirs_per_datanode unlimited volumes.
+    pub fn volume_group(mut self, group: VolumeGroup) -> ClusterSpec {
+        self.config.volume_groups.push(group);
+        return self;
+    }
+
+    pub fn num_inodes(mut self, num_inodes: u32) -> ClusterSpec {
+        self.config.num_inodes = num_inodes;
+        return self;
+    }
+
+    // Set the number of entries in each directory, and how many of those
+    // entries are themselves directories.
+    pub fn directory_shape(mut self, entries_per_dir: usize,
+                           dirs_per_dir: usize) -> ClusterSpec {
+        self.config.entries_per_dir = entries_per_dir;
+        self.config.dirs_per_dir = dirs_per_dir;
+        return self;
+    }
+
+    pub fn replication(mut self, repl: u16) -> ClusterSpec {
+        self.config.repl = repl;
+        return self;
+    }
+
+    pub fn preferred_block_size(mut self, preferred_block_size: u64) -> ClusterSpec {
+        self.config.preferred_block_size = preferred_block_size;
+        return self;
+    }
+
+    // Set the range which file sizes are uniformly drawn from, in bytes.
+    pub fn file_size_range(mut self, min_size: u64, max_size: u64) -> ClusterSpec {
+        self.config.min_file_size = min_size;
+        self.config.max_file_size = max_size;
+        return self;
+    }
+
+    pub fn cache_pools(mut self, num_pools: u32) -> ClusterSpec {
+        self.config.num_cache_pools = num_pools;
+        return self;
+    }
+
+    pub fn cache_directives(mut self, num_directives: u32) -> ClusterSpec {
+        self.config.num_cache_directives = num_directives;
+        return self;
+    }
+
+    pub fn delegation_keys(mut self, num_keys: u32) -> ClusterSpec {
+        self.config.num_delegation_keys = num_keys;
+        return self;
+    }
+
+    pub fn delegation_tokens(mut self, num_tokens: u32) -> ClusterSpec {
+        self.config.num_delegation_tokens = num_tokens;
+        return self;
+    }
+
+    pub fn seed(mut self, seed: u64) -> ClusterSpec {
+        self.config.seed = seed;
+        return self;
+    }
+
+    pub fn num_threads(mut self, num_threads: u32) -> ClusterSpec {
+        self.config.num_threads = num_threads;
+        return self;
+    }
+
+    // Validate the spec and generate the in-memory image it describes.  The
+    // same spec and seed always generate the same namespace.
+    pub fn generate(self) -> Result<FSImage, ConfigError> {
+        try!(self.config.validate_model(&ConfigSources::new()));
+        let mut rng = ChaChaRng::new_unseeded();
+        rng.set_counter(self.config.seed, self.config.seed);
+        return FSImage::new(self.config, None, &mut rng);
+    }
+
+    // Validate the spec and grow the namespace of an existing fsimage by
+    // num_inodes inodes.  Inode IDs, block IDs and generation stamps carry on
+    // from where the image left off.  The datanode settings and seed should
+    // match the run which wrote the image, so that the new replicas go to the
+    // same datanode directories.
+    pub fn append(self, base: &LoadedImage) -> Result<FSImage, ConfigError> {
+        try!(self.config.validate_model(&ConfigSources::new()));
+        let mut rng = ChaChaRng::new_unseeded();
+        rng.set_counter(self.config.seed, self.config.seed);
+        return FSImage::new(self.config, Some(base), &mut rng);
+    }
+}
+
+impl Default for ClusterSpec {
+    fn default() -> ClusterSpec {
+        return ClusterSpec::new();
+    }
+}
@@ -0,0 +1,361 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use namespace::FSImage;
+use namespace::ROOT_INODE_ID;
+use std::collections::BTreeMap;
+use std::collections::LinkedList;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+
+// The rule of thumb for NameNode heap usage is about 150 bytes for each
+// file, directory and block.
+const HEAP_BYTES_PER_OBJECT : u64 = 150;
+
+// Each replica adds a triplet entry to the BlockInfo: three references.
+const HEAP_BYTES_PER_REPLICA : u64 = 24;
+
+// Each inode keeps its name in a byte[], which has a 16 byte header and is
+// padded to a multiple of 8 bytes.
+const HEAP_BYTES_PER_NAME_ARRAY : u64 = 16;
+
+// A summary of a set of values.
+pub struct Distribution {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+impl Distribution {
+    fn new(values: &mut [u64]) -> Distribution {
+        if values.is_empty() {
+            return Distribution { count: 0, min: 0, max: 0, mean: 0.0, p50: 0, p90: 0, p99: 0 };
+        }
+        values.sort();
+        let sum : u64 = values.iter().sum();
+        let percentile = |p: usize| values[((values.len() - 1) * p) / 100];
+        return Distribution {
+            count: values.len() as u64,
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: (sum as f64) / (values.len() as f64),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+
+    // The ratio of the largest value to the mean.  A perfectly balanced set
+    // of values has an imbalance of 1.0.
+    pub fn imbalance(&self) -> f64 {
+        if self.mean == 0.0 {
+            return 0.0;
+        }
+        return (self.max as f64) / self.mean;
+    }
+
+    fn to_text(&self) -> String {
+        return format!("min {}, p50 {}, p90 {}, p99 {}, max {}, mean {:.1}",
+                       self.min, self.p50, self.p90, self.p99, self.max, self.mean);
+    }
+
+    fn to_json(&self) -> String {
+        return format!("{{\"count\":{},\"min\":{},\"max\":{},\"mean\":{:.3},\"p50\":{},\
+                       \"p90\":{},\"p99\":{}}}", self.count, self.min, self.max, self.mean,
+                       self.p50, self.p90, self.p99);
+    }
+}
+
+// Statistics about the shape of a generated namespace, and how its replicas
+// are spread over the datanodes.
+pub struct NamespaceStats {
+    pub num_directories: u64,
+    pub num_files: u64,
+    pub num_blocks: u64,
+    pub num_replicas: u64,
+
+    // The total size of all files, not counting replication
+    pub total_file_bytes: u64,
+
+    // Maps depth (0 for the root) to the number of inodes at that depth
+    pub depth_histogram: BTreeMap<u32, u64>,
+
+    // Maps number of children to the number of directories with that many
+    pub children_histogram: BTreeMap<u64, u64>,
+
+    // Maps number of blocks to the number of files with that many
+    pub blocks_per_file_histogram: BTreeMap<u64, u64>,
+
+    // Maps replication factor to the number of files with it
+    pub replication_histogram: BTreeMap<u64, u64>,
+
+    pub file_size: Distribution,
+
+    // The length of each name, in bytes
+    pub name_length: Distribution,
+
+    // The heap taken by the names of the inodes
+    pub name_heap_bytes: u64,
+
+    // The number of replicas on each datanode
+    pub replicas_per_datanode: Vec<u64>,
+
+    // The number of replica bytes on each datanode
+    pub bytes_per_datanode: Vec<u64>,
+
+    // The number of replicas in each storage directory of each datanode
+    pub replicas_per_volume: Vec<Vec<u64>>,
+
+    // True if the image was appended to, in which case the replicas per
+    // datanode and volume only count the new replicas.  We don't know where
+    // the replicas of the image we appended to are.
+    pub new_replicas_only: bool,
+
+    // A rough estimate of the NameNode heap needed to load the image
+    pub estimated_heap_bytes: u64,
+
+    // A rough estimate of the size of the binary fsimage
+    pub estimated_fsimage_bytes: u64,
+}
+
+impl FSImage {
+    // Compute statistics about this image.
+    pub fn stats(&self) -> NamespaceStats {
+        let num_datanodes = self.config.num_datanodes as usize;
+        let mut stats = NamespaceStats {
+            num_directories: 0,
+            num_files: 0,
+            num_blocks: 0,
+            num_replicas: 0,
+            total_file_bytes: 0,
+            depth_histogram: BTreeMap::new(),
+            children_histogram: BTreeMap::new(),
+            blocks_per_file_histogram: BTreeMap::new(),
+            replication_histogram: BTreeMap::new(),
+            file_size: Distribution::new(&mut []),
+            name_length: Distribution::new(&mut []),
+            name_heap_bytes: 0,
+            replicas_per_datanode: vec![0; num_datanodes],
+            bytes_per_datanode: vec![0; num_datanodes],
+            replicas_per_volume: self.datanode_info.iter()
+                .map(|dn_info| vec![0; dn_info.volumes.len()]).collect(),
+            new_replicas_only: self.num_base_inodes > 0,
+            estimated_heap_bytes: 0,
+            estimated_fsimage_bytes: 0,
+        };
+
+        // Walk the tree breadth-first to find the depth of each inode.
+        let mut queue : LinkedList<(u32, u32)> = LinkedList::new();
+        queue.push_back((ROOT_INODE_ID, 0));
+        while let Some((id, depth)) = queue.pop_front() {
+            *stats.depth_histogram.entry(depth).or_insert(0) += 1;
+            if let Some(children) = self.children.get(&id) {
+                *stats.children_histogram.entry(children.len() as u64).or_insert(0) += 1;
+                for child in children {
+                    queue.push_back((*child, depth + 1));
+                }
+            }
+        }
+
+        let mut file_sizes = vec![];
+        let mut name_lengths = vec![];
+        for inode in self.inode_map.values() {
+            stats.estimated_fsimage_bytes = stats.estimated_fsimage_bytes +
+                estimate_inode_bytes(inode.name.len(), inode.blocks.len());
+            let name_len = inode.name.len() as u64;
+            name_lengths.push(name_len);
+            stats.name_heap_bytes = stats.name_heap_bytes +
+                (HEAP_BYTES_PER_NAME_ARRAY + name_len).div_ceil(8) * 8;
+            if inode.is_dir {
+                stats.num_directories = stats.num_directories + 1;
+                continue;
+            }
+            stats.num_files = stats.num_files + 1;
+            *stats.blocks_per_file_histogram.entry(inode.blocks.len() as u64)
+                .or_insert(0) += 1;
+            *stats.replication_histogram.entry(inode.replication as u64).or_insert(0) += 1;
+            let mut file_bytes = 0;
+            for block in &inode.blocks {
+                stats.num_blocks = stats.num_blocks + 1;
+                file_bytes = file_bytes + block.num_bytes;
+                if block.id < self.first_new_block_id {
+                    // A block of the image we appended to has its replicas
+                    // on disk already, rather than in block.datanodes.
+                    stats.num_replicas = stats.num_replicas + inode.replication as u64;
+                    continue;
+                }
+                for (datanode, storage) in block.datanodes.iter().zip(block.storages.iter()) {
+                    let dn = *datanode as usize;
+                    let storage = *storage as usize;
+                    stats.num_replicas = stats.num_replicas + 1;
+                    stats.replicas_per_datanode[dn] = stats.replicas_per_datanode[dn] + 1;
+                    stats.bytes_per_datanode[dn] = stats.bytes_per_datanode[dn] +
+                        block.num_bytes;
+                    stats.replicas_per_volume[dn][storage] =
+                        stats.replicas_per_volume[dn][storage] + 1;
+                }
+            }
+            stats.total_file_bytes = stats.total_file_bytes + file_bytes;
+            file_sizes.push(file_bytes);
+        }
+        stats.file_size = Distribution::new(&mut file_sizes);
+        stats.name_length = Distribution::new(&mut name_lengths);
+
+        // Each directory entry in the INodeDirectorySection takes about 5
+        // bytes.
+        stats.estimated_fsimage_bytes = stats.estimated_fsimage_bytes +
+            (self.parents.len() as u64) * 5;
+        stats.estimated_heap_bytes = (stats.num_directories + stats.num_files +
+            stats.num_blocks) * HEAP_BYTES_PER_OBJECT +
+            stats.num_replicas * HEAP_BYTES_PER_REPLICA + stats.name_heap_bytes;
+        return stats;
+    }
+}
+
+// Estimate the size of an INode message in the binary fsimage.  The fixed
+// fields (type, id, times, permission and so forth) take about 40 bytes, and
+// each block takes about 16.
+fn estimate_inode_bytes(name_len: usize, num_blocks: usize) -> u64 {
+    return 40 + (name_len as u64) + 16 * (num_blocks as u64);
+}
+
+impl NamespaceStats {
+    pub fn replicas_per_datanode_distribution(&self) -> Distribution {
+        return Distribution::new(&mut self.replicas_per_datanode.clone());
+    }
+
+    pub fn bytes_per_datanode_distribution(&self) -> Distribution {
+        return Distribution::new(&mut self.bytes_per_datanode.clone());
+    }
+
+    pub fn replicas_per_volume_distribution(&self) -> Distribution {
+        let mut values : Vec<u64> = self.replicas_per_volume.iter()
+            .flat_map(|volumes| volumes.iter().cloned()).collect();
+        return Distribution::new(&mut values);
+    }
+
+    // Format the statistics for people to read.
+    pub fn to_text(&self) -> String {
+        let mut ret = "** namespace statistics:\n".to_owned();
+        ret.push_str(&format!("   inodes: {} ({} directories, {} files)\n",
+                              self.num_directories + self.num_files,
+                              self.num_directories, self.num_files));
+        ret.push_str(&format!("   blocks: {} ({} replicas, {} bytes of file data)\n",
+                              self.num_blocks, self.num_replicas, self.total_file_bytes));
+        ret.push_str(&format!("   inodes by depth: {}\n",
+                              histogram_to_text(&self.depth_histogram)));
+        ret.push_str(&format!("   directories by number of children: {}\n",
+                              histogram_to_text(&self.children_histogram)));
+        ret.push_str(&format!("   files by number of blocks: {}\n",
+                              histogram_to_text(&self.blocks_per_file_histogram)));
+        ret.push_str(&format!("   files by replication: {}\n",
+                              histogram_to_text(&self.replication_histogram)));
+        ret.push_str(&format!("   file size: {}\n", self.file_size.to_text()));
+        ret.push_str(&format!("   name length in bytes: {} ({} bytes of heap)\n",
+                              self.name_length.to_text(), self.name_heap_bytes));
+        let label = if self.new_replicas_only { " (new replicas only)" } else { "" };
+        let per_dn = self.replicas_per_datanode_distribution();
+        ret.push_str(&format!("   replicas per datanode{}: {} (imbalance {:.2})\n",
+                              label, per_dn.to_text(), per_dn.imbalance()));
+        let bytes_per_dn = self.bytes_per_datanode_distribution();
+        ret.push_str(&format!("   bytes per datanode{}: {} (imbalance {:.2})\n",
+                              label, bytes_per_dn.to_text(), bytes_per_dn.imbalance()));
+        let per_volume = self.replicas_per_volume_distribution();
+        ret.push_str(&format!("   replicas per volume{}: {} (imbalance {:.2})\n",
+                              label, per_volume.to_text(), per_volume.imbalance()));
+        ret.push_str(&format!("   estimated NameNode heap: {} bytes\n",
+                              self.estimated_heap_bytes));
+        ret.push_str(&format!("   estimated binary fsimage size: {} bytes\n",
+                              self.estimated_fsimage_bytes));
+        return ret;
+    }
+
+    // Format the statistics as a JSON object.
+    pub fn to_json(&self) -> String {
+        let per_dn = self.replicas_per_datanode_distribution();
+        let bytes_per_dn = self.bytes_per_datanode_distribution();
+        let per_volume = self.replicas_per_volume_distribution();
+        let mut ret = "{".to_owned();
+        ret.push_str(&format!("\"inodes\":{{\"directories\":{},\"files\":{},\"symlinks\":0}},",
+                              self.num_directories, self.num_files));
+        ret.push_str(&format!("\"blocks\":{},\"replicas\":{},\"totalFileBytes\":{},",
+                              self.num_blocks, self.num_replicas, self.total_file_bytes));
+        ret.push_str(&format!("\"depthHistogram\":{},",
+                              histogram_to_json(&self.depth_histogram)));
+        ret.push_str(&format!("\"childrenPerDirectoryHistogram\":{},",
+                              histogram_to_json(&self.children_histogram)));
+        ret.push_str(&format!("\"blocksPerFileHistogram\":{},",
+                              histogram_to_json(&self.blocks_per_file_histogram)));
+        ret.push_str(&format!("\"replicationHistogram\":{},",
+                              histogram_to_json(&self.replication_histogram)));
+        ret.push_str(&format!("\"fileSize\":{},", self.file_size.to_json()));
+        ret.push_str(&format!("\"nameLength\":{},\"nameHeapBytes\":{},",
+                              self.name_length.to_json(), self.name_heap_bytes));
+        ret.push_str(&format!("\"newReplicasOnly\":{},", self.new_replicas_only));
+        ret.push_str(&format!("\"replicasPerDatanode\":{{\"values\":{},\"summary\":{},\
+                              \"imbalance\":{:.3}}},", list_to_json(&self.replicas_per_datanode),
+                              per_dn.to_json(), per_dn.imbalance()));
+        ret.push_str(&format!("\"bytesPerDatanode\":{{\"values\":{},\"summary\":{},\
+                              \"imbalance\":{:.3}}},", list_to_json(&self.bytes_per_datanode),
+                              bytes_per_dn.to_json(), bytes_per_dn.imbalance()));
+        let volumes : Vec<String> = self.replicas_per_volume.iter()
+            .map(|volumes| list_to_json(volumes)).collect();
+        ret.push_str(&format!("\"replicasPerVolume\":{{\"values\":[{}],\"summary\":{},\
+                              \"imbalance\":{:.3}}},", volumes.join(","),
+                              per_volume.to_json(), per_volume.imbalance()));
+        ret.push_str(&format!("\"estimatedNameNodeHeapBytes\":{},", self.estimated_heap_bytes));
+        ret.push_str(&format!("\"estimatedFsimageBytes\":{}", self.estimated_fsimage_bytes));
+        ret.push('}');
+        return ret;
+    }
+
+    // Write the statistics to a JSON file.
+    pub fn write_json(&self, path: &str) -> Result<(), std::io::Error> {
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path));
+        let mut w = BufWriter::new(&file);
+        try!(write!(w, "{}\n", self.to_json()));
+        return Result::Ok(());
+    }
+}
+
+fn histogram_to_text<K: ::std::fmt::Display>(histogram: &BTreeMap<K, u64>) -> String {
+    let entries : Vec<String> = histogram.iter()
+        .map(|(key, count)| format!("{}:{}", key, count)).collect();
+    return entries.join(" ");
+}
+
+fn histogram_to_json<K: ::std::fmt::Display>(histogram: &BTreeMap<K, u64>) -> String {
+    let entries : Vec<String> = histogram.iter()
+        .map(|(key, count)| format!("\"{}\":{}", key, count)).collect();
+    return format!("{{{}}}", entries.join(","));
+}
+
+fn list_to_json(values: &[u64]) -> String {
+    let entries : Vec<String> = values.iter().map(|val| val.to_string()).collect();
+    return format!("[{}]", entries.join(","));
+}
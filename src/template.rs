@@ -0,0 +1,235 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Block and meta files with the same contents can be hardlinked to, or
+// cloned from, a shared template file instead of being written one by one.
+
+use libc;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs;
+use std::io::Error;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+// How block and meta files are created.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LinkMode {
+    // Write every file separately.
+    None,
+
+    // Hardlink files to a template with the same contents.
+    Hardlink,
+
+    // Clone meta files from a template, sharing its data blocks on
+    // filesystems which support reflinks, and copying it on others.
+    Reflink,
+}
+
+impl LinkMode {
+    pub fn parse(name: &str) -> Option<LinkMode> {
+        match name {
+            "none" => return Some(LinkMode::None),
+            "hardlink" => return Some(LinkMode::Hardlink),
+            "reflink" => return Some(LinkMode::Reflink),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LinkMode::None => return "none",
+            LinkMode::Hardlink => return "hardlink",
+            LinkMode::Reflink => return "reflink",
+        }
+    }
+}
+
+// The kinds of file which can share a template.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TemplateKind {
+    // A block data file.  These are sparse, so they are never cloned.
+    BlockData,
+
+    BlockMeta,
+}
+
+// The ioctl which clones one file into another.  See ioctl_ficlone(2)
+#[cfg(target_os = "linux")]
+const FICLONE : u64 = 0x40049409;
+
+// A template file, and how many times it has been replaced.
+struct Template {
+    path: String,
+    generation: u32,
+}
+
+// The templates for the files of one datanode.
+pub struct TemplateStore {
+    mode: LinkMode,
+
+    // The directory holding the templates.  It is created when the first
+    // template is.
+    dir: String,
+
+    // Maps the name of each template's contents to the template.
+    templates: Mutex<HashMap<String, Template>>,
+
+    // Set once cloning has failed, so that we go straight to copying.
+    reflink_unsupported: AtomicBool,
+}
+
+impl TemplateStore {
+    pub fn new(mode: LinkMode, dir: &str) -> TemplateStore {
+        return TemplateStore {
+            mode: mode,
+            dir: dir.to_owned(),
+            templates: Mutex::new(HashMap::new()),
+            reflink_unsupported: AtomicBool::new(false),
+        }
+    }
+
+    // Create the file at path.  name identifies its contents, which write
+    // fills in.  Files with the same name must have the same contents.
+    pub fn create_file<F>(&self, path: &str, kind: TemplateKind, name: &str,
+                          write: F) -> Result<(), Error>
+            where F: Fn(&File) -> Result<(), Error> {
+        let shared = match self.mode {
+            LinkMode::None => false,
+            LinkMode::Hardlink => true,
+            LinkMode::Reflink => kind != TemplateKind::BlockData,
+        };
+        if !shared {
+            return write_file(path, &write);
+        }
+        loop {
+            let (template, generation) = try!(self.get_template(name, &write));
+            let result = if self.mode == LinkMode::Hardlink {
+                fs::hard_link(&template, path)
+            } else {
+                self.clone_file(&template, path)
+            };
+            match result {
+                Ok(()) => return Result::Ok(()),
+                // The template has as many links as the filesystem allows.
+                // Start a new one.
+                Err(ref e) if e.raw_os_error() == Some(libc::EMLINK) =>
+                    try!(self.replace_template(name, generation, &write)),
+                // Hardlinks can't cross filesystems.
+                Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) =>
+                    return write_file(path, &write),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Get the path and generation of the template called name, creating it
+    // if it doesn't exist yet.
+    fn get_template<F>(&self, name: &str, write: &F) -> Result<(String, u32), Error>
+            where F: Fn(&File) -> Result<(), Error> {
+        let mut templates = self.templates.lock().unwrap();
+        if let Some(template) = templates.get(name) {
+            return Result::Ok((template.path.clone(), template.generation));
+        }
+        if templates.is_empty() {
+            try!(fs::create_dir_all(&self.dir));
+        }
+        let path = format!("{}/{}_0", self.dir, name);
+        try!(write_file(&path, write));
+        templates.insert(name.to_owned(), Template { path: path.clone(), generation: 0 });
+        return Result::Ok((path, 0));
+    }
+
+    // Replace a full template with a new copy, unless another thread already
+    // has.
+    fn replace_template<F>(&self, name: &str, generation: u32, write: &F) -> Result<(), Error>
+            where F: Fn(&File) -> Result<(), Error> {
+        let mut templates = self.templates.lock().unwrap();
+        let template = templates.get_mut(name).unwrap();
+        if template.generation != generation {
+            return Result::Ok(());
+        }
+        let path = format!("{}/{}_{}", self.dir, name, generation + 1);
+        try!(write_file(&path, write));
+        template.path = path;
+        template.generation = generation + 1;
+        return Result::Ok(());
+    }
+
+    // Clone the template to path, or copy it if the filesystem can't.
+    fn clone_file(&self, template: &str, path: &str) -> Result<(), Error> {
+        if !self.reflink_unsupported.load(Ordering::Relaxed) {
+            match reflink(template, path) {
+                Ok(()) => return Result::Ok(()),
+                Err(ref e) if is_unsupported(e) => {
+                    self.reflink_unsupported.store(true, Ordering::Relaxed);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        try!(fs::copy(template, path));
+        return Result::Ok(());
+    }
+}
+
+fn write_file<F>(path: &str, write: &F) -> Result<(), Error>
+        where F: Fn(&File) -> Result<(), Error> {
+    let file = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    return write(&file);
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(template: &str, path: &str) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+    let src = try!(File::open(template));
+    let dst = try!(OpenOptions::new().
+        read(false).
+        write(true).
+        create(true).
+        truncate(true).
+        open(path));
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+    if ret < 0 {
+        let err = Error::last_os_error();
+        drop(dst);
+        let _ = fs::remove_file(path);
+        return Err(err);
+    }
+    return Result::Ok(());
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_template: &str, _path: &str) -> Result<(), Error> {
+    return Err(Error::from_raw_os_error(libc::EOPNOTSUPP));
+}
+
+// Check whether an error means that the filesystem can't clone files.
+fn is_unsupported(e: &Error) -> bool {
+    match e.raw_os_error() {
+        Some(errno) => return errno == libc::EOPNOTSUPP || errno == libc::ENOTTY ||
+            errno == libc::EINVAL || errno == libc::EXDEV || errno == libc::ENOSYS,
+        None => return false,
+    }
+}
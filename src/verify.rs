@@ -0,0 +1,407 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Checks an existing output directory against the fsimage which describes
+// it.  This catches bugs in fsgen, as well as directories which were damaged
+// or edited by hand after they were generated.
+
+use datanode::block_subdir;
+use error::FsgenError;
+use image::load_image;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error;
+
+// The maximum number of examples to keep for each kind of problem.
+const MAX_EXAMPLES : usize = 10;
+
+// The problems found in one category.
+pub struct ProblemList {
+    // The total number of problems found
+    pub count: u64,
+
+    // The first few problems found, in the order they were found
+    pub examples: Vec<String>,
+}
+
+pub struct VerifyReport {
+    // The number of blocks in the fsimage
+    pub num_blocks: u64,
+
+    // The number of replicas found in the DataNode directories
+    pub num_replicas: u64,
+
+    // The number of DataNode storage directories which were checked
+    pub num_storage_dirs: u64,
+
+    // The problems found, keyed by category
+    pub problems: BTreeMap<&'static str, ProblemList>,
+}
+
+impl VerifyReport {
+    fn new() -> VerifyReport {
+        return VerifyReport {
+            num_blocks: 0,
+            num_replicas: 0,
+            num_storage_dirs: 0,
+            problems: BTreeMap::new(),
+        }
+    }
+
+    pub fn num_problems(&self) -> u64 {
+        return self.problems.values().map(|p| p.count).sum();
+    }
+
+    fn add(&mut self, category: &'static str, problem: String) {
+        let list = self.problems.entry(category).or_insert(ProblemList {
+            count: 0,
+            examples: vec![],
+        });
+        list.count = list.count + 1;
+        if list.examples.len() < MAX_EXAMPLES {
+            list.examples.push(problem);
+        }
+    }
+}
+
+// What the fsimage says about a block, and the DataNodes it was found on.
+struct ExpectedBlock {
+    genstamp: u64,
+    num_bytes: u64,
+    replication: u16,
+    path: String,
+    datanodes: Vec<String>,
+}
+
+// Verify the NameNode and DataNode directories under out_dir against the
+// fsimage at image_path, which may be XML or binary.
+pub fn verify_output_dir(out_dir: &str, image_path: &str)
+        -> Result<VerifyReport, FsgenError> {
+    let mut report = VerifyReport::new();
+    let image = try!(load_image(image_path).map_err(|e| FsgenError::io(image_path, e)));
+    let mut blocks : HashMap<u64, ExpectedBlock> = HashMap::new();
+    for inode in &image.inodes {
+        for block in &inode.blocks {
+            if blocks.contains_key(&block.id) {
+                report.add("image", format!("block {} belongs to more than one file",
+                                            block.id));
+                continue;
+            }
+            blocks.insert(block.id, ExpectedBlock {
+                genstamp: block.genstamp,
+                num_bytes: block.num_bytes,
+                replication: inode.replication,
+                path: format!("inode {} ({})", inode.id, inode.name),
+                datanodes: vec![],
+            });
+        }
+    }
+    report.num_blocks = blocks.len() as u64;
+
+    let nn_version_path = format!("{}/name/current/VERSION", out_dir);
+    let nn_version = match read_properties(&nn_version_path) {
+        Ok(props) => props,
+        Err(e) => {
+            report.add("version", format!("{}: {}", nn_version_path, e));
+            HashMap::new()
+        },
+    };
+    let nn_namespace_id = nn_version.get("namespaceID").cloned();
+    if let Some(ref id) = nn_namespace_id {
+        if *id != image.namespace_id.to_string() {
+            report.add("version", format!("{}: namespaceID={}, but the fsimage has {}",
+                       nn_version_path, id, image.namespace_id));
+        }
+    }
+    let cluster_id = nn_version.get("clusterID").cloned();
+    let block_pool_id = nn_version.get("blockpoolID").cloned();
+
+    let mut storage_ids : HashMap<String, String> = HashMap::new();
+    let mut datanode_uuids : HashMap<String, String> = HashMap::new();
+    for dn_name in try!(list_dir(out_dir, "datanode")) {
+        let dn_dir = format!("{}/{}", out_dir, dn_name);
+        let mut dn_uuid : Option<String> = None;
+        for storage_name in try!(find_volume_dirs(&dn_dir, "")) {
+            let storage_dir = format!("{}/{}/current", dn_dir, storage_name);
+            report.num_storage_dirs = report.num_storage_dirs + 1;
+            let version_path = format!("{}/VERSION", storage_dir);
+            let version = match read_properties(&version_path) {
+                Ok(props) => props,
+                Err(e) => {
+                    report.add("version", format!("{}: {}", version_path, e));
+                    continue;
+                },
+            };
+            check_property(&mut report, &version_path, &version, "clusterID", &cluster_id);
+            match version.get("storageID") {
+                None => report.add("version", format!("{}: no storageID", version_path)),
+                Some(id) => {
+                    if let Some(other) = storage_ids.get(id) {
+                        report.add("version", format!("{}: storageID {} is also used by {}",
+                                   version_path, id, other));
+                    }
+                    storage_ids.insert(id.clone(), version_path.clone());
+                },
+            }
+            match version.get("datanodeUuid") {
+                None => report.add("version", format!("{}: no datanodeUuid", version_path)),
+                Some(uuid) => {
+                    match dn_uuid {
+                        None => {
+                            if let Some(other) = datanode_uuids.get(uuid) {
+                                report.add("version", format!(
+                                    "{}: datanodeUuid {} is also used by {}",
+                                    version_path, uuid, other));
+                            }
+                            datanode_uuids.insert(uuid.clone(), dn_name.clone());
+                            dn_uuid = Some(uuid.clone());
+                        },
+                        Some(ref expected) if expected != uuid => {
+                            report.add("version", format!(
+                                "{}: datanodeUuid {} differs from the other storage \
+                                directories of {} ({})", version_path, uuid, dn_name, expected));
+                        },
+                        Some(_) => (),
+                    }
+                },
+            }
+            let layout_version = match version.get("layoutVersion")
+                    .and_then(|v| v.parse::<i32>().ok()) {
+                None => {
+                    report.add("version", format!("{}: missing or invalid layoutVersion",
+                                                  version_path));
+                    continue;
+                },
+                Some(v) => v,
+            };
+
+            let bp_name = match block_pool_id {
+                Some(ref id) => id.clone(),
+                None => match try!(list_dir(&storage_dir, "BP-")).pop() {
+                    None => continue,
+                    Some(name) => name,
+                },
+            };
+            let bp_dir = format!("{}/{}/current", storage_dir, bp_name);
+            let bp_version_path = format!("{}/VERSION", bp_dir);
+            let bp_version = match read_properties(&bp_version_path) {
+                Ok(props) => props,
+                Err(e) => {
+                    report.add("version", format!("{}: {}", bp_version_path, e));
+                    continue;
+                },
+            };
+            check_property(&mut report, &bp_version_path, &bp_version, "blockpoolID",
+                           &Some(bp_name.clone()));
+            check_property(&mut report, &bp_version_path, &bp_version, "namespaceID",
+                           &nn_namespace_id);
+            let finalized = format!("{}/finalized", bp_dir);
+            try!(verify_finalized_dir(&mut report, &mut blocks, &dn_name, &finalized, "",
+                                      layout_version));
+        }
+    }
+
+    let mut ids : Vec<&u64> = blocks.keys().collect();
+    ids.sort();
+    for id in ids {
+        let block = &blocks[id];
+        let found = block.datanodes.len();
+        if found == 0 {
+            report.add("missing", format!("block {} of {} has no replicas", id, block.path));
+        } else if found < block.replication as usize {
+            report.add("replication", format!("block {} of {} has {} replica(s), but \
+                replication is {}", id, block.path, found, block.replication));
+        } else if found > block.replication as usize {
+            report.add("replication", format!("block {} of {} has {} replicas, but \
+                replication is only {}", id, block.path, found, block.replication));
+        }
+    }
+    return Result::Ok(report);
+}
+
+// Check the block and meta files in a directory under finalized/, then
+// recurse into its subdirectories.  rel_dir is the path of the directory
+// relative to finalized/.
+fn verify_finalized_dir(report: &mut VerifyReport, blocks: &mut HashMap<u64, ExpectedBlock>,
+                        dn_name: &str, dir: &str, rel_dir: &str,
+                        layout_version: i32) -> Result<(), FsgenError> {
+    let mut data_files : HashMap<u64, u64> = HashMap::new();
+    let mut meta_files : HashMap<u64, u64> = HashMap::new();
+    let mut subdirs = vec![];
+    for entry in try!(fs::read_dir(dir).map_err(|e| FsgenError::io(dir, e))) {
+        let entry = try!(entry.map_err(|e| FsgenError::io(dir, e)));
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = try!(entry.metadata().map_err(|e| FsgenError::io(dir, e)));
+        if metadata.is_dir() {
+            subdirs.push(name);
+        } else if let Some(id) = parse_block_file_name(&name) {
+            data_files.insert(id, metadata.len());
+        } else if let Some((id, genstamp)) = parse_meta_file_name(&name) {
+            meta_files.insert(id, genstamp);
+        }
+    }
+    for (id, len) in &data_files {
+        let path = format!("{}/blk_{}", dir, id);
+        report.num_replicas = report.num_replicas + 1;
+        let expected_dir = block_subdir(*id, layout_version);
+        if rel_dir != expected_dir {
+            report.add("placement", format!("{}: expected to be in finalized/{} for layout \
+                version {}", path, expected_dir, layout_version));
+        }
+        if !meta_files.contains_key(id) {
+            report.add("missing", format!("{}: no meta file", path));
+        }
+        let block = match blocks.get_mut(id) {
+            None => {
+                report.add("extra", format!("{}: block is not in the fsimage", path));
+                continue;
+            },
+            Some(block) => block,
+        };
+        if *len != block.num_bytes {
+            report.add("length", format!("{}: {} bytes, but the fsimage says {}",
+                                         path, len, block.num_bytes));
+        }
+        if block.datanodes.iter().any(|dn| dn == dn_name) {
+            report.add("replication", format!("{}: {} holds more than one replica of block {}",
+                                              path, dn_name, id));
+        }
+        block.datanodes.push(dn_name.to_owned());
+    }
+    for (id, genstamp) in &meta_files {
+        let path = format!("{}/blk_{}_{}.meta", dir, id, genstamp);
+        if !data_files.contains_key(id) {
+            report.add("missing", format!("{}: no block file", path));
+        }
+        match blocks.get(id) {
+            None => {
+                if !data_files.contains_key(id) {
+                    report.add("extra", format!("{}: block is not in the fsimage", path));
+                }
+            },
+            Some(block) => {
+                if *genstamp != block.genstamp {
+                    report.add("genstamp", format!("{}: the fsimage has genstamp {}",
+                                                   path, block.genstamp));
+                }
+            },
+        }
+    }
+    subdirs.sort();
+    for subdir in subdirs {
+        let rel = if rel_dir.is_empty() {
+            subdir.clone()
+        } else {
+            format!("{}/{}", rel_dir, subdir)
+        };
+        try!(verify_finalized_dir(report, blocks, dn_name, &format!("{}/{}", dir, subdir),
+                                  &rel, layout_version));
+    }
+    return Result::Ok(());
+}
+
+// Parse a block data file name, like blk_1073741825
+fn parse_block_file_name(name: &str) -> Option<u64> {
+    return name.strip_prefix("blk_").and_then(|id| id.parse::<u64>().ok());
+}
+
+// Parse a block meta file name, like blk_1073741825_1001.meta
+fn parse_meta_file_name(name: &str) -> Option<(u64, u64)> {
+    let rest = name.strip_prefix("blk_").and_then(|n| n.strip_suffix(".meta"))?;
+    let mut parts = rest.splitn(2, '_');
+    let id = parts.next().and_then(|id| id.parse::<u64>().ok());
+    let genstamp = parts.next().and_then(|genstamp| genstamp.parse::<u64>().ok());
+    match (id, genstamp) {
+        (Some(id), Some(genstamp)) => return Some((id, genstamp)),
+        _ => return None,
+    }
+}
+
+fn check_property(report: &mut VerifyReport, path: &str, props: &HashMap<String, String>,
+                  key: &str, expected: &Option<String>) {
+    let expected = match *expected {
+        None => return,
+        Some(ref expected) => expected,
+    };
+    match props.get(key) {
+        None => report.add("version", format!("{}: no {}", path, key)),
+        Some(val) if val != expected => report.add("version",
+            format!("{}: {}={}, but expected {}", path, key, val, expected)),
+        Some(_) => (),
+    }
+}
+
+// Read a Java properties file, like the VERSION files in storage directories.
+pub(crate) fn read_properties(path: &str) -> Result<HashMap<String, String>, Error> {
+    let file = try!(File::open(path));
+    let mut props = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = try!(line);
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(idx) = line.find('=') {
+            props.insert(line[..idx].trim().to_owned(), line[(idx + 1)..].trim().to_owned());
+        }
+    }
+    return Result::Ok(props);
+}
+
+// Find the volume directories under a datanode directory: the directories
+// which have a current/VERSION file.  Returns their paths relative to dn_dir,
+// in order.
+fn find_volume_dirs(dn_dir: &str, rel_dir: &str) -> Result<Vec<String>, FsgenError> {
+    let mut volumes = vec![];
+    let dir = if rel_dir.is_empty() {
+        dn_dir.to_owned()
+    } else {
+        format!("{}/{}", dn_dir, rel_dir)
+    };
+    for name in try!(list_dir(&dir, "")) {
+        let rel = if rel_dir.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", rel_dir, name)
+        };
+        if fs::metadata(format!("{}/{}/current/VERSION", dn_dir, rel)).is_ok() {
+            volumes.push(rel);
+        } else if name != "current" {
+            volumes.append(&mut try!(find_volume_dirs(dn_dir, &rel)));
+        }
+    }
+    return Result::Ok(volumes);
+}
+
+// List the names of the directories in dir which start with prefix, in order.
+fn list_dir(dir: &str, prefix: &str) -> Result<Vec<String>, FsgenError> {
+    let mut names = vec![];
+    for entry in try!(fs::read_dir(dir).map_err(|e| FsgenError::io(dir, e))) {
+        let entry = try!(entry.map_err(|e| FsgenError::io(dir, e)));
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = try!(entry.metadata().map_err(|e| FsgenError::io(dir, e)));
+        if name.starts_with(prefix) && metadata.is_dir() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    return Result::Ok(names);
+}
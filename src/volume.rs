@@ -0,0 +1,273 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use config::Config;
+use rand::Rng;
+
+// The capacity of a volume with no limit on how much it can hold.
+pub const VOLUME_CAPACITY_UNLIMITED : u64 = u64::MAX;
+
+// The directory name prefix of the default volumes: storage01, storage02...
+pub const DEFAULT_VOLUME_PREFIX : &str = "storage";
+
+// Volumes whose available space is within this many bytes of each other are
+// considered balanced.  See
+// DFS_DATANODE_AVAILABLE_SPACE_VOLUME_CHOOSING_POLICY_BALANCED_SPACE_THRESHOLD_DEFAULT
+const BALANCED_SPACE_THRESHOLD : u64 = 10 * 1024 * 1024 * 1024;
+
+// The fraction of new replicas which go to the volumes with more available
+// space, when the volumes are not balanced.  See
+// DFS_DATANODE_AVAILABLE_SPACE_VOLUME_CHOOSING_POLICY_BALANCED_SPACE_PREFERENCE_FRACTION_DEFAULT
+const BALANCED_SPACE_PREFERENCE_FRACTION : f64 = 0.75;
+
+// The type of storage behind a volume.  See StorageType
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StorageType {
+    Disk,
+    Ssd,
+    Archive,
+    RamDisk,
+    Nvdimm,
+}
+
+impl StorageType {
+    pub fn parse(name: &str) -> Option<StorageType> {
+        match name {
+            "DISK" => return Some(StorageType::Disk),
+            "SSD" => return Some(StorageType::Ssd),
+            "ARCHIVE" => return Some(StorageType::Archive),
+            "RAM_DISK" => return Some(StorageType::RamDisk),
+            "NVDIMM" => return Some(StorageType::Nvdimm),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StorageType::Disk => return "DISK",
+            StorageType::Ssd => return "SSD",
+            StorageType::Archive => return "ARCHIVE",
+            StorageType::RamDisk => return "RAM_DISK",
+            StorageType::Nvdimm => return "NVDIMM",
+        }
+    }
+}
+
+// How a datanode chooses the volume for a new replica.  See
+// RoundRobinVolumeChoosingPolicy and AvailableSpaceVolumeChoosingPolicy
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VolumeChoosingPolicy {
+    RoundRobin,
+    AvailableSpace,
+}
+
+impl VolumeChoosingPolicy {
+    pub fn parse(name: &str) -> Option<VolumeChoosingPolicy> {
+        match name {
+            "round_robin" => return Some(VolumeChoosingPolicy::RoundRobin),
+            "available_space" => return Some(VolumeChoosingPolicy::AvailableSpace),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VolumeChoosingPolicy::RoundRobin => return "round_robin",
+            VolumeChoosingPolicy::AvailableSpace => return "available_space",
+        }
+    }
+}
+
+// A set of identical volumes on some of the datanodes, from a [[volumes]]
+// table in the configuration file.
+#[derive(Clone)]
+pub struct VolumeGroup {
+    // The 0-based indices of the datanodes which have these volumes.  Empty
+    // means all of them.
+    pub datanodes: Vec<u16>,
+
+    // The number of volumes on each datanode
+    pub count: u16,
+
+    // The volume directories are named by this prefix and a number, relative
+    // to the datanode directory
+    pub path_prefix: String,
+
+    // The capacity of each volume, in bytes
+    pub capacity: u64,
+
+    pub storage_type: StorageType,
+}
+
+// One storage directory of a datanode.
+#[derive(Clone)]
+pub struct Volume {
+    // The directory name, relative to the datanode directory
+    pub name: String,
+
+    // The capacity, in bytes
+    pub capacity: u64,
+
+    pub storage_type: StorageType,
+}
+
+impl Config {
+    // Get the volumes of a datanode.  A datanode which isn't in any volume
+    // group has storage_dirs_per_datanode unlimited DISK volumes.
+    pub fn datanode_volumes(&self, datanode_idx: u16) -> Vec<Volume> {
+        let mut volumes : Vec<Volume> = vec![];
+        for group in &self.volume_groups {
+            if !group.datanodes.is_empty() && !group.datanodes.contains(&datanode_idx) {
+                continue;
+            }
+            // Volumes which share a prefix are numbered consecutively.
+            let mut num = volumes.iter().filter(|v| is_numbered(&v.name, &group.path_prefix))
+                .count();
+            for _ in 0..group.count {
+                num = num + 1;
+                volumes.push(Volume {
+                    name: format!("{}{:>02}", group.path_prefix, num),
+                    capacity: group.capacity,
+                    storage_type: group.storage_type,
+                });
+            }
+        }
+        if volumes.is_empty() {
+            for num in 0..self.num_storage_dirs_per_dn {
+                volumes.push(Volume {
+                    name: format!("{}{:>02}", DEFAULT_VOLUME_PREFIX, num + 1),
+                    capacity: VOLUME_CAPACITY_UNLIMITED,
+                    storage_type: StorageType::Disk,
+                });
+            }
+        }
+        return volumes;
+    }
+}
+
+// Check whether name is prefix followed by a number.
+fn is_numbered(name: &str, prefix: &str) -> bool {
+    match name.strip_prefix(prefix) {
+        None => return false,
+        Some(rest) => return !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()),
+    }
+}
+
+// Chooses the volume for each new replica on one datanode, keeping track of
+// how full each volume is.
+pub struct VolumeChooser {
+    policy: VolumeChoosingPolicy,
+
+    // The capacity of each volume
+    capacity: Vec<u64>,
+
+    // The bytes used on each volume
+    used: Vec<u64>,
+
+    // Round-robin positions for all volumes, and for the volumes with more
+    // and less available space.
+    next_all: usize,
+    next_high: usize,
+    next_low: usize,
+}
+
+impl VolumeChooser {
+    pub fn new(policy: VolumeChoosingPolicy, volumes: &[Volume]) -> VolumeChooser {
+        return VolumeChooser {
+            policy: policy,
+            capacity: volumes.iter().map(|v| v.capacity).collect(),
+            used: vec![0; volumes.len()],
+            next_all: 0,
+            next_high: 0,
+            next_low: 0,
+        }
+    }
+
+    // Choose a volume with room for len more bytes, and account for them.
+    // Returns None if no volume has room.
+    pub fn choose(&mut self, rng: &mut Rng, len: u64) -> Option<u16> {
+        let all : Vec<usize> = (0..self.capacity.len()).collect();
+        let idx = match self.policy {
+            VolumeChoosingPolicy::RoundRobin => {
+                let mut next = self.next_all;
+                let idx = self.choose_round_robin(&all, &mut next, len);
+                self.next_all = next;
+                idx
+            },
+            VolumeChoosingPolicy::AvailableSpace => self.choose_available_space(rng, &all, len),
+        };
+        if let Some(idx) = idx {
+            self.used[idx] = self.used[idx] + len;
+        }
+        return idx.map(|idx| idx as u16);
+    }
+
+    fn available(&self, idx: usize) -> u64 {
+        return self.capacity[idx].saturating_sub(self.used[idx]);
+    }
+
+    // Choose the next volume in candidates, starting at *next, which has room
+    // for len bytes.
+    fn choose_round_robin(&self, candidates: &[usize], next: &mut usize,
+                          len: u64) -> Option<usize> {
+        for _ in 0..candidates.len() {
+            let idx = candidates[*next % candidates.len()];
+            *next = (*next + 1) % candidates.len();
+            if self.available(idx) >= len {
+                return Some(idx);
+            }
+        }
+        return None;
+    }
+
+    // If the volumes have about the same available space, choose one
+    // round-robin.  Otherwise, prefer the ones with more available space.
+    fn choose_available_space(&mut self, rng: &mut Rng, all: &[usize],
+                              len: u64) -> Option<usize> {
+        let fits : Vec<usize> = all.iter().cloned().filter(|idx| self.available(*idx) >= len)
+            .collect();
+        if fits.is_empty() {
+            return None;
+        }
+        let least = fits.iter().map(|idx| self.available(*idx)).min().unwrap();
+        let most = fits.iter().map(|idx| self.available(*idx)).max().unwrap();
+        if most - least < BALANCED_SPACE_THRESHOLD {
+            let mut next = self.next_all;
+            let idx = self.choose_round_robin(&fits, &mut next, len);
+            self.next_all = next;
+            return idx;
+        }
+        let (high, low) : (Vec<usize>, Vec<usize>) = fits.iter()
+            .partition(|idx| self.available(**idx) > least + BALANCED_SPACE_THRESHOLD);
+        let high_weight = (high.len() as f64) * BALANCED_SPACE_PREFERENCE_FRACTION;
+        let low_weight = (low.len() as f64) * (1.0 - BALANCED_SPACE_PREFERENCE_FRACTION);
+        let preference = high_weight / (high_weight + low_weight);
+        let roll = (rng.next_u32() as f64) / 4294967296.0;
+        if roll < preference {
+            let mut next = self.next_high;
+            let idx = self.choose_round_robin(&high, &mut next, len);
+            self.next_high = next;
+            return idx;
+        } else {
+            let mut next = self.next_low;
+            let idx = self.choose_round_robin(&low, &mut next, len);
+            self.next_low = next;
+            return idx;
+        }
+    }
+}
@@ -0,0 +1,320 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use error::FsgenError;
+use flate2::Compression;
+use hadoop::xml_escape;
+use flate2::write::GzEncoder;
+use namespace::Block;
+use namespace::CacheDirective;
+use namespace::CachePool;
+use namespace::DelegationKey;
+use namespace::DelegationToken;
+use namespace::FSImage;
+use namespace::INode;
+use namespace::LAST_TXID;
+use parallel::write_chunks;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+
+// The number of inodes or directories each worker thread formats at a time.
+const XML_CHUNK_LEN : usize = 1024;
+
+impl FSImage {
+    // Write the FSImage XML.  If path ends in .gz, the XML is gzipped.
+    pub fn write_xml(&self, path: &str) -> Result<(), FsgenError> {
+        self.progress.start_phase("xml", "entries",
+                                  (self.inode_map.len() + self.children.len()) as u64);
+        let file = try!(OpenOptions::new().
+            read(false).
+            write(true).
+            create(true).
+            truncate(true).
+            open(path).map_err(|e| FsgenError::io(path, e)));
+        let mut w = BufWriter::new(&file);
+        if path.ends_with(".gz") {
+            let mut encoder = GzEncoder::new(w, Compression::default());
+            try!(self.write_xml_sections(&mut encoder, path));
+            try!(encoder.finish().and_then(|mut w| w.flush())
+                 .map_err(|e| FsgenError::io(path, e)));
+        } else {
+            try!(self.write_xml_sections(&mut w, path));
+            try!(w.flush().map_err(|e| FsgenError::io(path, e)));
+        }
+        self.progress.finish_phase();
+        return Result::Ok(());
+    }
+
+    fn write_xml_sections<W: Write>(&self, w: &mut W, path: &str) -> Result<(), FsgenError> {
+        let io_error = |e| FsgenError::io(path, e);
+        try!(write!(w, "<?xml version=\"1.0\"?>").map_err(&io_error));
+        try!(write!(w, "<fsimage>").map_err(&io_error));
+        try!(self.write_version_section(w).map_err(&io_error));
+        try!(self.write_name_section(w).map_err(&io_error));
+        try!(self.write_inode_section(w, path));
+        try!(write!(w, "<INodeReferenceSection></INodeReferenceSection>").map_err(&io_error));
+        try!(self.write_snapshot_section(w).map_err(&io_error));
+        try!(self.write_inode_directory_section(w, path));
+        try!(write!(w, "<FileUnderConstructionSection></FileUnderConstructionSection>")
+             .map_err(&io_error));
+        try!(self.write_snapshot_diff_section(w).map_err(&io_error));
+        try!(self.write_secret_manager_section(w).map_err(&io_error));
+        try!(self.write_cache_manager_section(w).map_err(&io_error));
+        try!(write!(w, "</fsimage>").map_err(&io_error));
+        return Result::Ok(());
+    }
+
+    // Write the FSImage XML.
+    fn write_version_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<version>"));
+        try!(write!(w, "<layoutVersion>{}</layoutVersion>", self.config.nn_layout_version));
+        try!(write!(w, "<onDiskVersion>1</onDiskVersion>"));
+        try!(write!(w, "<oivRevision>545bbef596c06af1c3c8dca1ce29096a64608478</oivRevision>"));
+        try!(write!(w, "</version>\n"));
+        return Result::Ok(());
+    }
+
+    fn write_name_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<NameSection>"));
+        try!(write!(w, "<namespaceId>{}</namespaceId>", self.config.namespace_id));
+        try!(write!(w, "<genstampV1>1000</genstampV1>"));
+        try!(write!(w, "<genstampV2>{}</genstampV2>", self.next_genstamp));
+        try!(write!(w, "<genstampV1Limit>0</genstampV1Limit>"));
+        try!(write!(w, "<lastAllocatedBlockId>{}</lastAllocatedBlockId>",
+                    self.next_block_id - 1));
+        try!(write!(w, "<txid>{}</txid>", LAST_TXID));
+        try!(write!(w, "</NameSection>\n"));
+        return Result::Ok(());
+    }
+
+    fn write_inode_section<W: Write>(&self, w: &mut W, path: &str)
+            -> Result<(), FsgenError> {
+        let io_error = |e| FsgenError::io(path, e);
+        try!(write!(w, "<INodeSection>").map_err(&io_error));
+        try!(write!(w, "<lastInodeId>{}</lastInodeId>", self.next_inode_id - 1)
+             .map_err(&io_error));
+        try!(write!(w, "<numInodes>{}</numInodes>", self.num_inodes).map_err(&io_error));
+        let inodes : Vec<&INode> = self.inode_map.values().collect();
+        let preferred_block_size = self.config.preferred_block_size;
+        try!(write_chunks(w, path, &self.progress, self.config.num_threads as usize,
+                          &inodes, XML_CHUNK_LEN,
+                          |buf, inode| inode.write_xml(buf, preferred_block_size,
+                                                       &self.owners[inode.owner as usize])));
+        try!(write!(w, "</INodeSection>\n").map_err(&io_error));
+        return Result::Ok(());
+    }
+
+    fn write_inode_directory_section<W: Write>(&self, w: &mut W, path: &str)
+            -> Result<(), FsgenError> {
+        let io_error = |e| FsgenError::io(path, e);
+        try!(write!(w, "<INodeDirectorySection>").map_err(&io_error));
+        let directories : Vec<(&u32, &Vec<u32>)> = self.children.iter().collect();
+        try!(write_chunks(w, path, &self.progress, self.config.num_threads as usize,
+                          &directories, XML_CHUNK_LEN, |buf, &(parent_id, id_vec)| {
+            try!(write!(buf, "<directory><parent>{}</parent>", parent_id));
+            for child_id in id_vec {
+                try!(write!(buf, "<child>{}</child>", child_id));
+            }
+            try!(write!(buf, "</directory>"));
+            return Result::Ok(());
+        }));
+        try!(write!(w, "</INodeDirectorySection>\n").map_err(&io_error));
+        return Result::Ok(());
+    }
+
+    fn write_snapshot_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<SnapshotSection>"));
+        try!(write!(w, "<snapshotCounter>0</snapshotCounter>"));
+        try!(write!(w, "<numSnapshots>0</numSnapshots>"));
+        try!(write!(w, "</SnapshotSection>\n"));
+        return Result::Ok(());
+    }
+
+    fn write_snapshot_diff_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<SnapshotDiffSection>"));
+        try!(write!(w, "<dirDiffEntry><inodeId>16385</inodeId><count>0</count></dirDiffEntry>"));
+        try!(write!(w, "</SnapshotDiffSection>\n"));
+        return Result::Ok(());
+    }
+
+    fn write_secret_manager_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<SecretManagerSection>"));
+        // The current key ID and token sequence number are the highest ones
+        // we have handed out so far.
+        try!(write!(w, "<currentId>{}</currentId>", self.delegation_keys.len()));
+        try!(write!(w, "<tokenSequenceNumber>{}</tokenSequenceNumber>",
+                    self.delegation_tokens.len()));
+        try!(write!(w, "<numDelegationKeys>{}</numDelegationKeys>", self.delegation_keys.len()));
+        try!(write!(w, "<numTokens>{}</numTokens>", self.delegation_tokens.len()));
+        for key in &self.delegation_keys {
+            try!(write!(w, "{}", key.to_xml()));
+        }
+        for token in &self.delegation_tokens {
+            try!(write!(w, "{}", token.to_xml()));
+        }
+        try!(write!(w, "</SecretManagerSection>\n"));
+        return Result::Ok(());
+    }
+
+    fn write_cache_manager_section<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<CacheManagerSection>"));
+        try!(write!(w, "<nextDirectiveId>{}</nextDirectiveId>",
+                    self.cache_directives.len() + 1));
+        try!(write!(w, "<numDirectives>{}</numDirectives>", self.cache_directives.len()));
+        try!(write!(w, "<numPools>{}</numPools>", self.cache_pools.len()));
+        for pool in &self.cache_pools {
+            try!(write!(w, "{}", pool.to_xml()));
+        }
+        for directive in &self.cache_directives {
+            try!(write!(w, "{}", directive.to_xml()));
+        }
+        try!(write!(w, "</CacheManagerSection>\n"));
+        return Result::Ok(());
+    }
+}
+
+impl INode {
+    pub fn write_xml<W: Write>(&self, w: &mut W, preferred_block_size: u64,
+                               owner: &str) -> Result<(), std::io::Error> {
+        try!(write!(w, "<inode>"));
+        try!(write!(w, "<id>{}</id>", self.id));
+        try!(write!(w, "<type>{}</type>", self.get_type_name()));
+        try!(write!(w, "<name>{}</name>", xml_escape(&self.name)));
+        try!(write!(w, "<mtime>{}</mtime>", 0));
+        if self.is_dir {
+            try!(write!(w, "<dsquota>{}</dsquota>", -1));
+            try!(write!(w, "<nsquota>{}</nsquota>", -1));
+        } else {
+            try!(write!(w, "<atime>{}</atime>", 0));
+            try!(write!(w, "<replication>{}</replication>", self.replication));
+            try!(write!(w, "<preferredBlockSize>{}</preferredBlockSize>",
+                        preferred_block_size));
+        }
+        try!(write!(w, "<permission>{}:supergroup:0644</permission>", xml_escape(owner)));
+        if !self.is_dir {
+            try!(write!(w, "<blocks>"));
+            for block in &self.blocks {
+                try!(block.write_xml(w));
+            }
+            try!(write!(w, "</blocks>"));
+        }
+        try!(write!(w, "</inode>"));
+        return Result::Ok(());
+    }
+}
+
+impl Block {
+    pub fn write_xml<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        try!(write!(w, "<block>"));
+        try!(write!(w, "<id>{}</id>", self.id));
+        try!(write!(w, "<genstamp>{}</genstamp>", self.genstamp));
+        try!(write!(w, "<numBytes>{}</numBytes>", self.num_bytes));
+        try!(write!(w, "</block>"));
+        return Result::Ok(());
+    }
+}
+
+// Format a time in milliseconds since the epoch the way the offline image
+// viewer does: yyyy-MM-dd'T'HH:mm:ss.SSS, in UTC.
+fn format_iso_date(millis: u64) -> String {
+    let secs = millis / 1000;
+    let secs_of_day = secs % 86400;
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    return format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+                   year, month, day, secs_of_day / 3600, (secs_of_day / 60) % 60,
+                   secs_of_day % 60, millis % 1000);
+}
+
+// Convert a number of days since the epoch to a (year, month, day) date.  See
+// Howard Hinnant's civil_from_days algorithm.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (if month <= 2 { 1 } else { 0 });
+    return (year, month, day);
+}
+
+impl CachePool {
+    pub fn to_xml(&self) -> String {
+        let mut ret = "<pool>".to_owned();
+        ret.push_str(&format!("<poolName>{}</poolName>", xml_escape(&self.name)));
+        ret.push_str(&format!("<ownerName>{}</ownerName>", xml_escape(&self.owner)));
+        ret.push_str(&format!("<groupName>{}</groupName>", xml_escape(&self.group)));
+        ret.push_str(&format!("<mode>{}</mode>", self.mode));
+        ret.push_str(&format!("<limit>{}</limit>", self.limit));
+        ret.push_str(&format!("<maxRelativeExpiry>{}</maxRelativeExpiry>",
+                 self.max_relative_expiry));
+        ret.push_str("</pool>\n");
+        return ret;
+    }
+}
+
+impl CacheDirective {
+    pub fn to_xml(&self) -> String {
+        let mut ret = "<directive>".to_owned();
+        ret.push_str(&format!("<id>{}</id>", self.id));
+        ret.push_str(&format!("<path>{}</path>", xml_escape(&self.path)));
+        ret.push_str(&format!("<replication>{}</replication>", self.replication));
+        ret.push_str(&format!("<pool>{}</pool>", xml_escape(&self.pool)));
+        ret.push_str("<expiration>");
+        ret.push_str(&format!("<millis>{}</millis>", self.expiry_time));
+        ret.push_str("<relative>false</relative>");
+        ret.push_str("</expiration>\n");
+        ret.push_str("</directive>\n");
+        return ret;
+    }
+}
+
+impl DelegationKey {
+    pub fn to_xml(&self) -> String {
+        let mut ret = "<delegationKey>".to_owned();
+        ret.push_str(&format!("<id>{}</id>", self.id));
+        ret.push_str("<key>");
+        for b in &self.key {
+            ret.push_str(&format!("{:02x}", b));
+        }
+        ret.push_str("</key>");
+        ret.push_str(&format!("<expiry>{}</expiry>", format_iso_date(self.expiry_date)));
+        ret.push_str("</delegationKey>");
+        return ret;
+    }
+}
+
+impl DelegationToken {
+    pub fn to_xml(&self) -> String {
+        let mut ret = "<token>".to_owned();
+        ret.push_str(&format!("<owner>{}</owner>", xml_escape(&self.owner)));
+        ret.push_str(&format!("<renewer>{}</renewer>", xml_escape(&self.renewer)));
+        ret.push_str(&format!("<realUser>{}</realUser>", xml_escape(&self.real_user)));
+        ret.push_str(&format!("<issueDate>{}</issueDate>", format_iso_date(self.issue_date)));
+        ret.push_str(&format!("<maxDate>{}</maxDate>", format_iso_date(self.max_date)));
+        ret.push_str(&format!("<sequenceNumber>{}</sequenceNumber>", self.sequence_number));
+        ret.push_str(&format!("<masterKeyId>{}</masterKeyId>", self.master_key_id));
+        ret.push_str(&format!("<expiryDate>{}</expiryDate>",
+                 format_iso_date(self.expiry_date)));
+        ret.push_str("</token>");
+        return ret;
+    }
+}